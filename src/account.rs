@@ -0,0 +1,66 @@
+use crate::i18n::tr;
+use crate::models::Customer;
+use serde::Serialize;
+use std::fmt;
+
+/// The logged in account and its customer numbers
+#[derive(Debug, Serialize)]
+pub struct Account {
+    pub username: String,
+    pub customers: Vec<Customer>,
+}
+
+impl Account {
+    /// Name of the account holder, taken from the first customer that has one
+    pub fn name(&self) -> Option<&str> {
+        self.customers
+            .iter()
+            .find_map(|customer| customer.name.as_deref())
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => writeln!(f, "{} ({})", self.username, name)?,
+            None => writeln!(f, "{}", self.username)?,
+        }
+        if self.customers.is_empty() {
+            return writeln!(f, "{}", tr!("no-customers"));
+        }
+        for customer in &self.customers {
+            writeln!(
+                f,
+                "  {:<16} {}",
+                customer.customer_number,
+                customer.address().unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_display() {
+        let account = Account {
+            username: "02-2891001-00".to_string(),
+            customers: vec![Customer {
+                customer_number: "02-2891001-01".to_string(),
+                name: Some("Meikäläinen Matti".to_string()),
+                street_address: Some("Kotikatu 1".to_string()),
+                postal_code: Some("33100".to_string()),
+                city: Some("Tampere".to_string()),
+                unknown: Default::default(),
+            }],
+        };
+
+        assert_eq!(
+            account.to_string(),
+            "02-2891001-00 (Meikäläinen Matti)\n  02-2891001-01    Kotikatu 1, 33100 Tampere\n"
+        );
+    }
+}
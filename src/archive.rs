@@ -0,0 +1,98 @@
+/// Gzipped tar archive of in-memory files, enough for sharing a few text
+/// files. The data is stored without compression, which every gzip reader
+/// accepts.
+pub fn tar_gz(files: &[(String, Vec<u8>)], mtime: u32) -> Vec<u8> {
+    gzip(&tar(files, mtime), mtime)
+}
+
+/// ustar archive of regular files
+fn tar(files: &[(String, Vec<u8>)], mtime: u32) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; 512];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, &name.as_bytes()[..name.len().min(100)]);
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", data.len()).as_bytes());
+        field(136, format!("{:011o}\0", mtime).as_bytes());
+        field(148, b"        ");
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+    // End of archive
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+/// gzip member holding `data` in stored deflate blocks
+fn gzip(data: &[u8], mtime: u32) -> Vec<u8> {
+    let mut output = vec![0x1f, 0x8b, 8, 0];
+    output.extend_from_slice(&mtime.to_le_bytes());
+    output.extend_from_slice(&[0, 255]);
+
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 255, 255]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        output.push(u8::from(last));
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&(!len).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+
+    output.extend_from_slice(&crc32(data).to_le_bytes());
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output
+}
+
+/// CRC-32 of gzip (IEEE 802.3)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_gz() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let files = vec![("bundle/versions.txt".to_string(), b"pjhoy 0.1.0\n".to_vec())];
+        let archive = tar(&files, 0);
+        assert_eq!(archive.len(), 512 * 4);
+        assert_eq!(&archive[..19], b"bundle/versions.txt");
+        assert_eq!(&archive[124..136], b"00000000014\0");
+        assert_eq!(&archive[512..524], b"pjhoy 0.1.0\n");
+
+        let gzipped = gzip(&archive, 0);
+        assert_eq!(&gzipped[..3], &[0x1f, 0x8b, 8]);
+        assert_eq!(gzipped.len(), 10 + 5 + archive.len() + 8);
+        assert_eq!(&gzipped[15..15 + archive.len()], archive.as_slice());
+    }
+}
@@ -0,0 +1,117 @@
+#![allow(dead_code)] // Offered to embedding applications, the CLI is async
+
+use crate::client;
+use crate::config::Credentials;
+use crate::error::{PjhoyError, Result};
+use crate::models::{Customer, TrashService};
+use std::path::PathBuf;
+use tokio::runtime::Runtime;
+
+/// Blocking counterpart of [`client::PjhoyClient`] for scripts and GUI apps
+/// without async code. Every call runs the async client to completion on a
+/// private single-threaded runtime, like `reqwest::blocking`, so it must not
+/// be used from within an async runtime.
+#[derive(Debug)]
+pub struct PjhoyClient {
+    inner: client::PjhoyClient,
+    runtime: Runtime,
+}
+
+impl PjhoyClient {
+    /// Client set up from the provider and `[http]` section of the config,
+    /// keeping its state in the files of `data_dir`
+    pub fn new(config: Credentials, data_dir: PathBuf) -> Result<Self> {
+        Self::from_async(client::PjhoyClient::new(config, data_dir)?)
+    }
+
+    /// Wrap a client built with [`client::PjhoyClient::builder`]
+    pub fn from_async(inner: client::PjhoyClient) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| PjhoyError::io("Failed to start the async runtime", e))?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// The wrapped async client
+    pub fn inner(&self) -> &client::PjhoyClient {
+        &self.inner
+    }
+
+    pub fn login(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.login())
+    }
+
+    pub fn logout(&self) -> Result<()> {
+        self.runtime.block_on(self.inner.logout())
+    }
+
+    pub fn import_cookies(&self, text: &str) -> Result<usize> {
+        self.runtime.block_on(self.inner.import_cookies(text))
+    }
+
+    pub fn import_session(&self, session: &str) -> Result<usize> {
+        self.runtime.block_on(self.inner.import_session(session))
+    }
+
+    pub fn session_valid(&self) -> Result<bool> {
+        self.runtime.block_on(self.inner.session_valid())
+    }
+
+    pub fn fetch_trash_services(&self) -> Result<serde_json::Value> {
+        self.runtime.block_on(self.inner.fetch_trash_services())
+    }
+
+    pub fn fetch_services_for(&self, customer_numbers: &[String]) -> Result<serde_json::Value> {
+        self.runtime
+            .block_on(self.inner.fetch_services_for(customer_numbers))
+    }
+
+    pub fn fetch_invoices(&self) -> Result<serde_json::Value> {
+        self.runtime.block_on(self.inner.fetch_invoices())
+    }
+
+    pub fn fetch_customers(&self) -> Result<Vec<Customer>> {
+        self.runtime.block_on(self.inner.fetch_customers())
+    }
+
+    pub fn discover_customer_numbers(&self, max: u32) -> Result<Vec<String>> {
+        self.runtime
+            .block_on(self.inner.discover_customer_numbers(max))
+    }
+
+    pub fn parse_services(&self, raw: &serde_json::Value) -> Result<Vec<TrashService>> {
+        self.inner.parse_services(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::RetryPolicy;
+    use crate::storage::MemoryStorage;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_blocking_request_fails_without_server() -> anyhow::Result<()> {
+        let config: Credentials = serde_json::from_value(serde_json::json!({
+            "username": "02-2891001-00",
+            "password": "secret",
+            "customer_numbers": ["01"],
+        }))?;
+        let inner = client::PjhoyClient::builder(config, Arc::new(MemoryStorage::default()))
+            .base_url("http://127.0.0.1:9/jate")
+            .retry(RetryPolicy {
+                attempts: 1,
+                ..RetryPolicy::default()
+            })
+            .build()?;
+        let client = PjhoyClient::from_async(inner)?;
+
+        assert!(matches!(
+            client.fetch_customers(),
+            Err(PjhoyError::Http { .. })
+        ));
+        Ok(())
+    }
+}
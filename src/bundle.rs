@@ -0,0 +1,233 @@
+use crate::config::Credentials;
+use crate::{archive, client, config, doctor};
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const MASK: &str = "***";
+
+/// Keys of JSON objects holding names and addresses of the customer
+const PERSONAL_KEYS: &[&str] = &["name", "streetAddress", "postalCode", "city", "address"];
+
+/// Saved responses of the state directory, masked. JSON ones go first so
+/// that the names and addresses found in them are masked in the rest.
+const RESPONSE_FILES: &[&str] = &[
+    crate::SERVICES_FULL_FILE,
+    crate::SERVICES_FILE,
+    client::FAILED_RESPONSE_FILE,
+];
+
+/// Logs of the state directory, of which only the end is included
+const LOG_FILES: &[&str] = &["launchd-fetch.log", "launchd-notify.log"];
+const LOG_LINES: usize = 200;
+
+/// Hides personal data: customer numbers everywhere and the values of
+/// personal fields, which are also hidden wherever else they appear
+#[derive(Debug, Default)]
+struct Masker {
+    personal: BTreeSet<String>,
+    /// Shown as `~`, paths would otherwise tell the user name
+    home: Option<String>,
+}
+
+impl Masker {
+    /// Personal settings of the config such as the username and addresses
+    fn from_config(config: Option<&Credentials>) -> Self {
+        let mut masker = Self {
+            home: directories::BaseDirs::new()
+                .map(|dirs| dirs.home_dir().display().to_string())
+                .filter(|home| home.len() > 1),
+            ..Self::default()
+        };
+        let Some(config) = config else {
+            return masker;
+        };
+        let mut values = vec![Some(config.username.clone())];
+        values.push(config.ntfy.as_ref().map(|ntfy| ntfy.topic.clone()));
+        values.push(config.webhook.as_ref().map(|webhook| webhook.url.clone()));
+        values.push(config.slack.as_ref().map(|slack| slack.webhook_url.clone()));
+        values.push(config.mqtt.as_ref().and_then(|mqtt| mqtt.username.clone()));
+        if let Some(email) = &config.email {
+            values.push(email.username.clone());
+            values.push(Some(email.from.clone()));
+            values.extend(email.to.iter().cloned().map(Some));
+        }
+        masker.personal.extend(
+            values
+                .into_iter()
+                .flatten()
+                .filter(|value| !value.trim().is_empty()),
+        );
+        masker
+    }
+
+    /// Mask the personal fields and customer numbers of a JSON document
+    fn mask_json(&mut self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    match value {
+                        Value::String(text) if PERSONAL_KEYS.contains(&key.as_str()) => {
+                            if !text.trim().is_empty() {
+                                self.personal.insert(text.clone());
+                            }
+                            *text = MASK.to_string();
+                        }
+                        _ => self.mask_json(value),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.mask_json(item)),
+            Value::String(text) => *text = mask_customer_numbers(text),
+            _ => {}
+        }
+    }
+
+    fn mask_text(&self, text: &str) -> String {
+        let mut text = mask_customer_numbers(text);
+        if let Some(home) = &self.home {
+            text = text.replace(home.as_str(), "~");
+        }
+        // Longest first, so that a value containing another is hidden whole
+        let mut personal: Vec<&String> = self.personal.iter().collect();
+        personal.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        // Replacing a few characters would garble the report
+        for value in personal
+            .into_iter()
+            .filter(|value| value.chars().count() >= 3)
+        {
+            text = text.replace(value.as_str(), MASK);
+        }
+        text
+    }
+}
+
+/// Hide all but the last two digits of customer numbers such as
+/// 02-2891001-01, which identify the account, keeping the suffix that
+/// tells the properties apart
+fn mask_customer_numbers(text: &str) -> String {
+    const SHAPE: &[u8] = b"00-0000000-00";
+    let mut bytes = text.as_bytes().to_vec();
+    let mut i = 0;
+    while i + SHAPE.len() <= bytes.len() {
+        let found = SHAPE.iter().zip(&bytes[i..]).all(|(&shape, &byte)| {
+            if shape == b'0' {
+                byte.is_ascii_digit()
+            } else {
+                byte == shape
+            }
+        });
+        if found {
+            bytes[i..i + 10].copy_from_slice(b"xx-xxxxxxx");
+            i += SHAPE.len();
+        } else {
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).expect("ASCII replaced with ASCII")
+}
+
+fn versions() -> String {
+    format!(
+        "pjhoy {}\nos {} {}\nfeatures {}\ncreated {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if cfg!(feature = "blocking") {
+            "blocking"
+        } else {
+            "-"
+        },
+        chrono::Local::now().to_rfc3339(),
+    )
+}
+
+/// Gzipped tar archive of the versions, the redacted config, the doctor
+/// checks, the saved responses and the end of the logs, with customer
+/// numbers, names and addresses masked
+pub async fn create(
+    config_dir: &Path,
+    state_dir: &Path,
+    output_path: &Path,
+    config: Option<&Credentials>,
+) -> Result<Vec<u8>> {
+    let mut masker = Masker::from_config(config);
+    let mut files = vec![("versions.txt".to_string(), versions())];
+
+    let mut responses = Vec::new();
+    for name in RESPONSE_FILES {
+        let Ok(content) = std::fs::read_to_string(state_dir.join(name)) else {
+            continue;
+        };
+        match serde_json::from_str::<Value>(&content) {
+            Ok(mut json) => {
+                masker.mask_json(&mut json);
+                responses.push((name.to_string(), serde_json::to_string_pretty(&json)?));
+            }
+            Err(_) => responses.push((name.to_string(), content)),
+        }
+    }
+
+    let config_text = match config::show_config(config_dir) {
+        Ok(text) => text,
+        Err(e) => format!("# Config could not be read: {:#}\n", e),
+    };
+    files.push(("config.toml".to_string(), config_text));
+
+    let checks = doctor::diagnose(config_dir, state_dir, output_path).await;
+    let checks: Vec<String> = checks.iter().map(ToString::to_string).collect();
+    files.push(("doctor.txt".to_string(), checks.join("\n") + "\n"));
+
+    files.extend(responses);
+    for name in LOG_FILES {
+        if let Ok(content) = std::fs::read_to_string(state_dir.join(name)) {
+            let lines: Vec<&str> = content.lines().collect();
+            let tail = lines[lines.len().saturating_sub(LOG_LINES)..].join("\n");
+            files.push((name.to_string(), tail + "\n"));
+        }
+    }
+
+    let files: Vec<(String, Vec<u8>)> = files
+        .into_iter()
+        .map(|(name, content)| {
+            (
+                format!("pjhoy-debug/{}", name),
+                masker.mask_text(&content).into_bytes(),
+            )
+        })
+        .collect();
+    let mtime = chrono::Utc::now().timestamp().try_into().unwrap_or(0);
+    Ok(archive::tar_gz(&files, mtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masking() {
+        let mut masker = Masker::default();
+        let mut customers = serde_json::json!([{
+            "customerNumber": "02-2891001-01",
+            "name": "Matti Meikäläinen",
+            "streetAddress": "Kotikatu 1",
+            "city": null,
+        }]);
+        masker.mask_json(&mut customers);
+        assert_eq!(
+            customers,
+            serde_json::json!([{
+                "customerNumber": "xx-xxxxxxx-01",
+                "name": "***",
+                "streetAddress": "***",
+                "city": null,
+            }])
+        );
+
+        assert_eq!(
+            masker.mask_text("<td>Kotikatu 1</td><td>02-2891001-00</td> 2024-01-01"),
+            "<td>***</td><td>xx-xxxxxxx-00</td> 2024-01-01"
+        );
+    }
+}
@@ -1,25 +1,171 @@
-use crate::models::TrashService;
+use crate::client::provider_from_config;
+use crate::config::{CalendarConfig, Credentials, HolidayPolicy};
+use crate::holidays::{finnish_holiday, next_working_day};
+use crate::i18n::Language;
+use crate::ical;
+use crate::models::{ProductGroup, TrashService, VatRates};
 use anyhow::{Context, Result};
-use chrono::{Duration, NaiveDate, Utc};
-use ics::properties::{Description, DtEnd, DtStart, Summary};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use ics::properties::{
+    Categories, Description, DtEnd, DtStart, Location, Method, Status, Summary, Transp, URL,
+};
 use ics::components::Property;
 use ics::{escape_text, parameters, Event, ICalendar};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
 
-/// Product groups mapping with Finnish names and icons
-const PRODUCT_GROUPS: &[(&str, &str, &str)] = &[
-    ("SEK", "Sekajäte", "🗑️"),
-    ("BIO", "Biojäte", "🍃"),
-    ("KK", "Kartonki", "📦"),
-    ("MU", "Muovi", "🔄"),
-    ("PP", "Paperi", "📄"),
-    ("ME", "Metalli", "🔧"),
-    ("LA", "Lasi", "🥃"),
-    ("VU", "Vaarallinen jäte", "☣️"),
-];
-
-pub fn generate_calendar<'a>(services: &'a [TrashService], refresh_interval: Option<&'a str>) -> Result<ICalendar<'a>> {
+/// Settings shaping the content of each calendar event
+#[derive(Debug, Default)]
+pub struct EventOptions {
+    pub vat_rates: VatRates,
+    pub lang: Language,
+    pub calendar: CalendarConfig,
+    /// Link from each event back to the extranet
+    pub url: Option<String>,
+    /// Fixed DTSTAMP for reproducible output, the current time otherwise
+    pub dtstamp: Option<DateTime<Utc>>,
+    /// Last day to include events for
+    pub until: Option<NaiveDate>,
+    /// Number of pickups to generate per service
+    pub occurrences: Option<u32>,
+}
+
+impl EventOptions {
+    pub fn from_config(config: &Credentials) -> Self {
+        Self {
+            vat_rates: config.vat_rates(),
+            lang: config.lang.unwrap_or_default(),
+            calendar: config.calendar.clone().unwrap_or_default(),
+            url: config
+                .calendar
+                .as_ref()
+                .and_then(|calendar| calendar.url.clone())
+                .or_else(|| {
+                    provider_from_config(config)
+                        .ok()
+                        .map(|provider| provider.portal_url())
+                }),
+            dtstamp: None,
+            until: None,
+            occurrences: config
+                .calendar
+                .as_ref()
+                .and_then(|calendar| calendar.occurrences),
+        }
+    }
+
+    /// Icon of a product group, empty when emojis are disabled
+    pub fn icon(&self, group: &ProductGroup) -> String {
+        if !self.calendar.emoji.unwrap_or(true) {
+            return String::new();
+        }
+        let custom = self
+            .calendar
+            .icons
+            .as_ref()
+            .and_then(|icons| icons.get(group.code()));
+        match custom {
+            Some(icon) => icon.clone(),
+            None => group.icon().unwrap_or("📦").to_string(),
+        }
+    }
+}
+
+/// Build time given in the `SOURCE_DATE_EPOCH` environment variable
+pub fn source_date_epoch() -> Result<Option<DateTime<Utc>>> {
+    let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    let seconds: i64 = epoch
+        .trim()
+        .parse()
+        .context("SOURCE_DATE_EPOCH is not a number of seconds")?;
+    DateTime::from_timestamp(seconds, 0)
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("SOURCE_DATE_EPOCH is out of range"))
+}
+
+/// Time span of the generated events
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct EventWindow {
+    /// Only include events of the next N days
+    #[arg(long, value_name = "N", conflicts_with = "until")]
+    pub days: Option<u32>,
+
+    /// Only include events until this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<NaiveDate>,
+
+    /// Number of future pickups to generate per service
+    #[arg(long, value_name = "K")]
+    pub occurrences: Option<u32>,
+}
+
+impl EventWindow {
+    /// Last day of the window starting at `today`, if limited
+    pub fn last_day(&self, today: NaiveDate) -> Option<NaiveDate> {
+        match (self.days, self.until) {
+            (Some(days), _) => Some(today + Duration::days(i64::from(days) - 1)),
+            (None, until) => until,
+        }
+    }
+}
+
+/// Product groups to include in or leave out of the calendar
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct GroupFilter {
+    /// Only include these product groups (e.g. BIO,SEK)
+    #[arg(long, value_delimiter = ',', value_name = "GROUPS")]
+    pub only: Vec<ProductGroup>,
+
+    /// Leave out these product groups (e.g. PP)
+    #[arg(long, value_delimiter = ',', value_name = "GROUPS")]
+    pub exclude: Vec<ProductGroup>,
+}
+
+impl GroupFilter {
+    pub fn matches(&self, service: &TrashService) -> bool {
+        let group = service
+            .tariff
+            .as_ref()
+            .and_then(|tariff| tariff.product_group.as_ref());
+        let listed = |groups: &[ProductGroup]| group.is_some_and(|g| groups.contains(g));
+
+        (self.only.is_empty() || listed(&self.only)) && !listed(&self.exclude)
+    }
+
+    /// Services passing the filter
+    pub fn apply(&self, services: &[TrashService]) -> Vec<TrashService> {
+        services
+            .iter()
+            .filter(|service| self.matches(service))
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn generate_calendar<'a>(
+    services: &[TrashService],
+    refresh_interval: Option<&'a str>,
+    options: &EventOptions,
+) -> Result<ICalendar<'a>> {
     let mut calendar = ICalendar::new("2.0", "-//pjhoy//trash calendar//EN");
 
+    let name = calendar_name(options);
+    if options.calendar.method_publish.unwrap_or(false) {
+        calendar.push(Method::new("PUBLISH"));
+    }
+    calendar.push(Property::new("X-WR-CALNAME", escape_text(name)));
+    calendar.push(Property::new(
+        "X-WR-TIMEZONE",
+        options
+            .calendar
+            .timezone
+            .clone()
+            .unwrap_or_else(|| "Europe/Helsinki".to_string()),
+    ));
+
     if let Some(interval) = refresh_interval {
         let mut refresh_prop = Property::new("REFRESH-INTERVAL", interval);
         refresh_prop.append(parameters!("VALUE" => "DURATION"));
@@ -27,82 +173,552 @@ pub fn generate_calendar<'a>(services: &'a [TrashService], refresh_interval: Opt
         calendar.push(Property::new("X-PUBLISHED-TTL", interval));
     }
 
-    for service in services {
-        if let Ok(event) = generate_calendar_event(service) {
-            calendar.add_event(event);
+    let pickups = pickups_in_window(services, options);
+
+    if options.calendar.merge_same_day.unwrap_or(false) {
+        // Group services by pickup date, keeping the original order within a day
+        let mut days: BTreeMap<NaiveDate, Vec<&Pickup>> = BTreeMap::new();
+        for pickup in &pickups {
+            if let Some(next_date) = pickup.service.next_date {
+                days.entry(next_date).or_default().push(pickup);
+            }
+        }
+        for day_pickups in days.values() {
+            let event = match day_pickups.as_slice() {
+                [pickup] => generate_calendar_event(&pickup.service, pickup.holiday, options),
+                pickups => generate_merged_event(pickups, options),
+            };
+            if let Ok(event) = event {
+                calendar.add_event(event);
+            }
+        }
+    } else {
+        for pickup in &pickups {
+            if let Ok(event) = generate_calendar_event(&pickup.service, pickup.holiday, options) {
+                calendar.add_event(event);
+            }
         }
     }
 
     Ok(calendar)
 }
 
-fn generate_calendar_event(service: &TrashService) -> Result<Event<'_>> {
-    let Some(next_date) = &service.ASTNextDate else {
-        return Err(anyhow::anyhow!("Service has no next pickup date"));
+/// Name shown by calendar clients and feed readers
+pub fn calendar_name(options: &EventOptions) -> String {
+    options
+        .calendar
+        .name
+        .clone()
+        .unwrap_or_else(|| match options.lang {
+            Language::Fi => "Jätekalenteri".to_string(),
+            Language::En => "Waste collection".to_string(),
+        })
+}
+
+/// Synthesize future pickups and leave out those past the requested window
+fn pickups_in_window(services: &[TrashService], options: &EventOptions) -> Vec<Pickup> {
+    let occurrences = options.occurrences.unwrap_or(1).max(1);
+    services
+        .iter()
+        .flat_map(|service| expand_occurrences(service, occurrences, options))
+        .filter(|pickup| match options.until {
+            Some(until) => pickup_date(&pickup.service).is_ok_and(|date| date <= until),
+            None => true,
+        })
+        .collect()
+}
+
+/// A pickup as a feed entry, worded like its calendar event
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedItem {
+    /// The UID of the calendar event
+    pub id: String,
+    pub date: NaiveDate,
+    pub title: String,
+    pub content: String,
+    pub category: Option<String>,
+}
+
+/// Pickups from `today` on in the window of `options`, by date
+pub fn feed_items(
+    services: &[TrashService],
+    options: &EventOptions,
+    today: NaiveDate,
+) -> Vec<FeedItem> {
+    let mut items: Vec<FeedItem> = pickups_in_window(services, options)
+        .iter()
+        .filter_map(|pickup| {
+            let service = &pickup.service;
+            let date = pickup_date(service).ok().filter(|date| *date >= today)?;
+            let price = event_price(service, date, options);
+            Some(FeedItem {
+                id: event_uid(service),
+                date,
+                title: event_summary(service, price, options),
+                content: event_description(service, price, pickup.holiday, options),
+                category: event_category(service, options),
+            })
+        })
+        .collect();
+    items.sort_by_key(|item| item.date);
+    items
+}
+
+/// A pickup to put in the calendar
+struct Pickup {
+    service: TrashService,
+    /// Public holiday an estimated pickup date falls on
+    holiday: Option<&'static str>,
+}
+
+/// The service itself followed by `count - 1` synthesized pickups,
+/// `interval` weeks apart. Synthesized pickups on public holidays are
+/// flagged or moved to the next working day.
+fn expand_occurrences(service: &TrashService, count: u32, options: &EventOptions) -> Vec<Pickup> {
+    let (Ok(first), Some(weeks)) = (pickup_date(service), service.interval_weeks()) else {
+        return vec![Pickup {
+            service: service.clone(),
+            holiday: None,
+        }];
     };
 
-    let dstamp =
-        NaiveDate::parse_from_str(next_date, "%Y-%m-%d").context("Failed to parse date")?;
-    let service_type_id = service.ASTTyyppi.unwrap_or(0);
+    (0..i64::from(count))
+        .map(|n| {
+            let mut date = first + Duration::weeks(i64::from(weeks) * n);
+            // The extranet's own date already accounts for holidays
+            let mut holiday = (n > 0).then(|| finnish_holiday(date)).flatten();
+            if holiday.is_some() && options.calendar.holidays == Some(HolidayPolicy::Shift) {
+                date = next_working_day(date);
+                holiday = None;
+            }
 
-    let uid = format!(
-        "pjhoy_{}_{}_{}_{}",
-        service.ASTAsnro, service_type_id, service.ASTPos, next_date
-    );
+            let mut occurrence = service.clone();
+            occurrence.next_date = Some(date);
+            Pickup {
+                service: occurrence,
+                holiday,
+            }
+        })
+        .collect()
+}
+
+/// Date of an event's DTSTART
+fn event_start(event: &ical::Component) -> Option<NaiveDate> {
+    let value = &event.property("DTSTART")?.value;
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Merge a newly generated calendar into the previously written one. Past
+/// events of the old calendar are kept, everything else comes from the new
+/// one, so changed events are replaced by UID and new ones are added.
+pub fn merge_calendars(old: &str, new: &str, today: NaiveDate) -> Result<String> {
+    let old = ical::parse(old).context("Failed to parse the existing calendar")?;
+    let mut merged = ical::parse(new)?;
+
+    let new_uids: Vec<&str> = merged
+        .components_named("VEVENT")
+        .filter_map(|event| Some(event.property("UID")?.value.as_str()))
+        .collect();
+    let past_events: Vec<ical::Component> = old
+        .components_named("VEVENT")
+        .filter(|event| {
+            event
+                .property("UID")
+                .is_some_and(|uid| !new_uids.contains(&uid.value.as_str()))
+                && event_start(event).is_some_and(|date| date < today)
+        })
+        .cloned()
+        .collect();
+
+    merged.components.splice(0..0, past_events);
+    Ok(merged.to_string())
+}
+
+/// Event of a calendar read back from an ICS file
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub date: Option<NaiveDate>,
+    pub summary: Option<String>,
+    /// Product groups recognized from the categories
+    pub groups: Vec<ProductGroup>,
+    pub categories: Vec<String>,
+    /// Customer number encoded in pjhoy's UIDs
+    pub customer_number: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+/// Read the events of an ICS file, such as one written by pjhoy
+pub fn parse_events(text: &str) -> Result<Vec<CalendarEvent>> {
+    let calendar = ical::parse(text)?;
+    let text_value = |event: &ical::Component, name: &str| {
+        event
+            .property(name)
+            .map(|property| ical::unescape_text(&property.value))
+    };
+
+    Ok(calendar
+        .components_named("VEVENT")
+        .map(|event| {
+            let uid = text_value(event, "UID").unwrap_or_default();
+            let categories: Vec<String> = event
+                .properties
+                .iter()
+                .filter(|property| property.name == "CATEGORIES")
+                .flat_map(|property| split_list(&property.value))
+                .collect();
+            // pjhoy_{customer number}_{type}_{position}_{date}
+            let customer_number = uid
+                .strip_prefix("pjhoy_")
+                .and_then(|rest| rest.split('_').next())
+                .map(str::to_string);
 
-    let mut event = Event::new(uid, Utc::now().format("%Y%m%dT%H%M%SZ").to_string());
+            CalendarEvent {
+                date: event_start(event),
+                summary: text_value(event, "SUMMARY"),
+                groups: categories
+                    .iter()
+                    .filter_map(|category| ProductGroup::from_name(category))
+                    .collect(),
+                categories,
+                customer_number,
+                description: text_value(event, "DESCRIPTION"),
+                location: text_value(event, "LOCATION"),
+                uid,
+            }
+        })
+        .collect())
+}
 
-    let mut dtstart = DtStart::new(dstamp.format("%Y%m%d").to_string());
-    let mut dtend = DtEnd::new((dstamp + Duration::days(1)).format("%Y%m%d").to_string());
+/// Split a comma separated list value, unescaping the items
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            ',' if !escaped => {
+                items.push(ical::unescape_text(&value[start..i]));
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    items.push(ical::unescape_text(&value[start..]));
+    items
+}
+
+/// Pickup date of a service
+fn pickup_date(service: &TrashService) -> Result<NaiveDate> {
+    service.next_date.context("Service has no next pickup date")
+}
+
+/// All-day event with the properties shared by single and merged pickups
+fn new_event<'a>(uid: String, date: NaiveDate, options: &EventOptions) -> Event<'a> {
+    let dtstamp = options.dtstamp.unwrap_or_else(Utc::now);
+    let mut event = Event::new(uid, dtstamp.format("%Y%m%dT%H%M%SZ").to_string());
+
+    let mut dtstart = DtStart::new(date.format("%Y%m%d").to_string());
+    let mut dtend = DtEnd::new((date + Duration::days(1)).format("%Y%m%d").to_string());
     dtstart.append(parameters!("VALUE" => "DATE"));
     dtend.append(parameters!("VALUE" => "DATE"));
     event.push(dtstart);
     event.push(dtend);
+    event.push(Status::confirmed());
+    // Pickups should not show up as busy time unless asked to
+    if options.calendar.transparent.unwrap_or(true) {
+        event.push(Transp::transparent());
+    } else {
+        event.push(Transp::opaque());
+    }
 
-    let product_group_title = get_product_group_title(service);
+    if let Some(url) = &options.url {
+        let mut url = URL::new(url.clone());
+        url.append(parameters!("VALUE" => "URI"));
+        event.push(url);
+    }
 
-    if let Some(title) = product_group_title {
-        event.push(Summary::new(escape_text(title)));
-    } else {
-        event.push(Summary::new(escape_text(format!(
-            "Jäte: {}",
-            &service.ASTNimi
-        ))));
+    event
+}
+
+fn event_uid(service: &TrashService) -> String {
+    format!(
+        "pjhoy_{}_{}_{}_{}",
+        service.customer_number,
+        service.service_type.unwrap_or(0),
+        service.position,
+        service
+            .next_date
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    )
+}
+
+fn event_price(service: &TrashService, date: NaiveDate, options: &EventOptions) -> Option<Decimal> {
+    service
+        .price
+        .map(|cost| options.vat_rates.gross_on(cost, date))
+}
+
+fn event_summary(service: &TrashService, price: Option<Decimal>, options: &EventOptions) -> String {
+    let summary = match &options.calendar.summary {
+        Some(template) => render_template(template, &template_fields(service, price, options)),
+        None => match get_product_group_title(service, options) {
+            Some(title) => title,
+            None => format!("Jäte: {}", &service.name),
+        },
+    };
+    summary.trim().to_string()
+}
+
+fn event_description(
+    service: &TrashService,
+    price: Option<Decimal>,
+    holiday: Option<&str>,
+    options: &EventOptions,
+) -> String {
+    let description = match &options.calendar.description {
+        Some(template) => render_template(template, &template_fields(service, price, options)),
+        None => {
+            // Build description with optional cost information
+            let mut description_lines = Vec::new();
+            description_lines.push(service.name.clone());
+
+            if let Some(price) = price {
+                description_lines.push(format!("Hinta: {:.2} € (sis. ALV)", price));
+            }
+
+            description_lines.push(format!("{} viikon välein", service.interval));
+            description_lines.join("\n")
+        }
+    };
+
+    match holiday {
+        Some(holiday) => format!("{}\n{}: saattaa siirtyä pyhän vuoksi", description, holiday),
+        None => description,
     }
+}
+
+pub fn event_category(service: &TrashService, options: &EventOptions) -> Option<String> {
+    let group = service
+        .tariff
+        .as_ref()
+        .and_then(|tariff| tariff.product_group.as_ref())?;
+    Some(group.name(options.lang).unwrap_or(group.code()).to_string())
+}
 
-    // Build description with optional cost information
-    let mut description_lines = Vec::new();
-    description_lines.push(service.ASTNimi.clone());
+fn generate_calendar_event<'a>(
+    service: &TrashService,
+    holiday: Option<&str>,
+    options: &EventOptions,
+) -> Result<Event<'a>> {
+    let date = pickup_date(service)?;
+    let mut event = new_event(event_uid(service), date, options);
 
-    if let Some(cost) = service.ASTHinta {
-        description_lines.push(format!("Hinta: {:.2} € (sis. ALV)", 1.255 * cost));
+    let price = event_price(service, date, options);
+    event.push(Summary::new(escape_text(event_summary(
+        service, price, options,
+    ))));
+    event.push(Description::new(escape_text(event_description(
+        service, price, holiday, options,
+    ))));
+
+    if let Some(address) = &service.address {
+        event.push(Location::new(escape_text(address.clone())));
+    }
+
+    if let Some(category) = event_category(service, options) {
+        event.push(Categories::new(escape_text(category)));
+    }
+    if let Some(color) = service.color() {
+        event.push(Property::new("COLOR", color));
+    }
+
+    Ok(event)
+}
+
+/// Push `value` unless it is already in `values`
+fn push_unique<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
     }
+}
+
+/// One event covering several services picked up on the same day
+fn generate_merged_event<'a>(pickups: &[&Pickup], options: &EventOptions) -> Result<Event<'a>> {
+    let services: Vec<&TrashService> = pickups.iter().map(|pickup| &pickup.service).collect();
+    let date = pickup_date(services[0])?;
+    let uid = services
+        .iter()
+        .map(|service| event_uid(service))
+        .collect::<Vec<_>>()
+        .join("+");
+    let mut event = new_event(uid, date, options);
+
+    let summary = if options.calendar.summary.is_some() {
+        services
+            .iter()
+            .map(|service| event_summary(service, event_price(service, date, options), options))
+            .collect::<Vec<_>>()
+            .join(" & ")
+    } else {
+        let mut icons = Vec::new();
+        let mut names = Vec::new();
+        for service in &services {
+            match service
+                .tariff
+                .as_ref()
+                .and_then(|tariff| tariff.product_group.as_ref())
+            {
+                Some(group) => {
+                    push_unique(&mut icons, options.icon(group));
+                    push_unique(
+                        &mut names,
+                        group.finnish_name().unwrap_or(group.code()).to_string(),
+                    );
+                }
+                None => push_unique(&mut names, service.name.clone()),
+            }
+        }
+        icons.retain(|icon| !icon.is_empty());
+        format!("{} {}", icons.join("+"), names.join(" & "))
+            .trim()
+            .to_string()
+    };
+    event.push(Summary::new(escape_text(summary)));
 
-    description_lines.push(format!("{} viikon välein", service.ASTVali));
+    let description = pickups
+        .iter()
+        .map(|pickup| {
+            let service = &pickup.service;
+            event_description(
+                service,
+                event_price(service, date, options),
+                pickup.holiday,
+                options,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    event.push(Description::new(escape_text(description)));
 
-    event.push(Description::new(escape_text(description_lines.join("\n"))));
+    let mut addresses = Vec::new();
+    let mut categories = Vec::new();
+    for service in &services {
+        if let Some(address) = &service.address {
+            push_unique(&mut addresses, address.as_str());
+        }
+        if let Some(category) = event_category(service, options) {
+            push_unique(&mut categories, escape_text(category).to_string());
+        }
+    }
+    if !addresses.is_empty() {
+        event.push(Location::new(escape_text(addresses.join("; "))));
+    }
+    if !categories.is_empty() {
+        event.push(Categories::new(categories.join(",")));
+    }
+    if let Some(color) = services.iter().find_map(|service| service.color()) {
+        event.push(Property::new("COLOR", color));
+    }
 
     Ok(event)
 }
 
-fn get_product_group_title(service: &TrashService) -> Option<String> {
+/// Values of the event template placeholders for one service
+fn template_fields(
+    service: &TrashService,
+    price: Option<Decimal>,
+    options: &EventOptions,
+) -> Vec<(&'static str, String)> {
+    let tariff = service.tariff.as_ref();
+    let product_group = tariff.and_then(|tariff| tariff.product_group.as_ref());
+    let code = product_group.map(ProductGroup::code).unwrap_or("");
+    let (group, icon) = match product_group {
+        None => ("", String::new()),
+        Some(group) => (
+            group.name(options.lang).unwrap_or(code),
+            options.icon(group),
+        ),
+    };
+
+    vec![
+        ("icon", icon),
+        ("group", group.to_string()),
+        ("code", code.to_string()),
+        ("name", service.name.clone()),
+        (
+            "container",
+            tariff
+                .and_then(|tariff| tariff.container_size.clone())
+                .unwrap_or_default(),
+        ),
+        (
+            "cost",
+            price
+                .map(|price| format!("{:.2}", price))
+                .unwrap_or_default(),
+        ),
+        ("interval", service.interval.clone()),
+        ("customer", service.customer_number.clone()),
+        ("address", service.address.clone().unwrap_or_default()),
+    ]
+}
+
+/// Replace `{placeholder}`s in a template, leaving unknown ones as they are
+fn render_template(template: &str, fields: &[(&str, String)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find('}').and_then(|end| {
+            let key = &placeholder[1..end];
+            fields
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                output.push_str(value);
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn get_product_group_title(service: &TrashService, options: &EventOptions) -> Option<String> {
     let product_group = service
         .tariff
         .as_ref()
-        .and_then(|tariff| tariff.productgroup.as_ref())?;
+        .and_then(|tariff| tariff.product_group.as_ref())?;
 
-    for (code, finnish_name, icon) in PRODUCT_GROUPS {
-        if code == &product_group {
-            return Some(format!("{} {}", icon, finnish_name));
-        }
-    }
-    Some(format!("📦 {}", product_group))
+    let name = product_group.finnish_name().unwrap_or(product_group.code());
+    Some(format!("{} {}", options.icon(product_group), name))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::{Tariff, TrashService};
+    use crate::models::TrashService;
+    use std::collections::HashMap;
+
+    fn options(vat_rates: VatRates) -> EventOptions {
+        EventOptions {
+            vat_rates,
+            ..Default::default()
+        }
+    }
 
     fn parse_ics_properties(event_str: &str) -> std::collections::HashMap<String, Vec<String>> {
         let mut properties: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
@@ -129,7 +745,7 @@ mod tests {
                 let key = name.split_once(';').unwrap_or((name, "")).0.to_string();
                 properties
                     .entry(key.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(value.to_string());
                 current_key = Some(key);
             } else {
@@ -142,19 +758,13 @@ mod tests {
     #[test]
     fn test_event_creation_with_timestamp() -> Result<()> {
         // Create a sample trash service
-        let service = TrashService {
-            ASTNextDate: Some("2023-12-25".to_string()),
-            ASTNimi: "Test Trash Pickup".to_string(),
-            ASTAsnro: "12345".to_string(),
-            ASTPos: 1,
-            ASTTyyppi: Some(1),
-            ASTHinta: Some(10.50),
-            ASTVali: "6".to_string(),
-            tariff: None,
-        };
+        let service = TrashService::builder("Test Trash Pickup", "2023-12-25")
+            .price(Decimal::new(1050, 2))
+            .interval("6")
+            .build();
 
         // Generate the event
-        let event = generate_calendar_event(&service)?;
+        let event = generate_calendar_event(&service, None, &options(VatRates::flat(25.5)))?;
 
         // Convert event to string
         let event_str = event.to_string();
@@ -200,25 +810,34 @@ mod tests {
     #[test]
     fn test_product_group_titles() -> Result<()> {
         // Test with SEK product group
-        let sek_service = TrashService {
-            ASTNextDate: Some("2023-12-25".to_string()),
-            ASTNimi: "Sekajäte säiliö".to_string(),
-            ASTAsnro: "12345".to_string(),
-            ASTPos: 1,
-            ASTTyyppi: Some(1),
-            ASTHinta: Some(10.50),
-            ASTVali: "6".to_string(),
-            tariff: Some(Tariff {
-                productgroup: Some("SEK".to_string()),
-                name: Some("Sekajäte".to_string()),
-            }),
-        };
+        let sek_service = TrashService::builder("Sekajäte säiliö", "2023-12-25")
+            .price(Decimal::new(1050, 2))
+            .interval("6")
+            .group("SEK")
+            .build();
 
-        let event = generate_calendar_event(&sek_service)?;
+        let event = generate_calendar_event(&sek_service, None, &options(VatRates::flat(25.5)))?;
         let event_str = event.to_string();
         let properties = parse_ics_properties(&event_str);
 
         assert!(event_str.contains("SUMMARY:🗑️ Sekajäte"));
+        assert!(event_str.contains("STATUS:CONFIRMED"));
+        assert!(event_str.contains("TRANSP:TRANSPARENT"));
+        assert_eq!(
+            properties.get("CATEGORIES"),
+            Some(&vec!["Sekajäte".to_string()])
+        );
+        assert!(event_str.contains("COLOR:gray\r\n"));
+
+        let event = generate_calendar_event(
+            &sek_service,
+            None,
+            &EventOptions {
+                lang: Language::En,
+                ..options(VatRates::flat(25.5))
+            },
+        )?;
+        assert!(event.to_string().contains("CATEGORIES:Mixed waste"));
 
         let desc = properties.get("DESCRIPTION").unwrap().first().unwrap();
         assert!(desc.contains("Sekajäte säiliö"));
@@ -227,4 +846,327 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_event_location() -> Result<()> {
+        let service = TrashService::builder("Biojäte", "2024-03-05")
+            .address("Kotikatu 1, 33100 Tampere")
+            .build();
+
+        let options = EventOptions {
+            url: Some("https://extranet.pjhoy.fi/pirkka/".to_string()),
+            ..options(VatRates::finnish())
+        };
+        let event = generate_calendar_event(&service, None, &options)?.to_string();
+        let properties = parse_ics_properties(&event);
+
+        assert_eq!(
+            properties.get("LOCATION"),
+            Some(&vec!["Kotikatu 1\\, 33100 Tampere".to_string()])
+        );
+        assert_eq!(
+            properties.get("URL"),
+            Some(&vec!["https://extranet.pjhoy.fi/pirkka/".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_aware_vat() -> Result<()> {
+        let mut service = TrashService::builder("Biojäte", "2024-08-30")
+            .price(Decimal::TEN)
+            .build();
+
+        let event =
+            generate_calendar_event(&service, None, &options(VatRates::finnish()))?.to_string();
+        assert!(event.contains("Hinta: 12.40 € (sis. ALV)"));
+
+        service.next_date = "2024-09-13".parse().ok();
+        let event =
+            generate_calendar_event(&service, None, &options(VatRates::finnish()))?.to_string();
+        assert!(event.contains("Hinta: 12.55 € (sis. ALV)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_templates() -> Result<()> {
+        let service = TrashService::builder("Biojäte 140 l", "2024-03-05")
+            .price(Decimal::TEN)
+            .group("BIO")
+            .container_size("140 l")
+            .build();
+        let options = EventOptions {
+            calendar: CalendarConfig {
+                summary: Some("{icon} {group} ({container})".to_string()),
+                description: Some("{customer}: {cost} € / {interval} vk {unknown}".to_string()),
+                ..Default::default()
+            },
+            ..options(VatRates::flat(25.5))
+        };
+
+        let event = generate_calendar_event(&service, None, &options)?.to_string();
+        let properties = parse_ics_properties(&event);
+
+        assert_eq!(
+            properties.get("SUMMARY"),
+            Some(&vec!["🍃 Biojäte (140 l)".to_string()])
+        );
+        assert_eq!(
+            properties.get("DESCRIPTION"),
+            Some(&vec!["12345: 12.55 € / 2 vk {unknown}".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_icons() -> Result<()> {
+        let mut service = TrashService::builder("Biojäte 140 l", "2024-03-05")
+            .group("BIO")
+            .build();
+        let mut options = options(VatRates::finnish());
+        options.calendar.icons = Some(HashMap::from([("BIO".to_string(), "B".to_string())]));
+
+        let event = generate_calendar_event(&service, None, &options)?.to_string();
+        assert!(event.contains("SUMMARY:B Biojäte\r\n"));
+
+        options.calendar.emoji = Some(false);
+        let event = generate_calendar_event(&service, None, &options)?.to_string();
+        assert!(event.contains("SUMMARY:Biojäte\r\n"));
+
+        service.tariff.as_mut().unwrap().product_group =
+            Some(ProductGroup::Other("XY".to_string()));
+        let event = generate_calendar_event(&service, None, &options)?.to_string();
+        assert!(event.contains("SUMMARY:XY\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calendar_metadata() -> Result<()> {
+        let calendar = generate_calendar(&[], Some("PT12H"), &options(VatRates::finnish()))?;
+        let calendar = calendar.to_string();
+
+        assert!(calendar.contains("X-WR-CALNAME:Jätekalenteri\r\n"));
+        assert!(calendar.contains("X-WR-TIMEZONE:Europe/Helsinki\r\n"));
+        assert!(calendar.contains("REFRESH-INTERVAL;VALUE=DURATION:PT12H\r\n"));
+        assert!(calendar.contains("X-PUBLISHED-TTL:PT12H\r\n"));
+        assert!(!calendar.contains("METHOD:"));
+
+        let mut options = options(VatRates::finnish());
+        options.calendar.method_publish = Some(true);
+        let calendar = generate_calendar(&[], None, &options)?.to_string();
+        assert!(calendar.contains("METHOD:PUBLISH\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_dtstamp() -> Result<()> {
+        let service = TrashService::builder("Biojäte", "2024-03-05")
+            .price(Decimal::TEN)
+            .build();
+        let services = vec![service];
+        let options = EventOptions {
+            dtstamp: DateTime::from_timestamp(1709251200, 0),
+            ..options(VatRates::finnish())
+        };
+
+        let first = generate_calendar(&services, Some("P1D"), &options)?.to_string();
+        let second = generate_calendar(&services, Some("P1D"), &options)?.to_string();
+
+        assert_eq!(first, second);
+        assert!(first.contains("DTSTAMP:20240301T000000Z\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_same_day() -> Result<()> {
+        let service = |group: &str, position: i32, next_date: &str| {
+            TrashService::builder(&format!("{} astia", group), next_date)
+                .position(position)
+                .group(group)
+                .build()
+        };
+        let services = vec![
+            service("BIO", 1, "2024-03-05"),
+            service("SEK", 2, "2024-03-05"),
+            service("KK", 3, "2024-03-12"),
+        ];
+        let mut options = options(VatRates::finnish());
+        options.calendar.merge_same_day = Some(true);
+
+        let calendar = generate_calendar(&services, None, &options)?.to_string();
+
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+        assert!(calendar.contains("SUMMARY:🍃+🗑️ Biojäte & Sekajäte\r\n"));
+        assert!(calendar.contains("CATEGORIES:Biojäte,Sekajäte\r\n"));
+        assert!(calendar.contains("COLOR:green\r\n"));
+        assert!(calendar.contains("SUMMARY:📦 Kartonki\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_filter() {
+        let service = |group: Option<&str>| {
+            let builder = TrashService::builder("Astia", "2024-03-05");
+            match group {
+                Some(group) => builder.group(group).build(),
+                None => builder.build(),
+            }
+        };
+        let only = GroupFilter {
+            only: vec!["bio".parse().unwrap(), ProductGroup::Mixed],
+            exclude: Vec::new(),
+        };
+        let exclude = GroupFilter {
+            only: Vec::new(),
+            exclude: vec![ProductGroup::Paper],
+        };
+
+        assert!(only.matches(&service(Some("BIO"))));
+        assert!(!only.matches(&service(Some("PP"))));
+        assert!(!only.matches(&service(None)));
+        assert!(exclude.matches(&service(Some("BIO"))));
+        assert!(!exclude.matches(&service(Some("PP"))));
+        assert!(exclude.matches(&service(None)));
+    }
+
+    #[test]
+    fn test_event_window() -> Result<()> {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let window = EventWindow {
+            days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(window.last_day(today), NaiveDate::from_ymd_opt(2024, 3, 10));
+
+        let service = |next_date: &str| TrashService::builder("Biojäte", next_date).build();
+        let services = vec![service("2024-03-10"), service("2024-03-11")];
+        let options = EventOptions {
+            until: window.last_day(today),
+            ..options(VatRates::finnish())
+        };
+
+        let calendar = generate_calendar(&services, None, &options)?.to_string();
+        assert!(calendar.contains("DTSTART;VALUE=DATE:20240310"));
+        assert!(!calendar.contains("DTSTART;VALUE=DATE:20240311"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occurrences() -> Result<()> {
+        let service = TrashService::builder("Biojäte", "2024-03-05").build();
+        let options = EventOptions {
+            occurrences: Some(3),
+            until: NaiveDate::from_ymd_opt(2024, 3, 31),
+            ..options(VatRates::finnish())
+        };
+
+        let calendar = generate_calendar(&[service], None, &options)?.to_string();
+
+        // The third pickup on 2024-04-02 falls outside the window
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+        assert!(calendar.contains("UID:pjhoy_12345_1_1_2024-03-19\r\n"));
+        assert!(!calendar.contains("DTSTART;VALUE=DATE:20240402"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_holidays() -> Result<()> {
+        let service = TrashService::builder("Biojäte", "2024-11-19")
+            .interval("5")
+            .build();
+        let mut options = EventOptions {
+            occurrences: Some(2),
+            ..options(VatRates::finnish())
+        };
+
+        // Five weeks later is Christmas Eve
+        let calendar = generate_calendar(std::slice::from_ref(&service), None, &options)?;
+        let calendar = calendar.to_string().replace("\r\n ", "");
+        assert!(calendar.contains("Jouluaatto: saattaa siirtyä pyhän vuoksi"));
+
+        options.calendar.holidays = Some(HolidayPolicy::Shift);
+        let calendar = generate_calendar(&[service], None, &options)?.to_string();
+        assert!(calendar.contains("DTSTART;VALUE=DATE:20241227"));
+        assert!(!calendar.contains("Jouluaatto"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_calendars() -> Result<()> {
+        let service = |next_date: &str| TrashService::builder("Biojäte", next_date).build();
+        let options = options(VatRates::finnish());
+        let old = generate_calendar(
+            &[service("2024-02-20"), service("2024-03-05")],
+            None,
+            &options,
+        )?
+        .to_string();
+        let new = generate_calendar(&[service("2024-03-06")], None, &options)?.to_string();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        let merged = merge_calendars(&old, &new, today)?;
+
+        // The past pickup is kept, the rescheduled one replaced
+        assert!(merged.contains("UID:pjhoy_12345_1_1_2024-02-20\r\n"));
+        assert!(!merged.contains("UID:pjhoy_12345_1_1_2024-03-05\r\n"));
+        assert!(merged.contains("UID:pjhoy_12345_1_1_2024-03-06\r\n"));
+        assert_eq!(merged.matches("BEGIN:VCALENDAR").count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generated_calendar_passes_lint() -> Result<()> {
+        let service = TrashService::builder(
+            "Sekajäte; kaksi säiliötä, joista toinen pihan perällä aidan vieressä",
+            "2024-03-05",
+        )
+        .price(Decimal::TEN)
+        .address("Kotikatu 1, 33100 Tampere")
+        .group("SEK")
+        .build();
+        let options = EventOptions {
+            occurrences: Some(3),
+            ..options(VatRates::finnish())
+        };
+
+        let calendar = generate_calendar(&[service], Some("P1D"), &options)?.to_string();
+
+        assert_eq!(ical::lint(calendar.as_bytes()), vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_events() -> Result<()> {
+        let service = TrashService::builder("Biojäte", "2024-03-05")
+            .customer("02-2891001-01")
+            .address("Kotikatu 1, 33100 Tampere")
+            .group("BIO")
+            .build();
+        let calendar = generate_calendar(&[service], None, &options(VatRates::finnish()))?;
+
+        let events = parse_events(&calendar.to_string())?;
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.uid, "pjhoy_02-2891001-01_1_1_2024-03-05");
+        assert_eq!(event.date, NaiveDate::from_ymd_opt(2024, 3, 5));
+        assert_eq!(event.summary.as_deref(), Some("🍃 Biojäte"));
+        assert_eq!(event.groups, vec![ProductGroup::Bio]);
+        assert_eq!(event.customer_number.as_deref(), Some("02-2891001-01"));
+        assert_eq!(event.location.as_deref(), Some("Kotikatu 1, 33100 Tampere"));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,217 @@
+use crate::error::{PjhoyError, Result};
+use reqwest::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::{Method, Response, ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// One recorded request and the response the extranet gave to it. Request
+/// bodies and cookies are left out so that no credentials end up on disk.
+#[derive(Debug, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    /// URL of the response after redirects
+    final_url: String,
+    content_type: Option<String>,
+    location: Option<String>,
+    body: String,
+}
+
+#[derive(Debug)]
+enum Mode {
+    /// Number of the next interaction to save
+    Record(AtomicUsize),
+    /// Recorded interactions not replayed yet, in the order they happened
+    Replay(Mutex<Vec<Option<Interaction>>>),
+}
+
+/// Directory of HTTP exchanges numbered in the order they happened,
+/// `0001.json`, `0002.json` and so on. Recording saves every final response
+/// of a real run; replaying answers the same requests from the files
+/// without touching the network. Requests sent concurrently may arrive in
+/// a different order, so each gets the first unused response recorded for
+/// its method and URL.
+#[derive(Debug)]
+pub struct Cassette {
+    dir: PathBuf,
+    mode: Mode,
+}
+
+fn interaction_path(dir: &Path, number: usize) -> PathBuf {
+    dir.join(format!("{:04}.json", number))
+}
+
+impl Cassette {
+    /// Record into `dir`, which is created if needed and must not already
+    /// hold a recording
+    pub fn record(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| PjhoyError::io(format!("Failed to create {:?}", dir), e))?;
+        if interaction_path(dir, 1).exists() {
+            return Err(PjhoyError::Config(format!(
+                "{:?} already contains a recording",
+                dir
+            )));
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            mode: Mode::Record(AtomicUsize::new(1)),
+        })
+    }
+
+    /// Replay the recording in `dir`
+    pub fn replay(dir: &Path) -> Result<Self> {
+        let mut interactions = Vec::new();
+        for number in 1.. {
+            let path = interaction_path(dir, number);
+            let json = match std::fs::read(&path) {
+                Ok(json) => json,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(PjhoyError::io(format!("Failed to read {:?}", path), e)),
+            };
+            let interaction = serde_json::from_slice(&json)
+                .map_err(|e| PjhoyError::parse(format!("Invalid recording {:?}", path), e))?;
+            interactions.push(Some(interaction));
+        }
+        if interactions.is_empty() {
+            return Err(PjhoyError::Config(format!(
+                "No recording found in {:?}",
+                dir
+            )));
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            mode: Mode::Replay(Mutex::new(interactions)),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// Save a response received for `method` and `url` and hand back an
+    /// equivalent one, as reading the body consumes the original
+    pub async fn save(&self, method: &Method, url: &Url, response: Response) -> Result<Response> {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let content_type = header(CONTENT_TYPE);
+        let location = header(LOCATION);
+        let status = response.status();
+        let final_url = response.url().clone();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| PjhoyError::http(format!("Failed to read response of {}", url), e))?;
+
+        let interaction = Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            status: status.as_u16(),
+            final_url: final_url.to_string(),
+            content_type,
+            location,
+            body,
+        };
+        let Mode::Record(next) = &self.mode else {
+            return Err(PjhoyError::Config("Cassette is not recording".to_string()));
+        };
+        let path = interaction_path(&self.dir, next.fetch_add(1, Ordering::SeqCst));
+        let json = serde_json::to_string_pretty(&interaction)
+            .map_err(|e| PjhoyError::parse("Failed to serialize interaction", e))?;
+        std::fs::write(&path, json)
+            .map_err(|e| PjhoyError::io(format!("Failed to write {:?}", path), e))?;
+
+        to_response(interaction)
+    }
+
+    /// The first unused response recorded for `method` and `url`
+    pub fn load(&self, method: &Method, url: &Url) -> Result<Response> {
+        let Mode::Replay(interactions) = &self.mode else {
+            return Err(PjhoyError::Config("Cassette is not replaying".to_string()));
+        };
+        let mut interactions = interactions.lock().expect("cassette lock poisoned");
+        let interaction = interactions
+            .iter_mut()
+            .find(|interaction| {
+                interaction.as_ref().is_some_and(|interaction| {
+                    interaction.method == method.as_str() && interaction.url == url.as_str()
+                })
+            })
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                PjhoyError::invalid(format!(
+                    "No recorded response left for {} {} in {:?}",
+                    method, url, self.dir
+                ))
+            })?;
+        to_response(interaction)
+    }
+}
+
+fn to_response(interaction: Interaction) -> Result<Response> {
+    let final_url: Url = interaction
+        .final_url
+        .parse()
+        .map_err(|e| PjhoyError::parse("Invalid recorded URL", e))?;
+    let mut builder = http::Response::builder()
+        .status(interaction.status)
+        .url(final_url);
+    if let Some(content_type) = &interaction.content_type {
+        builder = builder.header(CONTENT_TYPE, content_type);
+    }
+    if let Some(location) = &interaction.location {
+        builder = builder.header(LOCATION, location);
+    }
+    let response = builder
+        .body(interaction.body)
+        .map_err(|e| PjhoyError::parse("Invalid recorded response", e))?;
+    Ok(Response::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path().join("cassette");
+        let url: Url =
+            "https://extranet.pjhoy.fi/pirkka/j_acegi_security_check?target=2".parse()?;
+        let home: Url = "https://extranet.pjhoy.fi/pirkka/secure/home.do".parse()?;
+
+        let recorder = Cassette::record(&dir)?;
+        let real = http::Response::builder()
+            .status(200)
+            .url(home.clone())
+            .header(CONTENT_TYPE, "text/html")
+            .body("<html>Tervetuloa</html>")?;
+        let passed_on = recorder
+            .save(&Method::POST, &url, Response::from(real))
+            .await?;
+        assert_eq!(passed_on.url(), &home);
+        assert_eq!(passed_on.text().await?, "<html>Tervetuloa</html>");
+        assert!(Cassette::record(&dir).is_err());
+
+        let player = Cassette::replay(&dir)?;
+        assert!(player.load(&Method::GET, &url).is_err());
+
+        let player = Cassette::replay(&dir)?;
+        let replayed = player.load(&Method::POST, &url)?;
+        assert_eq!(replayed.status(), 200);
+        assert_eq!(replayed.url(), &home);
+        assert_eq!(replayed.headers()[CONTENT_TYPE], "text/html");
+        assert_eq!(replayed.text().await?, "<html>Tervetuloa</html>");
+        assert!(player.load(&Method::POST, &url).is_err());
+        Ok(())
+    }
+}
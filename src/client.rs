@@ -1,110 +1,601 @@
-use crate::config::Credentials;
-use anyhow::{Context, Result};
-use reqwest::cookie::CookieStore;
-use reqwest::{cookie::Jar, Client};
-use std::collections::HashSet;
+use crate::cassette::Cassette;
+use crate::config::{Credentials, HttpConfig, PASSWORD_HINT};
+use crate::cookies::{CookieJar, COOKIES_FILE};
+use crate::error::{PjhoyError, Result};
+use crate::models::{report_unknown_fields, Customer, TrashService};
+use crate::storage::{FileStorage, Storage};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use reqwest::{Certificate, Client, NoProxy, Proxy, RequestBuilder, Response, Url};
+use std::collections::hash_map::RandomState;
 use std::fs;
+use std::hash::BuildHasher;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 
-/// Deduplicates cookies by removing duplicate cookie names (keeping the first occurrence)
-fn deduplicate_cookies(cookie_str: &str) -> String {
-    let mut seen_cookies = HashSet::new();
-    let mut deduped_cookies = Vec::new();
-
-    for cookie_part in cookie_str.split(';') {
-        let cookie_part = cookie_part.trim();
-        if !cookie_part.is_empty() {
-            let cookie_name = cookie_part.split('=').next().unwrap_or("");
-            if !seen_cookies.contains(cookie_name) {
-                seen_cookies.insert(cookie_name.to_string());
-                deduped_cookies.push(cookie_part.to_string());
-            }
+/// Whether the response to a login POST is the login page again. Acegi
+/// redirects a failed login to its failure URL with a 200/302 status, so the
+/// status alone does not tell.
+fn login_rejected(final_url: &Url, body: &str) -> bool {
+    let error_param = final_url
+        .query_pairs()
+        .any(|(key, _)| key == "login_error" || key == "error" || key == "authfailed");
+    error_param || body.contains("name=\"j_password\"")
+}
+
+/// Error of a login answered with `status`. Only a refusal is about the
+/// credentials; an outage still failing after the retries is a network
+/// failure, so that nobody changes their password over it.
+fn check_login_status(status: reqwest::StatusCode) -> Result<()> {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            Err(PjhoyError::AuthFailed(status.to_string()))
+        }
+        status if !status.is_success() => Err(PjhoyError::Http {
+            context: format!("Login failed: {}", status),
+            source: None,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// File in the data directory holding the last response that could not be
+/// used, for bug reports
+pub const FAILED_RESPONSE_FILE: &str = "failed-response.txt";
+
+/// A response that was not the expected JSON
+struct FailedResponse<'a> {
+    url: &'a str,
+    status: reqwest::StatusCode,
+    content_type: &'a str,
+    body: &'a str,
+}
+
+impl FailedResponse<'_> {
+    /// Write the request URL, status, content type and body to
+    /// [`FAILED_RESPONSE_FILE`], returning where it was saved
+    fn save(&self, storage: &dyn Storage) -> Result<String> {
+        let content = format!(
+            "URL: {}\nStatus: {}\nContent-Type: {}\n\n{}",
+            self.url, self.status, self.content_type, self.body
+        );
+        storage.write(FAILED_RESPONSE_FILE, content.as_bytes())?;
+        Ok(storage.location(FAILED_RESPONSE_FILE))
+    }
+}
+
+/// A waste management operator's extranet. Several Finnish operators run the
+/// same extranet software under different hostnames and paths; operators whose
+/// API differs can override the URLs and map their fields onto [`TrashService`].
+pub trait Provider: std::fmt::Debug + Send + Sync {
+    /// Root of the extranet, also the scope of the session cookies
+    fn base_url(&self) -> &str;
+
+    /// Form POST target for the username/password login
+    fn login_url(&self) -> String;
+
+    /// Page ending the session on the server, if the extranet has one
+    fn logout_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Endpoint returning the services of the given customer numbers
+    fn services_url(&self, username: &str, customer_numbers: &[String]) -> Result<String>;
+
+    /// Endpoint returning the invoices of the account
+    fn invoices_url(&self, username: &str) -> Result<String>;
+
+    /// Endpoint returning the customer details, including the pickup
+    /// address, of the given customer numbers
+    fn customers_url(&self, username: &str, customer_numbers: &[String]) -> Result<String>;
+
+    /// Page of the extranet where customers manage their services
+    fn portal_url(&self) -> String {
+        format!("{}/", self.base_url())
+    }
+
+    /// Convert the services response into the common model
+    fn map_services(&self, raw: &serde_json::Value) -> Result<Vec<TrashService>> {
+        serde_json::from_value(raw.clone())
+            .map_err(|e| PjhoyError::parse("Failed to parse services", e))
+    }
+}
+
+/// The Acegi-secured extranet used by PJHOY and other operators
+#[derive(Debug, Clone)]
+pub struct ExtranetProvider {
+    base_url: String,
+}
+
+impl ExtranetProvider {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
         }
     }
 
-    deduped_cookies.join("; ")
+    /// Pirkanmaan Jätehuolto Oy
+    pub fn pjhoy() -> Self {
+        Self::new("https://extranet.pjhoy.fi/pirkka")
+    }
+}
+
+impl Provider for ExtranetProvider {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn login_url(&self) -> String {
+        format!("{}/j_acegi_security_check?target=2", self.base_url)
+    }
+
+    fn logout_url(&self) -> Option<String> {
+        Some(format!("{}/j_acegi_logout", self.base_url))
+    }
+
+    fn services_url(&self, username: &str, customer_numbers: &[String]) -> Result<String> {
+        construct_api_url(&self.base_url, username, customer_numbers)
+    }
+
+    fn invoices_url(&self, username: &str) -> Result<String> {
+        Ok(format!(
+            "{}/secure/get_invoices_by_customer_number.do?customerNumber={}",
+            self.base_url, username
+        ))
+    }
+
+    fn customers_url(&self, username: &str, customer_numbers: &[String]) -> Result<String> {
+        Ok(format!(
+            "{}/secure/get_customers_by_customer_numbers.do?{}",
+            self.base_url,
+            customer_numbers_query(username, customer_numbers)?
+        ))
+    }
+}
+
+/// Resolve the configured provider, PJHOY unless configured otherwise
+pub fn provider_from_config(config: &Credentials) -> Result<Box<dyn Provider>> {
+    let Some(provider) = &config.provider else {
+        return Ok(Box::new(ExtranetProvider::pjhoy()));
+    };
+
+    match (provider.name.as_deref(), provider.base_url.as_deref()) {
+        (_, Some(base_url)) => Ok(Box::new(ExtranetProvider::new(base_url))),
+        (None | Some("pjhoy"), None) => Ok(Box::new(ExtranetProvider::pjhoy())),
+        (Some(name), None) => Err(PjhoyError::Config(format!(
+            "Unknown provider '{}', set provider.base_url for other extranets",
+            name
+        ))),
+    }
+}
+
+/// The proxy of the `[http]` section. An explicit proxy replaces the one from
+/// environment variables.
+fn configured_proxy(http: &HttpConfig) -> Result<Option<Proxy>> {
+    let Some(proxy_url) = &http.proxy else {
+        return Ok(None);
+    };
+    let proxy = Proxy::all(proxy_url)
+        .map_err(|e| PjhoyError::Config(format!("Invalid proxy URL {}: {}", proxy_url, e)))?
+        .no_proxy(http.no_proxy.as_deref().and_then(NoProxy::from_string));
+    Ok(Some(proxy))
+}
+
+/// The extra root certificates of the `[http]` section
+fn configured_root_certificates(http: &HttpConfig) -> Result<Vec<Certificate>> {
+    let mut all = Vec::new();
+    for ca_path in http.ca_certificates.iter().flatten() {
+        let pem = fs::read(ca_path).map_err(|e| {
+            PjhoyError::io(format!("Failed to read CA certificate {:?}", ca_path), e)
+        })?;
+        let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| {
+            PjhoyError::Config(format!(
+                "Invalid PEM in CA certificate {:?}: {}",
+                ca_path, e
+            ))
+        })?;
+        all.extend(certificates);
+    }
+    Ok(all)
+}
+
+/// HTTP client for the ntfy, Slack and webhook notifiers, with the timeouts,
+/// proxy and root certificates of the `[http]` section like the extranet
+/// client but without its cookies
+pub fn notifier_client(config: &Credentials) -> Result<Client> {
+    let http = config.http.clone().unwrap_or_default();
+    let mut builder = Client::builder()
+        .user_agent(concat!("pjhoy/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(
+            http.connect_timeout_secs
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+        .timeout(Duration::from_secs(
+            http.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS),
+        ));
+    if let Some(proxy) = configured_proxy(&http)? {
+        builder = builder.proxy(proxy);
+    }
+    for certificate in configured_root_certificates(&http)? {
+        builder = builder.add_root_certificate(certificate);
+    }
+    builder
+        .build()
+        .map_err(|e| PjhoyError::Config(format!("Failed to set up the HTTP client: {}", e)))
 }
 
+/// Connection timeout in seconds unless configured otherwise
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Timeout for a whole request in seconds unless configured otherwise
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Customer numbers per request unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 20;
+
+/// Batches requested at the same time unless configured otherwise
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Minimum time between two requests unless configured otherwise
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Spaces requests to the extranet at least `interval` apart, however many
+/// are sent concurrently, so that batches and retries cannot hammer it
 #[derive(Debug)]
-pub struct SessionExpired;
+pub struct RateLimiter {
+    interval: Duration,
+    next: tokio::sync::Mutex<Instant>,
+}
 
-impl std::fmt::Display for SessionExpired {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Session expired")
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request may be sent
+    pub async fn acquire(&self) {
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+}
+
+/// How often and how patiently transient HTTP failures are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay between half and
+    /// all of `base_delay * 2^(attempt - 1)`, capped at `max_delay`
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let random = RandomState::new().hash_one(attempt) % 1000;
+        exponential / 2 + exponential / 2 * random as u32 / 1000
     }
 }
 
-impl std::error::Error for SessionExpired {}
+/// Connection problems and timeouts are worth another try, other errors are not
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request()
+}
 
 #[derive(Debug)]
 pub struct PjhoyClient {
     pub config: Credentials,
+    pub provider: Box<dyn Provider>,
     pub client: Client,
-    pub cookie_jar: Arc<Jar>,
-    pub data_dir: PathBuf,
+    pub cookie_jar: Arc<CookieJar>,
+    pub storage: Arc<dyn Storage>,
+    pub retry: RetryPolicy,
+    pub cassette: Option<Cassette>,
+    /// Customer numbers per services or customers request
+    pub batch_size: usize,
+    /// Batches requested at the same time
+    pub concurrency: usize,
+    pub rate_limiter: RateLimiter,
 }
 
-impl PjhoyClient {
-    pub fn new(config: Credentials, data_dir: PathBuf) -> Result<Self> {
-        let cookie_jar = std::sync::Arc::new(Self::load_cookies(&data_dir)?);
+/// Builder for [`PjhoyClient`]. Everything not set falls back to the
+/// defaults of a config without an `[http]` section.
+#[derive(Debug)]
+pub struct PjhoyClientBuilder {
+    config: Credentials,
+    storage: Arc<dyn Storage>,
+    provider: Option<Box<dyn Provider>>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    proxy: Option<Proxy>,
+    user_agent: Option<String>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    retry: RetryPolicy,
+    root_certificates: Vec<Certificate>,
+    cassette: Option<Cassette>,
+    batch_size: usize,
+    concurrency: usize,
+    min_interval: Duration,
+}
+
+impl PjhoyClientBuilder {
+    /// Talk to the Acegi extranet at `base_url` instead of the configured
+    /// provider, e.g. a local mock server
+    #[allow(dead_code)] // Not needed by the CLI, which reads the provider from the config
+    pub fn base_url(self, base_url: &str) -> Self {
+        self.provider(Box::new(ExtranetProvider::new(base_url)))
+    }
+
+    pub fn provider(mut self, provider: Box<dyn Provider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Timeout for a whole request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Proxy for all requests, replacing the one from environment variables
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Use the given cookies instead of those saved in the data directory
+    pub fn cookie_jar(mut self, cookie_jar: Arc<CookieJar>) -> Self {
+        self.cookie_jar = Some(cookie_jar);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
 
-        let client = Client::builder()
+    /// Customer numbers per request, at least one
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Batches requested at the same time, at least one
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Minimum time between two requests, zero to disable rate limiting
+    pub fn min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = interval;
+        self
+    }
+
+    /// Record the responses to, or answer requests from, a cassette
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Trust an extra root certificate, e.g. of a TLS-intercepting proxy
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Builder set up from the provider and `[http]` section of the config
+    pub fn from_config(config: Credentials, storage: Arc<dyn Storage>) -> Result<Self> {
+        let http = config.http.clone().unwrap_or_default();
+        let provider = provider_from_config(&config)?;
+        let mut builder = PjhoyClient::builder(config, storage)
+            .provider(provider)
+            .user_agent(concat!("pjhoy/", env!("CARGO_PKG_VERSION")));
+
+        if let Some(secs) = http.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = http.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+
+        if let Some(proxy) = configured_proxy(&http)? {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in configured_root_certificates(&http)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        let mut retry = RetryPolicy::default();
+        if let Some(retries) = http.retries {
+            retry.attempts = retries + 1;
+        }
+        if let Some(delay_ms) = http.retry_delay_ms {
+            retry.base_delay = Duration::from_millis(delay_ms);
+        }
+
+        if let Some(batch_size) = http.batch_size {
+            builder = builder.batch_size(batch_size);
+        }
+        if let Some(concurrency) = http.concurrency {
+            builder = builder.concurrency(concurrency);
+        }
+        if let Some(interval_ms) = http.min_interval_ms {
+            builder = builder.min_interval(Duration::from_millis(interval_ms));
+        }
+
+        Ok(builder.retry(retry))
+    }
+
+    pub fn build(self) -> Result<PjhoyClient> {
+        let provider = match self.provider {
+            Some(provider) => provider,
+            None => provider_from_config(&self.config)?,
+        };
+        let cookie_jar = match self.cookie_jar {
+            Some(cookie_jar) => cookie_jar,
+            None => Arc::new(CookieJar::load(self.storage.as_ref(), provider.base_url())?),
+        };
+
+        let mut builder = Client::builder()
             .cookie_provider(cookie_jar.clone())
-            .build()?;
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        for certificate in self.root_certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
 
-        Ok(Self {
-            config,
+        let client = builder
+            .build()
+            .map_err(|e| PjhoyError::Config(format!("Failed to set up the HTTP client: {}", e)))?;
+
+        Ok(PjhoyClient {
+            config: self.config,
+            provider,
             client,
             cookie_jar,
-            data_dir,
+            storage: self.storage,
+            retry: self.retry,
+            cassette: self.cassette,
+            batch_size: self.batch_size,
+            concurrency: self.concurrency,
+            rate_limiter: RateLimiter::new(self.min_interval),
         })
     }
+}
 
-    fn load_cookies(data_dir: &PathBuf) -> Result<Jar> {
-        let cookie_path = data_dir.join("cookies.txt");
+impl PjhoyClient {
+    /// Start building a client for the account of `config`, keeping its
+    /// cookies and diagnostics in `storage`
+    pub fn builder(config: Credentials, storage: Arc<dyn Storage>) -> PjhoyClientBuilder {
+        PjhoyClientBuilder {
+            config,
+            storage,
+            provider: None,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            proxy: None,
+            user_agent: None,
+            cookie_jar: None,
+            retry: RetryPolicy::default(),
+            root_certificates: Vec::new(),
+            cassette: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            min_interval: DEFAULT_MIN_INTERVAL,
+        }
+    }
 
-        if cookie_path.exists() {
-            let cookie_data =
-                fs::read_to_string(&cookie_path).context("Failed to read cookies file")?;
+    /// Client set up from the provider and `[http]` section of the config,
+    /// keeping its state in the files of `data_dir`
+    pub fn new(config: Credentials, data_dir: PathBuf) -> Result<Self> {
+        PjhoyClientBuilder::from_config(config, Arc::new(FileStorage::new(data_dir)))?.build()
+    }
 
-            if cookie_data.trim().is_empty() {
-                Ok(Jar::default())
-            } else {
-                let cookie_jar = Jar::default();
-                let url = "https://extranet.pjhoy.fi/pirkka".parse().unwrap();
+    /// Send a request, retrying transient network errors and 5xx responses
+    /// according to the retry policy
+    async fn send_with_retry(&self, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let request = build()
+                .build()
+                .map_err(|e| PjhoyError::http("Failed to build request", e))?;
+            let (method, url) = (request.method().clone(), request.url().clone());
+            if let Some(cassette) = self.cassette.as_ref().filter(|c| c.is_replay()) {
+                let response = cassette.load(&method, &url)?;
+                debug!(%method, %url, status = %response.status(), "Replayed HTTP response");
+                return Ok(response);
+            }
+            self.rate_limiter.acquire().await;
+            let result = self.client.execute(request).await;
+            match &result {
+                Ok(response) => {
+                    debug!(%method, %url, status = %response.status(), attempt, "HTTP response")
+                }
+                Err(e) => debug!(%method, %url, error = %e, attempt, "HTTP request failed"),
+            }
 
-                for cookie_str in cookie_data.split(';') {
-                    let cookie_str = cookie_str.trim();
-                    if !cookie_str.is_empty() {
-                        cookie_jar.add_cookie_str(cookie_str, &url);
+            let failure = match result {
+                Ok(response)
+                    if response.status().is_server_error() && attempt < self.retry.attempts =>
+                {
+                    format!("server responded {}", response.status())
+                }
+                Ok(response) => {
+                    return match &self.cassette {
+                        Some(cassette) => cassette.save(&method, &url, response).await,
+                        None => Ok(response),
                     }
                 }
-                Ok(cookie_jar)
-            }
-        } else {
-            Ok(Jar::default())
+                Err(e) if is_transient(&e) && attempt < self.retry.attempts => e.to_string(),
+                Err(e) => return Err(PjhoyError::http(format!("Request to {} failed", url), e)),
+            };
+
+            let delay = self.retry.delay(attempt);
+            warn!(
+                "Request failed ({}), retrying in {:.1}s...",
+                failure,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
 
     pub fn save_cookies(&self) -> Result<()> {
-        let cookie_path = self.data_dir.join("cookies.txt");
-        let url = "https://extranet.pjhoy.fi/pirkka".parse().unwrap();
-        let cookies = self.cookie_jar.cookies(&url);
-
-        if let Some(cookie_header) = cookies {
-            fs::write(&cookie_path, deduplicate_cookies(cookie_header.to_str()?))
-                .context("Failed to save cookies")?;
-        } else {
-            // println!("Debug: No cookies to save");
-            fs::write(&cookie_path, "").context("Failed to save empty cookies file")?;
-        }
-        Ok(())
+        self.cookie_jar.save(self.storage.as_ref())
     }
 
     pub async fn login(&mut self) -> Result<()> {
-        let login_url = "https://extranet.pjhoy.fi/pirkka/j_acegi_security_check?target=2";
-        let base_url = "https://extranet.pjhoy.fi/pirkka";
+        // Not required by the config, so that a session imported from
+        // elsewhere works without the password
+        if self.config.password.is_empty() {
+            return Err(PjhoyError::Config(format!(
+                "password is not set: {}",
+                PASSWORD_HINT
+            )));
+        }
+        let login_url = self.provider.login_url();
+        let base_url = self.provider.base_url();
 
         let params = [
             ("j_username", &self.config.username),
@@ -112,100 +603,359 @@ impl PjhoyClient {
             ("remember-me", &"false".to_string()),
         ];
 
-        let _session_response = self
-            .client
-            .get(base_url)
-            .send()
-            .await
-            .context("Failed to establish session")?;
+        debug!(username = %self.config.username, "Logging in");
+        let _session_response = self.send_with_retry(|| self.client.get(base_url)).await?;
 
         let response = self
-            .client
-            .post(login_url)
-            .form(&params)
-            .send()
+            .send_with_retry(|| self.client.post(&login_url).form(&params))
+            .await?;
+
+        check_login_status(response.status())?;
+        let final_url = response.url().clone();
+        debug!(status = %response.status(), final_url = %final_url, "Login response");
+        let body = response
+            .text()
             .await
-            .context("Failed to send login request")?;
+            .map_err(|e| PjhoyError::http("Failed to read login response", e))?;
+        if login_rejected(&final_url, &body) {
+            return Err(PjhoyError::AuthFailed(
+                "invalid username or password, check the config".to_string(),
+            ));
+        }
 
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!("Login failed: {}", response.status()));
+        self.save_cookies()?;
+        Ok(())
+    }
+
+    /// Take over a session logged in elsewhere, e.g. in a browser, from a
+    /// Netscape cookies.txt file. The cookies are saved only when they give
+    /// a valid session.
+    pub async fn import_cookies(&self, text: &str) -> Result<usize> {
+        let url: Url = self
+            .provider
+            .base_url()
+            .parse()
+            .map_err(|e| PjhoyError::Config(format!("Invalid provider base URL: {}", e)))?;
+        let count = self.cookie_jar.import_netscape(text, &url)?;
+        if count == 0 {
+            return Err(PjhoyError::invalid(format!(
+                "No cookies of {} found",
+                url.host_str().unwrap_or_default()
+            )));
+        }
+        if !self.session_valid().await? {
+            return Err(PjhoyError::SessionExpired);
         }
 
-        let url = "https://extranet.pjhoy.fi/pirkka".parse().unwrap();
+        self.save_cookies()?;
+        Ok(count)
+    }
 
-        for set_cookie_header in response.headers().get_all("set-cookie") {
-            let set_cookie_str = set_cookie_header.to_str()?;
-            self.cookie_jar.add_cookie_str(set_cookie_str, &url);
+    /// Take over a session exported on another machine with
+    /// [`CookieJar::export_session`]. The cookies are saved only when they
+    /// give a valid session.
+    pub async fn import_session(&self, session: &str) -> Result<usize> {
+        let count = self.cookie_jar.import_session(session)?;
+        if !self.session_valid().await? {
+            return Err(PjhoyError::SessionExpired);
         }
 
         self.save_cookies()?;
-        Ok(())
+        Ok(count)
+    }
+
+    /// End the session on the server when possible and delete the saved
+    /// cookies. A failure to reach the server does not keep the local
+    /// session around.
+    pub async fn logout(&self) -> Result<()> {
+        if let Some(logout_url) = self.provider.logout_url() {
+            match self.send_with_retry(|| self.client.get(&logout_url)).await {
+                Ok(response) => debug!(status = %response.status(), "Logout response"),
+                Err(e) => warn!("Could not end the session on the server: {:#}", e),
+            }
+        }
+
+        crate::cookies::delete_saved(self.storage.as_ref())
     }
 
     pub async fn fetch_trash_services(&self) -> Result<serde_json::Value> {
-        let customer_numbers = &self.config.customer_numbers;
-        let url = construct_api_url(&self.config.username, customer_numbers)?;
+        self.fetch_services_for(&self.config.customer_numbers).await
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to fetch trash schedule")?;
+    /// Fetch the services of the given customer number suffixes (the `zz` in xx-yyyyyyy-zz)
+    pub async fn fetch_services_for(
+        &self,
+        customer_numbers: &[String],
+    ) -> Result<serde_json::Value> {
+        self.get_json_batched(customer_numbers, "trash schedule", |batch| {
+            self.provider.services_url(&self.config.username, batch)
+        })
+        .await
+    }
 
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch schedule: {}",
-                response.status()
-            ));
+    /// Fetch the invoices of the logged in account
+    pub async fn fetch_invoices(&self) -> Result<serde_json::Value> {
+        let url = self.provider.invoices_url(&self.config.username)?;
+
+        self.get_json(&url, "invoices").await
+    }
+
+    /// Fetch customer details of the configured customer numbers
+    pub async fn fetch_customers(&self) -> Result<Vec<Customer>> {
+        let raw = self
+            .get_json_batched(&self.config.customer_numbers, "customer details", |batch| {
+                self.provider.customers_url(&self.config.username, batch)
+            })
+            .await?;
+        let customers: Vec<Customer> = serde_json::from_value(raw)
+            .map_err(|e| PjhoyError::parse("Failed to parse customer details", e))?;
+        report_unknown_fields(
+            "customer",
+            customers
+                .iter()
+                .flat_map(|customer| customer.unknown.keys().cloned()),
+        );
+        Ok(customers)
+    }
+
+    /// Check the saved session with a lightweight authenticated request
+    pub async fn session_valid(&self) -> Result<bool> {
+        match self.fetch_customers().await {
+            Ok(_) => Ok(true),
+            Err(PjhoyError::SessionExpired) => Ok(false),
+            Err(e) => Err(e),
         }
+    }
+
+    /// When the session cookies were last saved, if there are any
+    pub fn cookies_saved_at(&self) -> Option<std::time::SystemTime> {
+        self.storage.modified(COOKIES_FILE)
+    }
 
+    /// GET an authenticated JSON endpoint, `what` naming the data in error
+    /// messages. The extranet answers with the HTML login page instead of
+    /// JSON when the session has expired.
+    async fn get_json(&self, url: &str, what: &str) -> Result<serde_json::Value> {
+        let response = self.send_with_retry(|| self.client.get(url)).await?;
+        let status = response.status();
         let content_type = response
             .headers()
             .get(reqwest::header::CONTENT_TYPE)
             .and_then(|value| value.to_str().ok())
-            .unwrap_or("");
+            .unwrap_or("")
+            .to_string();
 
-        if !content_type.contains("application/json") {
-            return Err(anyhow::Error::new(SessionExpired));
+        if status.is_success() && !content_type.contains("application/json") {
+            debug!(%content_type, "Expected JSON, assuming the session has expired");
+            return Err(PjhoyError::SessionExpired);
         }
 
-        let json_response: serde_json::Value = response
-            .json()
+        let body = response
+            .text()
             .await
-            .context("Failed to parse JSON response")?;
+            .map_err(|e| PjhoyError::http(format!("Failed to read {}", what), e))?;
+        let parse_error = if status.is_success() {
+            match serde_json::from_str(&body) {
+                Ok(json) => return Ok(json),
+                Err(e) => Some(e),
+            }
+        } else {
+            None
+        };
+
+        let saved = FailedResponse {
+            url,
+            status,
+            content_type: &content_type,
+            body: &body,
+        }
+        .save(self.storage.as_ref());
+        let hint = match saved {
+            Ok(location) => format!(
+                " (response saved to {}, attach it to a bug report)",
+                location
+            ),
+            Err(e) => {
+                warn!("Could not save the failed response: {:#}", e);
+                String::new()
+            }
+        };
 
-        Ok(json_response)
+        Err(match parse_error {
+            Some(e) => PjhoyError::parse(format!("Failed to parse {}{}", what, hint), e),
+            None => PjhoyError::Http {
+                context: format!("Failed to fetch {}: {}{}", what, status, hint),
+                source: None,
+            },
+        })
+    }
+
+    /// GET a list endpoint taking customer numbers in the query string, in
+    /// requests of at most `batch_size` numbers with up to `concurrency` of
+    /// them in flight, and concatenate the lists in the order of the numbers
+    async fn get_json_batched(
+        &self,
+        customer_numbers: &[String],
+        what: &str,
+        url_for: impl Fn(&[String]) -> Result<String>,
+    ) -> Result<serde_json::Value> {
+        if customer_numbers.len() <= self.batch_size {
+            return self.get_json(&url_for(customer_numbers)?, what).await;
+        }
+
+        let url_for = &url_for;
+        let mut responses: Vec<(usize, serde_json::Value)> =
+            stream::iter(customer_numbers.chunks(self.batch_size).enumerate())
+                .map(|(index, batch)| async move {
+                    let url = url_for(batch)?;
+                    Ok::<_, PjhoyError>((index, self.get_json(&url, what).await?))
+                })
+                .buffer_unordered(self.concurrency)
+                .try_collect()
+                .await?;
+        responses.sort_by_key(|(index, _)| *index);
+
+        let mut merged = Vec::new();
+        for (_, response) in responses {
+            match response {
+                serde_json::Value::Array(items) => merged.extend(items),
+                _ => {
+                    return Err(PjhoyError::invalid(format!(
+                        "Expected a list of {} to merge",
+                        what
+                    )))
+                }
+            }
+        }
+        Ok(serde_json::Value::Array(merged))
+    }
+
+    /// Find the customer numbers of the account by requesting the services of
+    /// every suffix from 00 to `max` and collecting those that exist
+    pub async fn discover_customer_numbers(&self, max: u32) -> Result<Vec<String>> {
+        let candidates: Vec<String> = (0..=max).map(|n| format!("{:02}", n)).collect();
+        let raw = self.fetch_services_for(&candidates).await?;
+
+        let mut numbers: Vec<String> = self
+            .parse_services(&raw)?
+            .iter()
+            .filter_map(|service| service.customer_number.rsplit('-').next())
+            .map(str::to_string)
+            .collect();
+        numbers.sort();
+        numbers.dedup();
+
+        Ok(numbers)
+    }
+
+    /// Parse a services response with the provider's field mapping
+    pub fn parse_services(&self, raw: &serde_json::Value) -> Result<Vec<TrashService>> {
+        let services = self.provider.map_services(raw)?;
+        report_unknown_fields(
+            "service",
+            services.iter().flat_map(TrashService::unknown_fields),
+        );
+        Ok(services)
     }
 }
 
-fn construct_api_url(username: &str, customer_numbers: &[String]) -> Result<String> {
+fn construct_api_url(
+    base_url: &str,
+    username: &str,
+    customer_numbers: &[String],
+) -> Result<String> {
+    Ok(format!(
+        "{}/secure/get_services_by_customer_numbers.do?{}",
+        base_url,
+        customer_numbers_query(username, customer_numbers)?
+    ))
+}
+
+/// Query string selecting the given customer number suffixes of the account
+fn customer_numbers_query(username: &str, customer_numbers: &[String]) -> Result<String> {
     if customer_numbers.is_empty() {
-        return Err(anyhow::anyhow!("No customer numbers configured"));
+        return Err(PjhoyError::Config(
+            "No customer numbers configured".to_string(),
+        ));
     }
     let username_parts: Vec<&str> = username.split('-').collect();
     if username_parts.len() < 2 {
-        return Err(anyhow::anyhow!(
-            "Invalid username format. Expected format: xx-yyyyyyy-zz"
+        return Err(PjhoyError::Config(
+            "Invalid username format. Expected format: xx-yyyyyyy-zz".to_string(),
         ));
     }
 
-    Ok(format!(
-        "https://extranet.pjhoy.fi/pirkka/secure/get_services_by_customer_numbers.do?{}",
-        customer_numbers
-            .iter()
-            .map(|cn| format!(
+    Ok(customer_numbers
+        .iter()
+        .map(|cn| {
+            format!(
                 "customerNumbers%5B%5D={}-{}-{}",
                 username_parts[0], username_parts[1], cn
-            ))
-            .collect::<Vec<_>>()
-            .join("&")
-    ))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::MemoryStorage;
+    use anyhow::Result;
+
+    #[test]
+    fn test_save_failed_response() -> Result<()> {
+        let storage = MemoryStorage::default();
+        let location = FailedResponse {
+            url: "https://extranet.pjhoy.fi/pirkka/secure/x.do",
+            status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+            content_type: "text/html",
+            body: "<html>Virhe</html>",
+        }
+        .save(&storage)?;
+
+        assert_eq!(location, FAILED_RESPONSE_FILE);
+        assert_eq!(
+            String::from_utf8(storage.read(FAILED_RESPONSE_FILE)?.unwrap())?,
+            "URL: https://extranet.pjhoy.fi/pirkka/secure/x.do\n\
+             Status: 500 Internal Server Error\n\
+             Content-Type: text/html\n\n<html>Virhe</html>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_login_rejected() -> Result<()> {
+        let form = r#"<form action="j_acegi_security_check"><input type="password" name="j_password"></form>"#;
+        let home: Url = "https://extranet.pjhoy.fi/pirkka/secure/home.do".parse()?;
+        assert!(!login_rejected(&home, "<html>Tervetuloa</html>"));
+        assert!(login_rejected(&home, form));
+
+        let failure: Url = "https://extranet.pjhoy.fi/pirkka/login.jsp?login_error=1".parse()?;
+        assert!(login_rejected(&failure, ""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_login_status() {
+        assert!(check_login_status(reqwest::StatusCode::OK).is_ok());
+        assert!(matches!(
+            check_login_status(reqwest::StatusCode::UNAUTHORIZED),
+            Err(PjhoyError::AuthFailed(_))
+        ));
+        assert!(matches!(
+            check_login_status(reqwest::StatusCode::FORBIDDEN),
+            Err(PjhoyError::AuthFailed(_))
+        ));
+        assert!(matches!(
+            check_login_status(reqwest::StatusCode::SERVICE_UNAVAILABLE),
+            Err(PjhoyError::Http { .. })
+        ));
+        assert!(matches!(
+            check_login_status(reqwest::StatusCode::TOO_MANY_REQUESTS),
+            Err(PjhoyError::Http { .. })
+        ));
+    }
 
     #[test]
     fn test_url_construction() -> Result<()> {
@@ -213,7 +963,11 @@ mod tests {
         let username = "02-2891001-00";
         let customer_numbers = vec!["00".to_string(), "01".to_string(), "02".to_string()];
 
-        let url = construct_api_url(username, &customer_numbers)?;
+        let url = construct_api_url(
+            ExtranetProvider::pjhoy().base_url(),
+            username,
+            &customer_numbers,
+        )?;
 
         // Verify URL contains expected customer numbers
         assert!(url.contains("customerNumbers%5B%5D=02-2891001-00"));
@@ -224,7 +978,11 @@ mod tests {
         let username2 = "02-2030045-99";
         let customer_numbers2 = vec!["99".to_string(), "98".to_string()];
 
-        let url2 = construct_api_url(username2, &customer_numbers2)?;
+        let url2 = construct_api_url(
+            ExtranetProvider::pjhoy().base_url(),
+            username2,
+            &customer_numbers2,
+        )?;
 
         assert!(url2.contains("customerNumbers%5B%5D=02-2030045-99"));
         assert!(url2.contains("customerNumbers%5B%5D=02-2030045-98"));
@@ -233,16 +991,140 @@ mod tests {
     }
 
     #[test]
-    fn test_cookie_deduplication() {
-        let cookie_str = "JSESSIONID=test123; JSESSIONIDVERSION=test456; JSESSIONIDVERSION=test789";
-        let deduped = deduplicate_cookies(cookie_str);
-        assert_eq!(deduped, "JSESSIONID=test123; JSESSIONIDVERSION=test456");
+    fn test_provider_urls() -> Result<()> {
+        let pjhoy = ExtranetProvider::pjhoy();
+        assert_eq!(
+            pjhoy.login_url(),
+            "https://extranet.pjhoy.fi/pirkka/j_acegi_security_check?target=2"
+        );
+        assert_eq!(
+            pjhoy.logout_url().as_deref(),
+            Some("https://extranet.pjhoy.fi/pirkka/j_acegi_logout")
+        );
+        assert!(pjhoy
+            .services_url("02-2891001-00", &["01".to_string()])?
+            .starts_with(
+                "https://extranet.pjhoy.fi/pirkka/secure/get_services_by_customer_numbers.do?"
+            ));
+
+        let other = ExtranetProvider::new("https://extranet.example.fi/jate/");
+        assert_eq!(other.base_url(), "https://extranet.example.fi/jate");
+        assert_eq!(
+            other.services_url("02-2891001-00", &["01".to_string()])?,
+            "https://extranet.example.fi/jate/secure/get_services_by_customer_numbers.do?customerNumbers%5B%5D=02-2891001-01"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> Result<()> {
+        let config: Credentials = serde_json::from_value(serde_json::json!({
+            "username": "02-2891001-00",
+            "password": "secret",
+            "customer_numbers": ["01"],
+        }))?;
+        let cookie_jar = Arc::new(CookieJar::default());
+        let client = PjhoyClient::builder(config, Arc::new(MemoryStorage::default()))
+            .base_url("http://127.0.0.1:8080/jate/")
+            .cookie_jar(cookie_jar.clone())
+            .retry(RetryPolicy {
+                attempts: 1,
+                ..RetryPolicy::default()
+            })
+            .user_agent("pjhoy-test")
+            .build()?;
+
+        assert_eq!(client.provider.base_url(), "http://127.0.0.1:8080/jate");
+        assert!(Arc::ptr_eq(&client.cookie_jar, &cookie_jar));
+        assert_eq!(client.retry.attempts, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_services_fetched_in_batches() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let base_url = "http://127.0.0.1:8080/jate";
+        for (number, customers, names) in [
+            (1, "01", r#"[{"n": "a"}]"#),
+            (2, "02", r#"[{"n": "b"}, {"n": "c"}]"#),
+        ] {
+            let url = format!(
+                "{}/secure/get_services_by_customer_numbers.do?customerNumbers%5B%5D=02-2891001-{}",
+                base_url, customers
+            );
+            let interaction = serde_json::json!({
+                "method": "GET",
+                "url": url,
+                "status": 200,
+                "final_url": url,
+                "content_type": "application/json",
+                "location": null,
+                "body": names,
+            });
+            std::fs::write(
+                dir.join(format!("{:04}.json", number)),
+                interaction.to_string(),
+            )?;
+        }
+
+        let config: Credentials = serde_json::from_value(serde_json::json!({
+            "username": "02-2891001-00",
+            "password": "secret",
+        }))?;
+        let client = PjhoyClient::builder(config, Arc::new(MemoryStorage::default()))
+            .base_url(base_url)
+            .batch_size(1)
+            .cassette(Cassette::replay(dir)?)
+            .build()?;
+
+        let merged = client
+            .fetch_services_for(&["01".to_string(), "02".to_string()])
+            .await?;
+        assert_eq!(
+            merged,
+            serde_json::json!([{"n": "a"}, {"n": "b"}, {"n": "c"}])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_requests() {
+        let limiter = RateLimiter::new(Duration::from_millis(20));
+        let start = Instant::now();
+        futures::future::join_all((0..4).map(|_| limiter.acquire())).await;
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+        };
+
+        for (attempt, full) in [(1, 2), (2, 4), (3, 8), (4, 10), (5, 10)] {
+            let delay = policy.delay(attempt);
+            let full = Duration::from_secs(full);
+            assert!(delay >= full / 2 && delay <= full, "attempt {}", attempt);
+        }
     }
 
     #[test]
     fn test_session_expired_error() {
-        let err = anyhow::Error::new(SessionExpired);
-        assert!(err.downcast_ref::<SessionExpired>().is_some());
+        let err = anyhow::Error::new(PjhoyError::SessionExpired);
+        assert!(matches!(
+            err.downcast_ref::<PjhoyError>(),
+            Some(PjhoyError::SessionExpired)
+        ));
         assert_eq!(err.to_string(), "Session expired");
+
+        let err = err.context("Failed to fetch trash schedule");
+        assert!(matches!(
+            err.downcast_ref::<PjhoyError>(),
+            Some(PjhoyError::SessionExpired)
+        ));
     }
 }
@@ -1,28 +1,966 @@
+use crate::i18n::Language;
+use crate::models::{VatRate, VatRates};
 use anyhow::{Context, Result};
-use config::{Config, File};
+use config::{Config, Environment, File};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Credentials {
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    /// File containing the password, e.g. a Docker secret or systemd
+    /// credential, used when `password` is not set
+    pub password_file: Option<PathBuf>,
+    #[serde(default)]
     pub customer_numbers: Vec<String>,
     pub ics_interval: Option<String>,
+    /// Language of messages and calendar categories: "fi" or "en",
+    /// defaults to the locale and then Finnish
+    pub lang: Option<Language>,
+    /// Flat VAT percentage, overrides the built-in Finnish rates
+    pub vat_rate: Option<f64>,
+    /// Date-aware VAT percentages, overrides the built-in Finnish rates
+    pub vat_rates: Option<Vec<VatRate>>,
+    pub calendar: Option<CalendarConfig>,
+    pub http: Option<HttpConfig>,
+    pub provider: Option<ProviderConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub ntfy: Option<NtfyConfig>,
+    pub email: Option<EmailConfig>,
+    pub slack: Option<SlackConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub hooks: Option<HooksConfig>,
+    pub daemon: Option<DaemonConfig>,
+    /// Directory for the session, saved services and fetch history instead
+    /// of the platform state directory
+    pub state_dir: Option<PathBuf>,
+    /// Further households served by `pjhoy daemon`, each a directory with
+    /// its own config.toml, keyed by the name used in calendar URLs
+    pub tenants: Option<BTreeMap<String, PathBuf>>,
 }
 
-pub fn load_config(config_dir: &PathBuf) -> Result<Credentials> {
+impl Credentials {
+    pub fn vat_rates(&self) -> VatRates {
+        match (&self.vat_rates, self.vat_rate) {
+            (Some(rates), _) => VatRates::new(rates.clone()),
+            (None, Some(percent)) => VatRates::flat(percent),
+            (None, None) => VatRates::default(),
+        }
+    }
+}
+
+/// Waste management operator whose extranet is used
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProviderConfig {
+    /// Built-in provider, currently only "pjhoy"
+    pub name: Option<String>,
+    /// Root URL of another operator running the same extranet software,
+    /// takes precedence over `name`
+    pub base_url: Option<String>,
+}
+
+/// Customization of the generated calendar events
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CalendarConfig {
+    /// Event summary template, e.g. "{icon} {group} ({container})".
+    /// Placeholders: {icon}, {group}, {code}, {name}, {container}, {cost},
+    /// {interval}, {customer} and {address}
+    pub summary: Option<String>,
+    /// Event description template with the same placeholders
+    pub description: Option<String>,
+    /// Prefix event summaries with product group emojis, on by default
+    pub emoji: Option<bool>,
+    /// Product group icons replacing the built-in emojis, keyed by code
+    pub icons: Option<HashMap<String, String>>,
+    /// Mark events as free time, on by default
+    pub transparent: Option<bool>,
+    /// Calendar name shown by subscribing clients, "Jätekalenteri" by default
+    pub name: Option<String>,
+    /// Timezone hint for clients, "Europe/Helsinki" by default
+    pub timezone: Option<String>,
+    /// Emit METHOD:PUBLISH, required by some servers for imports
+    pub method_publish: Option<bool>,
+    /// Link added to each event, the extranet front page by default
+    pub url: Option<String>,
+    /// Combine services picked up on the same day into one event
+    pub merge_same_day: Option<bool>,
+    /// Number of future pickups to generate per service by repeating
+    /// its interval, only the next one by default
+    pub occurrences: Option<u32>,
+    /// What to do with generated pickups falling on public holidays
+    pub holidays: Option<HolidayPolicy>,
+    /// Keep past events of the existing calendar file when writing a new one
+    pub merge: Option<bool>,
+    /// Previous calendar files to keep as `pjhoy.ics.1`, `pjhoy.ics.2`...,
+    /// none by default
+    pub backups: Option<usize>,
+    /// Secret required to download the calendar from the daemon, as
+    /// `/<token>/pjhoy.ics` or as a bearer token
+    pub token: Option<String>,
+}
+
+/// Handling of generated pickups falling on Finnish public holidays
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HolidayPolicy {
+    /// Note in the description that the pickup may move
+    #[default]
+    Flag,
+    /// Move the pickup to the next working day
+    Shift,
+}
+
+/// Connection settings for the extranet HTTP client. The timeouts, proxy and
+/// certificates also apply to the notifiers.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HttpConfig {
+    /// Retries after a transient failure, defaults to 2
+    pub retries: Option<u32>,
+    /// Initial retry delay in milliseconds, doubled on every retry
+    pub retry_delay_ms: Option<u64>,
+    /// Connection timeout in seconds, defaults to 10
+    pub connect_timeout_secs: Option<u64>,
+    /// Timeout for a whole request in seconds, defaults to 60
+    pub timeout_secs: Option<u64>,
+    /// Proxy for all requests, e.g. "http://proxy:3128" or "socks5h://proxy:1080".
+    /// Without it HTTP_PROXY, HTTPS_PROXY and ALL_PROXY are honored.
+    pub proxy: Option<String>,
+    /// Comma separated hosts that bypass `proxy`
+    pub no_proxy: Option<String>,
+    /// Extra trusted root certificates as PEM files, e.g. of a TLS-intercepting proxy
+    pub ca_certificates: Option<Vec<PathBuf>>,
+    /// Customer numbers per request, defaults to 20. Accounts with many
+    /// customer numbers are fetched in several requests to keep the URL short.
+    pub batch_size: Option<usize>,
+    /// Batches requested at the same time, defaults to 4
+    pub concurrency: Option<usize>,
+    /// Minimum time between two requests in milliseconds, defaults to 250.
+    /// 0 disables rate limiting.
+    pub min_interval_ms: Option<u64>,
+}
+
+/// MQTT broker connection and topic layout
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Defaults to "pjhoy_" and the account, like the topics
+    pub client_id: Option<String>,
+    /// Prefix for state topics, defaults to "pjhoy"
+    pub topic_prefix: Option<String>,
+    /// Home Assistant discovery prefix, defaults to "homeassistant"
+    pub discovery_prefix: Option<String>,
+    /// Publish Home Assistant discovery configs, defaults to true
+    pub discovery: Option<bool>,
+}
+
+/// ntfy push notification target
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NtfyConfig {
+    /// Server base URL, defaults to "https://ntfy.sh"
+    pub server: Option<String>,
+    pub topic: String,
+    /// Access token for protected topics
+    pub token: Option<String>,
+    /// Default message priority (1-5), defaults to 3
+    pub priority: Option<u8>,
+    /// Per product group priority overrides, e.g. `BIO = 4`
+    pub priorities: Option<HashMap<String, u8>>,
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS, usually port 587
+    Starttls,
+    /// Implicit TLS, usually port 465
+    Tls,
+    /// Unencrypted, only for local relays
+    None,
+}
+
+/// SMTP server and addresses for email reminders
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    /// Defaults to STARTTLS
+    pub security: Option<SmtpSecurity>,
+    pub username: Option<String>,
+    /// Account or app password
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Slack incoming webhook for reminders and alerts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlackConfig {
+    /// e.g. "https://hooks.slack.com/services/T000/B000/XXXX", a secret
+    pub webhook_url: String,
+}
+
+/// Endpoint receiving a JSON diff whenever the schedule changes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Sent as a bearer token when set
+    pub token: Option<String>,
+}
+
+/// Shell commands run after fetches, with the outcome in `PJHOY_*`
+/// environment variables
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HooksConfig {
+    /// Run when the schedule changed since the previous fetch, with the
+    /// changes as JSON on stdin
+    pub on_change: Option<String>,
+    pub on_fetch_success: Option<String>,
+    pub on_fetch_failure: Option<String>,
+    /// Seconds a command may run before it is killed, defaults to 60
+    pub timeout_secs: Option<u64>,
+}
+
+/// When `pjhoy daemon` runs its jobs, as cron expressions with seconds
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DaemonConfig {
+    /// Fetch and calendar update, defaults to "0 0 6 * * *" (06:00 daily)
+    pub fetch: Option<String>,
+    /// Reminders of tomorrow's pickups, defaults to "0 0 19 * * *" (19:00
+    /// daily). Only used when ntfy, email or Slack is configured.
+    pub notify: Option<String>,
+    /// Address to serve the calendars, their feeds, Prometheus metrics and
+    /// the health check on, e.g. "127.0.0.1:9464"
+    pub listen: Option<String>,
+    /// Fetch again when a served calendar is asked for and is older than
+    /// this, at most once per this many hours
+    pub ttl_hours: Option<u32>,
+    /// PEM certificate chain to serve HTTPS with
+    pub tls_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key of `tls_cert`
+    pub tls_key: Option<PathBuf>,
+}
+
+/// A single problem found in the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Dotted key of the offending setting, when known
+    pub key: Option<String>,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Problem {
+    fn new(key: Option<&str>, message: impl Into<String>, hint: Option<&str>) -> Self {
+        Self {
+            key: key.map(str::to_string),
+            message: message.into(),
+            hint: hint.map(str::to_string),
+        }
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}: {}", key, self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n    hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// The config file is missing or has invalid settings
+#[derive(Debug)]
+pub struct InvalidConfig {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl std::fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid config file {}:", self.path.display())?;
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+const USERNAME_HINT: &str =
+    "expected the login customer number of the form xx-yyyyyyy-zz, e.g. \"02-2891001-00\"";
+
+pub const PASSWORD_HINT: &str =
+    "the password of the extranet account, or set password_file or use --password-stdin";
+
+const MINIMAL_CONFIG: &str = "create it with at least:\n        \
+     username = \"xx-yyyyyyy-00\"\n        \
+     password = \"secret\"\n        \
+     customer_numbers = [\"00\", \"01\"]";
+
+/// Hint for a setting whose value has the wrong type or is missing
+fn hint_for(key: &str) -> Option<&'static str> {
+    match key {
+        "username" => Some(USERNAME_HINT),
+        "password" => Some(PASSWORD_HINT),
+        "customer_numbers" => {
+            Some("a list of two-digit customer number suffixes, e.g. [\"00\", \"01\"]")
+        }
+        "ics_interval" => Some("an ISO 8601 duration, e.g. \"P1D\" or \"PT12H\""),
+        "lang" => Some("\"fi\" or \"en\""),
+        "calendar.holidays" => Some("\"flag\" or \"shift\""),
+        "email.security" => Some("\"starttls\", \"tls\" or \"none\""),
+        _ => None,
+    }
+}
+
+/// Turn a config crate error into a problem naming the offending key
+fn describe_error(error: &config::ConfigError) -> Problem {
+    match error {
+        config::ConfigError::Type {
+            key: Some(key),
+            unexpected,
+            expected,
+            ..
+        } => Problem::new(
+            Some(key),
+            format!("expected {}, found {}", expected, unexpected),
+            hint_for(key),
+        ),
+        config::ConfigError::FileParse { cause, .. } => Problem::new(
+            None,
+            format!("not valid TOML: {}", cause),
+            Some("check quotes and brackets around the reported line"),
+        ),
+        error => {
+            let message = error.to_string();
+            match message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.strip_suffix('`'))
+            {
+                Some(key) => Problem::new(Some(key), "is required", hint_for(key)),
+                None => Problem::new(None, message, None),
+            }
+        }
+    }
+}
+
+fn is_customer_number(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Check the values of settings that deserialized fine
+pub fn validate(config: &Credentials) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if !is_customer_number(&config.username) {
+        problems.push(Problem::new(
+            Some("username"),
+            format!("\"{}\" is not a customer number", config.username),
+            Some(USERNAME_HINT),
+        ));
+    }
+    if let Some(suffix) = config
+        .customer_numbers
+        .iter()
+        .find(|suffix| suffix.len() != 2 || !suffix.chars().all(|c| c.is_ascii_digit()))
+    {
+        problems.push(Problem::new(
+            Some("customer_numbers"),
+            format!("\"{}\" is not a two-digit suffix", suffix),
+            hint_for("customer_numbers"),
+        ));
+    }
+    if let Some(interval) = config
+        .ics_interval
+        .as_deref()
+        .filter(|interval| !interval.starts_with('P'))
+    {
+        problems.push(Problem::new(
+            Some("ics_interval"),
+            format!("\"{}\" is not a duration", interval),
+            hint_for("ics_interval"),
+        ));
+    }
+    if let Some(rate) = config.vat_rate.filter(|rate| !(0.0..100.0).contains(rate)) {
+        problems.push(Problem::new(
+            Some("vat_rate"),
+            format!("{} is not a percentage", rate),
+            Some("e.g. 25.5"),
+        ));
+    }
+    if let Some(priority) = config
+        .ntfy
+        .as_ref()
+        .and_then(|ntfy| ntfy.priority)
+        .filter(|priority| !(1..=5).contains(priority))
+    {
+        problems.push(Problem::new(
+            Some("ntfy.priority"),
+            format!("{} is out of range", priority),
+            Some("1 (min) to 5 (max)"),
+        ));
+    }
+
+    let daemon = config.daemon.clone().unwrap_or_default();
+    for (key, schedule) in [
+        ("daemon.fetch", &daemon.fetch),
+        ("daemon.notify", &daemon.notify),
+    ] {
+        if let Some(schedule) = schedule
+            .as_deref()
+            .filter(|schedule| crate::daemon::parse_schedule(schedule).is_err())
+        {
+            problems.push(Problem::new(
+                Some(key),
+                format!("\"{}\" is not a cron schedule", schedule),
+                Some("sec min hour day month weekday, e.g. \"0 0 6 * * *\""),
+            ));
+        }
+    }
+    if let Some(listen) = daemon
+        .listen
+        .as_deref()
+        .filter(|listen| listen.parse::<SocketAddr>().is_err())
+    {
+        problems.push(Problem::new(
+            Some("daemon.listen"),
+            format!("\"{}\" is not an address", listen),
+            Some("IP address and port, e.g. \"127.0.0.1:9464\""),
+        ));
+    }
+    if daemon.ttl_hours == Some(0) {
+        problems.push(Problem::new(
+            Some("daemon.ttl_hours"),
+            "must be at least 1",
+            Some("hours, e.g. 6"),
+        ));
+    }
+    for name in config.tenants.iter().flatten().map(|(name, _)| name) {
+        let url_safe = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(url_safe) {
+            problems.push(Problem::new(
+                Some(format!("tenants.{}", name).as_str()),
+                "tenant names are used in URLs",
+                Some("letters a-z, digits, - and _"),
+            ));
+        }
+    }
+    if daemon.tls_cert.is_some() != daemon.tls_key.is_some() {
+        problems.push(Problem::new(
+            Some("daemon.tls_key"),
+            "tls_cert and tls_key must be set together",
+            None,
+        ));
+    }
+
+    problems
+}
+
+/// Prefix of environment variables overriding settings
+const ENV_PREFIX: &str = "PJHOY";
+
+/// Deserialize config file contents layered with `PJHOY_*` variables from
+/// `env`. Nested settings use a double underscore, e.g.
+/// `PJHOY_CALENDAR__EMOJI`, and `PJHOY_CUSTOMER_NUMBERS` is a comma
+/// separated list.
+fn deserialize_with_env(
+    content: &str,
+    env: &HashMap<String, String>,
+) -> std::result::Result<Credentials, config::ConfigError> {
+    let mut builder = Config::builder()
+        .add_source(File::from_str(content, config::FileFormat::Toml))
+        .add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__")
+                .ignore_empty(true)
+                .source(Some(env.clone())),
+        );
+
+    if let Some(numbers) = env
+        .get(&format!("{}_CUSTOMER_NUMBERS", ENV_PREFIX))
+        .filter(|numbers| !numbers.is_empty())
+    {
+        let numbers: Vec<String> = numbers
+            .split(',')
+            .map(|number| number.trim().to_string())
+            .collect();
+        builder = builder.set_override("customer_numbers", numbers)?;
+    }
+
+    builder.build()?.try_deserialize()
+}
+
+fn deserialize(content: &str) -> std::result::Result<Credentials, config::ConfigError> {
+    deserialize_with_env(content, &HashMap::new())
+}
+
+/// Environment variable naming the age identity file that decrypts
+/// config.toml.age, `identity.txt` in the config directory by default
+const IDENTITY_ENV: &str = "PJHOY_IDENTITY";
+
+fn identity_path(config_dir: &Path) -> PathBuf {
+    std::env::var_os(IDENTITY_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config_dir.join("identity.txt"))
+}
+
+/// Decrypt an age encrypted, optionally armored, file with the identities
+/// in `identity_path`
+fn decrypt_file(path: &Path, identity_path: &Path) -> Result<String> {
+    use std::io::Read;
+
+    let identities = age::IdentityFile::from_file(identity_path.to_string_lossy().into_owned())
+        .context(format!("Failed to read age identity {:?}", identity_path))?
+        .into_identities()
+        .context(format!("Invalid age identity {:?}", identity_path))?;
+
+    let ciphertext = std::fs::read(path).context(format!("Failed to read {:?}", path))?;
+    let decryptor = age::Decryptor::new_buffered(age::armor::ArmoredReader::new(&ciphertext[..]))
+        .context(format!("{:?} is not an age encrypted file", path))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref() as _))
+        .context(format!("Failed to decrypt {:?}", path))?;
+
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .context(format!("Failed to decrypt {:?}", path))?;
+    Ok(content)
+}
+
+/// Path and contents of config.toml, or of config.toml.age decrypted,
+/// whichever exists
+fn read_config_text(config_dir: &Path) -> Result<Option<(PathBuf, String)>> {
     let config_path = config_dir.join("config.toml");
+    if config_path.exists() {
+        let content = std::fs::read_to_string(&config_path)
+            .context(format!("Failed to read {:?}", config_path))?;
+        return Ok(Some((config_path, content)));
+    }
 
-    let settings = Config::builder()
-        .add_source(File::from(config_path))
-        .build()?;
+    let encrypted_path = config_dir.join("config.toml.age");
+    if encrypted_path.exists() {
+        let content = decrypt_file(&encrypted_path, &identity_path(config_dir))?;
+        return Ok(Some((encrypted_path, content)));
+    }
 
-    let credentials: Credentials = settings.try_deserialize()?;
+    Ok(None)
+}
+
+/// Read a password file, dropping the trailing newline
+fn read_password_file(path: &Path) -> std::io::Result<String> {
+    let password = std::fs::read_to_string(path)?;
+    Ok(password.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Load config.toml with environment variable overrides. The file may be
+/// left out when the environment provides the settings. `password`, e.g.
+/// read from stdin, overrides the configured one.
+pub fn load_config(config_dir: &Path, password: Option<String>) -> Result<Credentials> {
+    let env: HashMap<String, String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with(&format!("{}_", ENV_PREFIX)) && name != IDENTITY_ENV)
+        .collect();
+    load_config_with_env(config_dir, password, &env)
+}
+
+/// Config of a tenant, which `PJHOY_*` variables meant for the main
+/// account must not override
+pub fn load_tenant_config(config_dir: &Path) -> Result<Credentials> {
+    load_config_with_env(config_dir, None, &HashMap::new())
+}
+
+fn load_config_with_env(
+    config_dir: &Path,
+    password: Option<String>,
+    env: &HashMap<String, String>,
+) -> Result<Credentials> {
+    let (config_path, content) = match read_config_text(config_dir)? {
+        Some(config) => config,
+        None if env.is_empty() => {
+            return Err(InvalidConfig {
+                path: config_dir.join("config.toml"),
+                problems: vec![Problem::new(None, "file not found", Some(MINIMAL_CONFIG))],
+            }
+            .into())
+        }
+        None => (config_dir.join("config.toml"), String::new()),
+    };
+
+    let invalid = |problems| InvalidConfig {
+        path: config_path.clone(),
+        problems,
+    };
+
+    let mut credentials =
+        deserialize_with_env(&content, env).map_err(|e| invalid(vec![describe_error(&e)]))?;
+
+    // A password given on the command line wins over the config, which
+    // wins over the password file
+    if let Some(password) = password {
+        credentials.password = password;
+    } else if credentials.password.is_empty() {
+        if let Some(path) = &credentials.password_file {
+            credentials.password = read_password_file(path).map_err(|e| {
+                invalid(vec![Problem::new(
+                    Some("password_file"),
+                    format!("cannot read {}: {}", path.display(), e),
+                    None,
+                )])
+            })?;
+        }
+    }
+
+    let problems = validate(&credentials);
+    if !problems.is_empty() {
+        return Err(invalid(problems).into());
+    }
     Ok(credentials)
 }
 
+/// Refuse to write config.toml when the settings are in config.toml.age,
+/// which would leave them decrypted next to it
+fn ensure_not_encrypted(config_dir: &Path) -> Result<()> {
+    if !config_dir.join("config.toml").exists() && config_dir.join("config.toml.age").exists() {
+        return Err(anyhow::anyhow!(
+            "config.toml.age is encrypted, decrypt it with age to change settings"
+        ));
+    }
+    Ok(())
+}
+
+/// Replace `customer_numbers` in config.toml, keeping the rest of the file intact
+pub fn save_customer_numbers(config_dir: &Path, customer_numbers: &[String]) -> Result<()> {
+    ensure_not_encrypted(config_dir)?;
+    let config_path = config_dir.join("config.toml");
+    let mut document = read_document(config_dir)?;
+
+    document["customer_numbers"] = toml_edit::value(toml_edit::Array::from_iter(customer_numbers));
+
+    crate::storage::write_atomic(&config_path, document.to_string().as_bytes(), 0)
+        .context(format!("Failed to write {:?}", config_path))?;
+    Ok(())
+}
+
+/// Keys whose values are hidden by `pjhoy config show`
+const SECRET_KEYS: &[&str] = &["password", "token", "webhook_url"];
+
+fn read_document(config_dir: &Path) -> Result<toml_edit::DocumentMut> {
+    let (config_path, content) = read_config_text(config_dir)?.context(format!(
+        "Failed to read {:?}",
+        config_dir.join("config.toml")
+    ))?;
+    content
+        .parse()
+        .context(format!("Failed to parse {:?}", config_path))
+}
+
+/// Replace secret values in a table and its subtables with asterisks
+fn redact(table: &mut dyn toml_edit::TableLike) {
+    for (key, item) in table.iter_mut() {
+        if let Some(value) = item
+            .as_value_mut()
+            .filter(|_| SECRET_KEYS.contains(&key.get()))
+        {
+            let decor = value.decor().clone();
+            *value = "********".into();
+            *value.decor_mut() = decor;
+        } else if let Some(subtable) = item.as_table_like_mut() {
+            redact(subtable);
+        }
+    }
+}
+
+/// The config file with passwords and tokens hidden
+pub fn show_config(config_dir: &Path) -> Result<String> {
+    let mut document = read_document(config_dir)?;
+    redact(document.as_table_mut());
+    Ok(document.to_string())
+}
+
+/// Look up a dotted key such as `calendar.emoji`
+fn get_item<'a>(document: &'a toml_edit::DocumentMut, key: &str) -> Option<&'a toml_edit::Item> {
+    key.split('.')
+        .try_fold(document.as_item(), |item, part| item.get(part))
+        .filter(|item| !item.is_none())
+}
+
+/// Value of a dotted key in the config file. Strings are printed without
+/// quotes, other values as TOML.
+pub fn get_config_value(config_dir: &Path, key: &str) -> Result<String> {
+    let document = read_document(config_dir)?;
+    let item = get_item(&document, key).context(format!("{} is not set", key))?;
+    Ok(match item.as_str() {
+        Some(text) => text.to_string(),
+        None => item.to_string().trim().to_string(),
+    })
+}
+
+/// Set a dotted key to `raw`, parsed as a TOML value (e.g. `true`, `12` or
+/// `["00", "01"]`) or taken as a string when it is not one
+fn set_item(document: &mut toml_edit::DocumentMut, key: &str, raw: &str) -> Result<()> {
+    let value = raw
+        .parse::<toml_edit::Value>()
+        .unwrap_or_else(|_| toml_edit::Value::from(raw));
+
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|part| !part.is_empty());
+    let last = last.context(format!("Invalid key: {}", key))?;
+
+    let mut table = document.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for part in parts {
+        let entry = table
+            .entry(part)
+            .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()));
+        table = entry
+            .as_table_like_mut()
+            .context(format!("{} is not a table", part))?;
+    }
+    table.insert(last, toml_edit::value(value));
+    Ok(())
+}
+
+/// Check that the document is a valid config and `key` is one of its
+/// settings. Username and password may still be missing while the file
+/// is being written.
+fn validate_document(document: &toml_edit::DocumentMut, key: &str) -> Result<()> {
+    let mut probe = document.clone();
+    for required in ["username", "password"] {
+        if !probe.contains_key(required) {
+            probe[required] = toml_edit::value("");
+        }
+    }
+
+    let credentials = deserialize(&probe.to_string())
+        .map_err(|e| anyhow::anyhow!("Invalid value for {}: {}", key, describe_error(&e)))?;
+    if let Some(problem) = validate(&credentials)
+        .into_iter()
+        .find(|problem| problem.key.as_deref() == Some(key))
+    {
+        return Err(anyhow::anyhow!("{}", problem));
+    }
+
+    let known = serde_json::to_value(&credentials)?;
+    let found = key
+        .split('.')
+        .try_fold(&known, |value, part| value.get(part))
+        .filter(|value| !value.is_null());
+    if found.is_none() {
+        return Err(anyhow::anyhow!("Unknown setting: {}", key));
+    }
+    Ok(())
+}
+
+/// Set a dotted key in config.toml after validating the result, keeping
+/// the rest of the file intact. The file is created if missing.
+pub fn set_config_value(config_dir: &Path, key: &str, raw: &str) -> Result<()> {
+    ensure_not_encrypted(config_dir)?;
+    let config_path = config_dir.join("config.toml");
+    let mut document = if config_path.exists() {
+        read_document(config_dir)?
+    } else {
+        toml_edit::DocumentMut::new()
+    };
+
+    set_item(&mut document, key, raw)?;
+    validate_document(&document, key)?;
+
+    crate::storage::write_atomic(&config_path, document.to_string().as_bytes(), 0)
+        .context(format!("Failed to write {:?}", config_path))?;
+    Ok(())
+}
+
 pub fn get_project_dirs() -> Result<ProjectDirs> {
     ProjectDirs::from("fi", "pjhoy", "pjhoy").context("Could not determine project directories")
 }
+
+/// Where the session and other files changing on every run go by default,
+/// away from the config so that backing it up leaves them out: the state
+/// directory on Linux (~/.local/state/pjhoy), the cache directory elsewhere
+pub fn default_state_dir(proj_dirs: &ProjectDirs) -> PathBuf {
+    proj_dirs
+        .state_dir()
+        .unwrap_or_else(|| proj_dirs.cache_dir())
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get_items() -> Result<()> {
+        let mut document: toml_edit::DocumentMut =
+            "username = \"02-2891001-00\" # account\n".parse()?;
+
+        set_item(&mut document, "customer_numbers", r#"["00", "01"]"#)?;
+        set_item(&mut document, "calendar.emoji", "false")?;
+        set_item(&mut document, "calendar.name", "Kotikadun jätteet")?;
+        validate_document(&document, "calendar.name")?;
+
+        assert!(document.to_string().contains("# account"));
+        assert_eq!(
+            get_item(&document, "calendar.emoji").and_then(|item| item.as_bool()),
+            Some(false)
+        );
+        assert_eq!(
+            get_item(&document, "calendar.name").and_then(|item| item.as_str()),
+            Some("Kotikadun jätteet")
+        );
+        assert!(get_item(&document, "calendar.url").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_values() -> Result<()> {
+        let mut document = toml_edit::DocumentMut::new();
+        set_item(&mut document, "calendar.occurrences", "many")?;
+        assert!(validate_document(&document, "calendar.occurrences").is_err());
+
+        let mut document = toml_edit::DocumentMut::new();
+        set_item(&mut document, "calendar.colour", "red")?;
+        assert!(validate_document(&document, "calendar.colour").is_err());
+        Ok(())
+    }
+
+    fn credentials(username: &str, customer_numbers: &[&str]) -> Credentials {
+        let mut document = toml_edit::DocumentMut::new();
+        document["username"] = toml_edit::value(username);
+        document["password"] = toml_edit::value("secret");
+        document["customer_numbers"] = toml_edit::value(toml_edit::Array::from_iter(
+            customer_numbers.iter().copied(),
+        ));
+        deserialize(&document.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&credentials("02-2891001-00", &["00", "01"])).is_empty());
+
+        let problems = validate(&credentials("2891001", &["1"]));
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].key.as_deref(), Some("username"));
+        assert_eq!(problems[0].hint.as_deref(), Some(USERNAME_HINT));
+        assert_eq!(problems[1].key.as_deref(), Some("customer_numbers"));
+
+        let mut config = credentials("02-2891001-00", &["01"]);
+        config.tenants = Some(BTreeMap::from([
+            ("mökki".to_string(), PathBuf::from("/srv/pjhoy/mokki")),
+            ("koti-2".to_string(), PathBuf::from("/srv/pjhoy/koti")),
+        ]));
+        let problems = validate(&config);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key.as_deref(), Some("tenants.mökki"));
+    }
+
+    #[test]
+    fn test_describe_errors() {
+        let error = deserialize("password = \"x\"").unwrap_err();
+        assert_eq!(
+            describe_error(&error),
+            Problem::new(Some("username"), "is required", Some(USERNAME_HINT))
+        );
+
+        let error = deserialize(
+            "username = \"02-2891001-00\"\npassword = \"x\"\n[calendar]\noccurrences = \"x\"",
+        )
+        .unwrap_err();
+        let problem = describe_error(&error);
+        assert_eq!(problem.key.as_deref(), Some("calendar.occurrences"));
+        assert!(problem.message.starts_with("expected an integer"));
+
+        let error = deserialize("username = \"x").unwrap_err();
+        assert!(describe_error(&error).message.starts_with("not valid TOML"));
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        let env = HashMap::from([
+            ("PJHOY_PASSWORD".to_string(), "from-env".to_string()),
+            ("PJHOY_CUSTOMER_NUMBERS".to_string(), "00, 03".to_string()),
+            ("PJHOY_CALENDAR__EMOJI".to_string(), "false".to_string()),
+            ("PJHOY_ICS_INTERVAL".to_string(), "PT6H".to_string()),
+        ]);
+
+        let config = deserialize_with_env(
+            "username = \"02-2891001-00\"\npassword = \"from-file\"\ncustomer_numbers = [\"01\"]",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(config.password, "from-env");
+        assert_eq!(config.customer_numbers, vec!["00", "03"]);
+        assert_eq!(config.ics_interval.as_deref(), Some("PT6H"));
+        assert_eq!(
+            config.calendar.and_then(|calendar| calendar.emoji),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_read_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "s3cret \n").unwrap();
+
+        assert_eq!(read_password_file(&path).unwrap(), "s3cret ");
+    }
+
+    #[test]
+    fn test_decrypt_file() -> Result<()> {
+        use age::secrecy::ExposeSecret;
+
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let identity = age::x25519::Identity::generate();
+        let identity_path = dir.join("identity.txt");
+        std::fs::write(&identity_path, identity.to_string().expose_secret())?;
+
+        let plaintext = "username = \"02-2891001-00\"\n";
+        let encrypted_path = dir.join("config.toml.age");
+        std::fs::write(
+            &encrypted_path,
+            age::encrypt(&identity.to_public(), plaintext.as_bytes())?,
+        )?;
+        assert_eq!(decrypt_file(&encrypted_path, &identity_path)?, plaintext);
+        // Saving would write the decrypted settings in plain text
+        assert!(save_customer_numbers(dir, &["00".to_string()]).is_err());
+        assert!(!dir.join("config.toml").exists());
+
+        let other = age::x25519::Identity::generate();
+        std::fs::write(&identity_path, other.to_string().expose_secret())?;
+        assert!(decrypt_file(&encrypted_path, &identity_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_redact() -> Result<()> {
+        let mut document: toml_edit::DocumentMut =
+            "password = \"secret\"\n[ntfy]\ntopic = \"t\"\ntoken = \"tk\"\n".parse()?;
+
+        redact(document.as_table_mut());
+
+        let text = document.to_string();
+        assert!(!text.contains("secret") && !text.contains("tk\""));
+        assert!(text.contains("topic = \"t\""));
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::models::{ProductGroup, TrashService};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::fmt;
+
+/// Service agreement details of one service
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ServiceContract {
+    pub customer_number: String,
+    pub name: String,
+    pub product_group: Option<ProductGroup>,
+    pub tariff: Option<String>,
+    pub container_size: Option<String>,
+    pub containers: Option<f64>,
+    pub interval_weeks: String,
+    pub valid_from: Option<NaiveDate>,
+    pub valid_until: Option<NaiveDate>,
+}
+
+impl From<&TrashService> for ServiceContract {
+    fn from(service: &TrashService) -> Self {
+        let tariff = service.tariff.as_ref();
+        Self {
+            customer_number: service.customer_number.clone(),
+            name: service.name.clone(),
+            product_group: tariff.and_then(|t| t.product_group.clone()),
+            tariff: tariff.and_then(|t| t.name.clone()),
+            container_size: tariff.and_then(|t| t.container_size.clone()),
+            containers: service.containers,
+            interval_weeks: service.interval.clone(),
+            valid_from: service
+                .start_date
+                .or_else(|| tariff.and_then(|t| t.valid_from)),
+            valid_until: service.end_date.or_else(|| tariff.and_then(|t| t.valid_to)),
+        }
+    }
+}
+
+/// Table of service agreements
+pub struct ContractTable<'a>(pub &'a [ServiceContract]);
+
+impl fmt::Display for ContractTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:<28} {:<10} {:>4} {:>9} {:<24} Valid",
+            "Customer", "Service", "Container", "Qty", "Interval", "Tariff"
+        )?;
+        for contract in self.0 {
+            writeln!(
+                f,
+                "{:<16} {:<28} {:<10} {:>4} {:>7} w {:<24} {} – {}",
+                contract.customer_number,
+                contract.name,
+                contract.container_size.as_deref().unwrap_or("-"),
+                contract
+                    .containers
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                contract.interval_weeks,
+                contract.tariff.as_deref().unwrap_or("-"),
+                contract
+                    .valid_from
+                    .map(|date| date.to_string())
+                    .unwrap_or_default(),
+                contract
+                    .valid_until
+                    .map(|date| date.to_string())
+                    .unwrap_or_default(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Tariff;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_contract_validity_falls_back_to_tariff() {
+        let service = TrashService {
+            next_date: "2024-03-05".parse().ok(),
+            name: "Biojäte".to_string(),
+            customer_number: "02-2891001-01".to_string(),
+            position: 1,
+            service_type: Some(1),
+            price: Some(Decimal::TEN),
+            interval: "2".to_string(),
+            address: None,
+            unknown: Default::default(),
+            containers: Some(1.0),
+            start_date: "2023-01-01".parse().ok(),
+            end_date: None,
+            tariff: Some(Tariff {
+                product_group: Some(ProductGroup::Bio),
+                name: Some("Biojäte 140 l".to_string()),
+                container_size: Some("140 l".to_string()),
+                valid_from: "2024-01-01".parse().ok(),
+                valid_to: "2024-12-31".parse().ok(),
+                unknown: Default::default(),
+            }),
+        };
+
+        let contract = ServiceContract::from(&service);
+
+        assert_eq!(contract.container_size.as_deref(), Some("140 l"));
+        assert_eq!(contract.valid_from, NaiveDate::from_ymd_opt(2023, 1, 1));
+        assert_eq!(contract.valid_until, NaiveDate::from_ymd_opt(2024, 12, 31));
+        assert!(ContractTable(&[contract])
+            .to_string()
+            .contains("Biojäte 140 l"));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::error::{PjhoyError, Result};
+use crate::storage::Storage;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use cookie_store::{CookieDomain, CookieExpiration, CookieStore, RawCookie};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use std::sync::RwLock;
+use tracing::debug;
+
+/// File in the state directory holding the session cookies
+pub const COOKIES_FILE: &str = "cookies.json";
+
+/// Cookie file of older versions, only `name=value` pairs separated by `;`
+pub const LEGACY_COOKIES_FILE: &str = "cookies.txt";
+
+/// Prefix of exported sessions, versioning the format
+const SESSION_PREFIX: &str = "pjhoy-session-v1:";
+
+/// Cookie jar keeping every cookie attribute (domain, path, expiry, Secure,
+/// HttpOnly), shared with the HTTP client and persisted as JSON
+#[derive(Debug, Default)]
+pub struct CookieJar(RwLock<CookieStore>);
+
+impl CookieJar {
+    /// Load the saved cookies. Cookies of the older semicolon separated
+    /// file are taken over as session cookies of `base_url`.
+    pub fn load(storage: &dyn Storage, base_url: &str) -> Result<Self> {
+        if let Some(json) = storage.read(COOKIES_FILE)? {
+            let store = CookieStore::load_json(json.as_slice()).map_err(|e| {
+                PjhoyError::parse(
+                    format!("Invalid cookies file {}", storage.location(COOKIES_FILE)),
+                    e,
+                )
+            })?;
+            debug!(location = %storage.location(COOKIES_FILE), count = store.iter_unexpired().count(), "Loaded cookies");
+            return Ok(Self(RwLock::new(store)));
+        }
+
+        let jar = Self::default();
+        if let Some(legacy) = storage.read(LEGACY_COOKIES_FILE)? {
+            let url: Url = base_url
+                .parse()
+                .map_err(|e| PjhoyError::Config(format!("Invalid provider base URL: {}", e)))?;
+            for pair in String::from_utf8_lossy(&legacy)
+                .split(';')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+            {
+                jar.insert(pair, &url);
+            }
+            debug!(location = %storage.location(LEGACY_COOKIES_FILE), "Took over cookies of the older format");
+        } else {
+            debug!(location = %storage.location(COOKIES_FILE), "No saved cookies");
+        }
+        Ok(jar)
+    }
+
+    /// Save the unexpired cookies, including session cookies
+    pub fn save(&self, storage: &dyn Storage) -> Result<()> {
+        let content = self.to_json()?;
+        debug!(location = %storage.location(COOKIES_FILE), "Saving cookies");
+        storage.write(COOKIES_FILE, content.as_bytes())?;
+
+        // The older file would otherwise be taken over again after a logout
+        storage.remove(LEGACY_COOKIES_FILE)
+    }
+
+    /// The unexpired cookies as one JSON cookie per line, the format read by
+    /// `CookieStore::load_json`
+    fn to_json(&self) -> Result<String> {
+        let mut content = String::new();
+        let store = self.0.read().expect("cookie store lock poisoned");
+        for cookie in store.iter_unexpired() {
+            let json = serde_json::to_string(cookie)
+                .map_err(|e| PjhoyError::parse("Failed to serialize cookie", e))?;
+            content.push_str(&json);
+            content.push('\n');
+        }
+        Ok(content)
+    }
+
+    /// The whole session as a single line that can be copied to another
+    /// machine and given to [`CookieJar::import_session`]
+    pub fn export_session(&self) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            SESSION_PREFIX,
+            STANDARD.encode(self.to_json()?)
+        ))
+    }
+
+    /// Replace the cookies with those of a session exported with
+    /// [`CookieJar::export_session`]. Returns how many cookies were taken.
+    pub fn import_session(&self, session: &str) -> Result<usize> {
+        let encoded = session.trim().strip_prefix(SESSION_PREFIX).ok_or_else(|| {
+            PjhoyError::invalid("Not a session exported with `pjhoy session export`")
+        })?;
+        let json = STANDARD
+            .decode(encoded)
+            .map_err(|e| PjhoyError::parse("Invalid session, was it copied whole?", e))?;
+        let store = CookieStore::load_json(json.as_slice())
+            .map_err(|e| PjhoyError::parse("Invalid session cookies", e))?;
+
+        let count = store.iter_unexpired().count();
+        *self.0.write().expect("cookie store lock poisoned") = store;
+        Ok(count)
+    }
+
+    /// The unexpired cookies in the Netscape cookies.txt format read by
+    /// curl, wget and browser extensions
+    pub fn to_netscape(&self) -> String {
+        let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+
+        let mut output = String::from("# Netscape HTTP Cookie File\n# Written by pjhoy\n\n");
+        let store = self.0.read().expect("cookie store lock poisoned");
+        for cookie in store.iter_unexpired() {
+            let (domain, subdomains) = match &cookie.domain {
+                CookieDomain::HostOnly(host) => (host.clone(), false),
+                CookieDomain::Suffix(suffix) => (format!(".{}", suffix), true),
+                CookieDomain::NotPresent | CookieDomain::Empty => continue,
+            };
+            let expires = match &cookie.expires {
+                CookieExpiration::AtUtc(time) => time.unix_timestamp(),
+                CookieExpiration::SessionEnd => 0,
+            };
+            let http_only = if cookie.http_only().unwrap_or(false) {
+                "#HttpOnly_"
+            } else {
+                ""
+            };
+            output.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                http_only,
+                domain,
+                flag(subdomains),
+                &cookie.path[..],
+                flag(cookie.secure().unwrap_or(false)),
+                expires,
+                cookie.name(),
+                cookie.value(),
+            ));
+        }
+        output
+    }
+
+    /// Add the cookies of a Netscape cookies.txt file, as exported from a
+    /// browser, that belong to the host of `url`. Returns how many cookies
+    /// were added.
+    pub fn import_netscape(&self, text: &str, url: &Url) -> Result<usize> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| PjhoyError::Config(format!("URL without a host: {}", url)))?;
+        let mut store = self.0.write().expect("cookie store lock poisoned");
+        let mut count = 0;
+        for (number, line) in text.lines().enumerate() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+                return Err(PjhoyError::invalid(format!(
+                    "Line {}: expected 7 tab separated fields, found {}",
+                    number + 1,
+                    fields.len()
+                )));
+            };
+
+            let domain = domain.trim_start_matches('.');
+            let subdomains = subdomains.eq_ignore_ascii_case("TRUE");
+            let matches = host == domain || (subdomains && host.ends_with(&format!(".{}", domain)));
+            if !matches {
+                continue;
+            }
+
+            let expires: i64 = expires.parse().map_err(|e| {
+                PjhoyError::parse(
+                    format!("Line {}: invalid expiry {:?}", number + 1, expires),
+                    e,
+                )
+            })?;
+
+            let mut set_cookie = format!("{}={}; Path={}", name, value, path);
+            if subdomains {
+                set_cookie.push_str(&format!("; Domain={}", domain));
+            }
+            if secure.eq_ignore_ascii_case("TRUE") {
+                set_cookie.push_str("; Secure");
+            }
+            if http_only {
+                set_cookie.push_str("; HttpOnly");
+            }
+            // Zero marks a session cookie
+            if let Some(time) = chrono::DateTime::from_timestamp(expires, 0).filter(|_| expires > 0)
+            {
+                set_cookie.push_str(
+                    &time
+                        .format("; Expires=%a, %d %b %Y %H:%M:%S GMT")
+                        .to_string(),
+                );
+            }
+            let cookie = RawCookie::parse(set_cookie).map_err(|e| {
+                PjhoyError::parse(format!("Line {}: invalid cookie", number + 1), e)
+            })?;
+            if store.insert_raw(&cookie, url).is_ok() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Add a cookie given as a `Set-Cookie` value received from `url`
+    pub fn insert(&self, set_cookie: &str, url: &Url) {
+        if let Ok(cookie) = RawCookie::parse(set_cookie.to_string()) {
+            let mut store = self.0.write().expect("cookie store lock poisoned");
+            let _ = store.insert_raw(&cookie, url);
+        }
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers.filter_map(|value| {
+            std::str::from_utf8(value.as_bytes())
+                .ok()
+                .and_then(|text| RawCookie::parse(text.to_string()).ok())
+        });
+        let mut store = self.0.write().expect("cookie store lock poisoned");
+        store.store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let store = self.0.read().expect("cookie store lock poisoned");
+        let header = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&header).ok()
+        }
+    }
+}
+
+/// Delete the saved cookies, in both the current and the older format
+pub fn delete_saved(storage: &dyn Storage) -> Result<()> {
+    for name in [COOKIES_FILE, LEGACY_COOKIES_FILE] {
+        storage.remove(name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use anyhow::Result;
+    use reqwest::cookie::CookieStore as _;
+
+    #[test]
+    fn test_save_and_load_keep_attributes() -> Result<()> {
+        let storage = MemoryStorage::default();
+        let url: Url = "https://extranet.pjhoy.fi/pirkka/".parse()?;
+        let jar = CookieJar::default();
+        let headers = [
+            HeaderValue::from_static("JSESSIONID=abc; Path=/pirkka; Secure; HttpOnly"),
+            HeaderValue::from_static("old=1; Max-Age=0"),
+            HeaderValue::from_static("JSESSIONID=newer; Path=/pirkka; Secure; HttpOnly"),
+        ];
+        jar.set_cookies(&mut headers.iter(), &url);
+        jar.save(&storage)?;
+
+        let loaded = CookieJar::load(&storage, "https://extranet.pjhoy.fi/pirkka")?;
+        let header = loaded.cookies(&url).unwrap();
+        assert_eq!(header.to_str()?, "JSESSIONID=newer");
+
+        // Secure and Path are kept
+        assert!(loaded
+            .cookies(&"http://extranet.pjhoy.fi/pirkka/".parse()?)
+            .is_none());
+        assert!(loaded
+            .cookies(&"https://extranet.pjhoy.fi/other/".parse()?)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_netscape_export() -> Result<()> {
+        let url: Url = "https://extranet.pjhoy.fi/pirkka/".parse()?;
+        let jar = CookieJar::default();
+        jar.insert("JSESSIONID=abc; Path=/pirkka; Secure; HttpOnly", &url);
+        jar.insert(
+            "remember=1; Domain=pjhoy.fi; Path=/; Expires=Sat, 01 Jan 2000 00:00:00 GMT",
+            &url,
+        );
+        jar.insert(
+            "lang=fi; Domain=pjhoy.fi; Path=/; Expires=Fri, 01 Jan 2100 00:00:00 GMT",
+            &url,
+        );
+
+        let lines: Vec<String> = jar
+            .to_netscape()
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with("# "))
+            .map(str::to_string)
+            .collect();
+
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(lines.contains(
+            &"#HttpOnly_extranet.pjhoy.fi\tFALSE\t/pirkka\tTRUE\t0\tJSESSIONID\tabc".to_string()
+        ));
+        assert!(lines.contains(&".pjhoy.fi\tTRUE\t/\tFALSE\t4102444800\tlang\tfi".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_netscape_import() -> Result<()> {
+        let url: Url = "https://extranet.pjhoy.fi/pirkka".parse()?;
+        let text = "# Netscape HTTP Cookie File\n\
+            \n\
+            #HttpOnly_extranet.pjhoy.fi\tFALSE\t/pirkka\tTRUE\t0\tJSESSIONID\tabc\n\
+            .pjhoy.fi\tTRUE\t/\tFALSE\t4102444800\tlang\tfi\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tother\t1\n\
+            extranet.pjhoy.fi\tFALSE\t/\tFALSE\t946684800\texpired\t1\n";
+
+        let jar = CookieJar::default();
+        assert_eq!(jar.import_netscape(text, &url)?, 2);
+        let header = jar
+            .cookies(&"https://extranet.pjhoy.fi/pirkka/secure/x.do".parse()?)
+            .unwrap();
+        let mut cookies: Vec<&str> = header.to_str()?.split("; ").collect();
+        cookies.sort();
+        assert_eq!(cookies, ["JSESSIONID=abc", "lang=fi"]);
+
+        // Exporting gives the same lines back
+        assert!(jar
+            .to_netscape()
+            .contains("#HttpOnly_extranet.pjhoy.fi\tFALSE\t/pirkka\tTRUE\t0\tJSESSIONID\tabc\n"));
+
+        assert!(jar.import_netscape("pjhoy.fi\tTRUE\t/\n", &url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_export_and_import() -> Result<()> {
+        let url: Url = "https://extranet.pjhoy.fi/pirkka/".parse()?;
+        let jar = CookieJar::default();
+        jar.insert("JSESSIONID=abc; Path=/pirkka; Secure; HttpOnly", &url);
+        let session = jar.export_session()?;
+        assert!(session.starts_with(SESSION_PREFIX));
+        assert!(!session.contains('\n'));
+
+        let other = CookieJar::default();
+        other.insert("JSESSIONID=stale; Path=/pirkka", &url);
+        assert_eq!(other.import_session(&format!("{}\n", session))?, 1);
+        assert_eq!(other.cookies(&url).unwrap().to_str()?, "JSESSIONID=abc");
+        assert_eq!(other.to_netscape(), jar.to_netscape());
+
+        assert!(other.import_session("JSESSIONID=abc").is_err());
+        assert!(other.import_session("pjhoy-session-v1:%%%").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_takes_over_legacy_file() -> Result<()> {
+        let storage = MemoryStorage::default();
+        storage.write(LEGACY_COOKIES_FILE, b"JSESSIONID=abc; other=1")?;
+
+        let jar = CookieJar::load(&storage, "https://extranet.pjhoy.fi/pirkka")?;
+        let url = "https://extranet.pjhoy.fi/pirkka/secure/x.do".parse()?;
+        let header = jar.cookies(&url).unwrap();
+        let mut cookies: Vec<&str> = header.to_str()?.split("; ").collect();
+        cookies.sort();
+        assert_eq!(cookies, ["JSESSIONID=abc", "other=1"]);
+
+        jar.save(&storage)?;
+        assert_eq!(storage.read(LEGACY_COOKIES_FILE)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use crate::models::{TrashService, VatRates};
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Costs of one product group, customer number or the whole account
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct CostRow {
+    pub key: String,
+    pub pickups: usize,
+    /// Cost including VAT per month, keyed by `YYYY-MM`
+    pub monthly: BTreeMap<String, Decimal>,
+    pub total: Decimal,
+}
+
+impl CostRow {
+    fn add(&mut self, date: NaiveDate, cost: Decimal) {
+        self.pickups += 1;
+        *self
+            .monthly
+            .entry(format!("{}-{:02}", date.year(), date.month()))
+            .or_default() += cost;
+        self.total += cost;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CostReport {
+    pub from: NaiveDate,
+    pub until: NaiveDate,
+    pub by_group: Vec<CostRow>,
+    pub by_customer: Vec<CostRow>,
+    pub total: CostRow,
+}
+
+/// A row of a `CostReport` on its own, for JSON Lines
+#[derive(Debug, Serialize)]
+pub struct CostRecord<'a> {
+    /// `group`, `customer` or `total`
+    pub by: &'static str,
+    #[serde(flatten)]
+    pub row: &'a CostRow,
+}
+
+impl CostReport {
+    pub fn records(&self) -> impl Iterator<Item = CostRecord<'_>> {
+        let groups = self
+            .by_group
+            .iter()
+            .map(|row| CostRecord { by: "group", row });
+        let customers = self.by_customer.iter().map(|row| CostRecord {
+            by: "customer",
+            row,
+        });
+        groups.chain(customers).chain(std::iter::once(CostRecord {
+            by: "total",
+            row: &self.total,
+        }))
+    }
+}
+
+/// Pickup dates of a service between `from` (inclusive) and `until` (exclusive),
+/// assuming it keeps being emptied every `interval` weeks after `next_date`
+pub fn pickup_dates(service: &TrashService, from: NaiveDate, until: NaiveDate) -> Vec<NaiveDate> {
+    let Some(mut date) = service.next_date else {
+        return Vec::new();
+    };
+    let interval = service.interval_weeks();
+
+    let mut dates = Vec::new();
+    while date < until {
+        if date >= from {
+            dates.push(date);
+        }
+        let Some(weeks) = interval else {
+            break;
+        };
+        date += Duration::weeks(i64::from(weeks));
+    }
+    dates
+}
+
+fn group_name(service: &TrashService) -> String {
+    let group = service
+        .tariff
+        .as_ref()
+        .and_then(|tariff| tariff.product_group.as_ref());
+    match group {
+        Some(group) => group.finnish_name().unwrap_or(group.code()).to_string(),
+        None => "Muu".to_string(),
+    }
+}
+
+/// Sum the priced pickups of the next `months` months starting at `from`
+pub fn cost_report(
+    services: &[TrashService],
+    from: NaiveDate,
+    months: u32,
+    vat_rates: &VatRates,
+) -> CostReport {
+    let until = from + Months::new(months);
+    let mut by_group: BTreeMap<String, CostRow> = BTreeMap::new();
+    let mut by_customer: BTreeMap<String, CostRow> = BTreeMap::new();
+    let mut total = CostRow {
+        key: "Yhteensä".to_string(),
+        ..Default::default()
+    };
+
+    for service in services {
+        let Some(price) = service.price else {
+            continue;
+        };
+        for date in pickup_dates(service, from, until) {
+            let cost = vat_rates.gross_on(price, date);
+            let group = group_name(service);
+            by_group
+                .entry(group.clone())
+                .or_insert_with(|| CostRow {
+                    key: group,
+                    ..Default::default()
+                })
+                .add(date, cost);
+            by_customer
+                .entry(service.customer_number.clone())
+                .or_insert_with(|| CostRow {
+                    key: service.customer_number.clone(),
+                    ..Default::default()
+                })
+                .add(date, cost);
+            total.add(date, cost);
+        }
+    }
+
+    CostReport {
+        from,
+        until,
+        by_group: by_group.into_values().collect(),
+        by_customer: by_customer.into_values().collect(),
+        total,
+    }
+}
+
+/// Projected yearly cost of a single service
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ServiceForecast {
+    pub customer_number: String,
+    pub name: String,
+    pub interval_weeks: u32,
+    /// Price of one pickup including VAT
+    pub price: Decimal,
+    pub pickups_per_year: f64,
+    pub yearly_cost: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Forecast {
+    pub services: Vec<ServiceForecast>,
+    pub yearly_total: f64,
+}
+
+const WEEKS_PER_YEAR: f64 = 365.25 / 7.0;
+
+/// Project the yearly cost of each priced service with a regular interval,
+/// using the VAT rate in effect on `today`
+pub fn forecast(services: &[TrashService], today: NaiveDate, vat_rates: &VatRates) -> Forecast {
+    let services: Vec<ServiceForecast> = services
+        .iter()
+        .filter_map(|service| {
+            let price = vat_rates.gross_on(service.price?, today);
+            let interval_weeks = service.interval_weeks()?;
+            let pickups_per_year = WEEKS_PER_YEAR / interval_weeks as f64;
+
+            Some(ServiceForecast {
+                customer_number: service.customer_number.clone(),
+                name: service.name.clone(),
+                interval_weeks,
+                price,
+                pickups_per_year,
+                yearly_cost: price.to_f64().unwrap_or_default() * pickups_per_year,
+            })
+        })
+        .collect();
+    let yearly_total = services.iter().map(|s| s.yearly_cost).sum();
+
+    Forecast {
+        services,
+        yearly_total,
+    }
+}
+
+impl fmt::Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>9} {:>10} {:>9} {:>12}",
+            "Service", "Interval", "Price", "Per year", "Yearly cost"
+        )?;
+        for service in &self.services {
+            writeln!(
+                f,
+                "{:<32} {:>7} w {:>8.2} € {:>9.1} {:>10.2} €",
+                format!("{} ({})", service.name, service.customer_number),
+                service.interval_weeks,
+                service.price,
+                service.pickups_per_year,
+                service.yearly_cost
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<32} {:>9} {:>10} {:>9} {:>10.2} €",
+            "Yhteensä", "", "", "", self.yearly_total
+        )
+    }
+}
+
+fn write_rows(f: &mut fmt::Formatter<'_>, title: &str, rows: &[CostRow]) -> fmt::Result {
+    writeln!(
+        f,
+        "{:<20} {:>8} {:>12} {:>12}",
+        title, "Pickups", "Per month", "Total"
+    )?;
+    for row in rows {
+        let per_month = row.total / Decimal::from(row.monthly.len().max(1));
+        writeln!(
+            f,
+            "{:<20} {:>8} {:>10.2} € {:>10.2} €",
+            row.key, row.pickups, per_month, row.total
+        )?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for CostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Costs including VAT {} – {}",
+            self.from,
+            self.until - Duration::days(1)
+        )?;
+        writeln!(f)?;
+        write_rows(f, "Product group", &self.by_group)?;
+        writeln!(f)?;
+        write_rows(f, "Customer number", &self.by_customer)?;
+        writeln!(f)?;
+        writeln!(f, "{:<20} {:>8} {:>12}", "Month", "Pickups", "Total")?;
+        for (month, cost) in &self.total.monthly {
+            writeln!(f, "{:<20} {:>8} {:>10.2} €", month, "", cost)?;
+        }
+        writeln!(
+            f,
+            "{:<20} {:>8} {:>10.2} €",
+            self.total.key, self.total.pickups, self.total.total
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(customer: &str, group: &str, next_date: &str, interval: &str) -> TrashService {
+        TrashService::builder(&format!("{} astia", group), next_date)
+            .customer(customer)
+            .price(Decimal::TEN)
+            .interval(interval)
+            .group(group)
+            .build()
+    }
+
+    #[test]
+    fn test_pickup_dates() {
+        let from = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let until = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+
+        let dates = pickup_dates(&service("01", "BIO", "2024-03-05", "2"), from, until);
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 3, 19).unwrap(),
+            ]
+        );
+
+        let dates = pickup_dates(&service("01", "BIO", "2024-03-05", "?"), from, until);
+        assert_eq!(dates.len(), 1);
+    }
+
+    #[test]
+    fn test_cost_report() {
+        let services = vec![
+            service("02-2891001-01", "BIO", "2024-03-05", "2"),
+            service("02-2891001-02", "SEK", "2024-03-12", "4"),
+        ];
+        let from = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        let report = cost_report(&services, from, 2, &VatRates::flat(25.5));
+
+        assert_eq!(report.by_group.len(), 2);
+        assert_eq!(report.by_group[0].key, "Biojäte");
+        assert_eq!(report.by_group[0].pickups, 5);
+        assert_eq!(report.by_customer[1].key, "02-2891001-02");
+        assert_eq!(report.by_customer[1].pickups, 2);
+        assert_eq!(report.total.pickups, 7);
+        assert_eq!(report.total.total, Decimal::new(7 * 1255, 2));
+        assert_eq!(report.total.monthly.len(), 2);
+        let by: Vec<&str> = report.records().map(|record| record.by).collect();
+        assert_eq!(by, ["group", "group", "customer", "customer", "total"]);
+    }
+
+    #[test]
+    fn test_forecast() {
+        let services = vec![
+            service("02-2891001-01", "BIO", "2024-03-05", "2"),
+            service("02-2891001-02", "SEK", "2024-03-12", "4"),
+            service("02-2891001-03", "VU", "2024-03-12", ""),
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        let forecast = forecast(&services, today, &VatRates::flat(25.5));
+
+        assert_eq!(forecast.services.len(), 2);
+        let bio = &forecast.services[0];
+        assert!((bio.pickups_per_year - 26.089).abs() < 0.001);
+        assert_eq!(bio.price, Decimal::new(1255, 2));
+        assert!((bio.yearly_cost - 12.55 * bio.pickups_per_year).abs() < 1e-9);
+        let expected_total = 12.55 * WEEKS_PER_YEAR * (1.0 / 2.0 + 1.0 / 4.0);
+        assert!((forecast.yearly_total - expected_total).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::config::Credentials;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Fetch every morning unless configured otherwise
+pub const DEFAULT_FETCH_SCHEDULE: &str = "0 0 6 * * *";
+/// Remind every evening of the next day's pickups unless configured otherwise
+pub const DEFAULT_NOTIFY_SCHEDULE: &str = "0 0 19 * * *";
+
+/// Work the daemon does on its schedules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Job {
+    /// Fetch the services, regenerate the calendar and save the services
+    Fetch,
+    /// Send reminders of tomorrow's pickups
+    Notify,
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Job::Fetch => write!(f, "fetch"),
+            Job::Notify => write!(f, "notify"),
+        }
+    }
+}
+
+/// Cron expression with seconds: `sec min hour day month weekday`
+pub fn parse_schedule(expression: &str) -> Result<Schedule, cron::error::Error> {
+    Schedule::from_str(expression)
+}
+
+/// When each job runs
+#[derive(Debug)]
+pub struct Schedules {
+    fetch: Schedule,
+    /// Only when a notifier is configured
+    notify: Option<Schedule>,
+}
+
+/// Address of `[daemon] listen` to serve metrics and health on, if set
+pub fn listen_address(config: &Credentials) -> Result<Option<SocketAddr>> {
+    let Some(listen) = config
+        .daemon
+        .as_ref()
+        .and_then(|daemon| daemon.listen.as_deref())
+    else {
+        return Ok(None);
+    };
+    let addr = listen
+        .parse()
+        .with_context(|| format!("Invalid daemon.listen {:?}", listen))?;
+    Ok(Some(addr))
+}
+
+impl Schedules {
+    pub fn from_config(config: &Credentials) -> Result<Self> {
+        let daemon = config.daemon.clone().unwrap_or_default();
+        let fetch = daemon.fetch.as_deref().unwrap_or(DEFAULT_FETCH_SCHEDULE);
+        let fetch =
+            parse_schedule(fetch).with_context(|| format!("Invalid daemon.fetch {:?}", fetch))?;
+
+        let notify = if config.ntfy.is_some() || config.email.is_some() || config.slack.is_some() {
+            let notify = daemon.notify.as_deref().unwrap_or(DEFAULT_NOTIFY_SCHEDULE);
+            Some(
+                parse_schedule(notify)
+                    .with_context(|| format!("Invalid daemon.notify {:?}", notify))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self { fetch, notify })
+    }
+
+    pub fn fetch(&self) -> &Schedule {
+        &self.fetch
+    }
+
+    /// Each enabled job with its schedule
+    pub fn jobs(&self) -> Vec<(Job, &Schedule)> {
+        let mut jobs = vec![(Job::Fetch, &self.fetch)];
+        jobs.extend(self.notify.iter().map(|schedule| (Job::Notify, schedule)));
+        jobs
+    }
+
+    /// The first job due after `now`. A fetch goes first when both are due
+    /// at the same time, so that reminders use fresh data.
+    pub fn next_job(&self, now: DateTime<Local>) -> Option<(DateTime<Local>, Job)> {
+        let fetch = self.fetch.after(&now).next().map(|when| (when, Job::Fetch));
+        let notify = self
+            .notify
+            .as_ref()
+            .and_then(|schedule| schedule.after(&now).next())
+            .map(|when| (when, Job::Notify));
+
+        match (fetch, notify) {
+            (Some(fetch), Some(notify)) if notify.0 < fetch.0 => Some(notify),
+            (Some(fetch), _) => Some(fetch),
+            (None, notify) => notify,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config(toml: &str) -> Credentials {
+        let toml = format!("username = \"02-2891001-00\"\npassword = \"x\"\n{}", toml);
+        ::config::Config::builder()
+            .add_source(::config::File::from_str(&toml, ::config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_job() {
+        let evening = Local.with_ymd_and_hms(2024, 3, 4, 18, 30, 0).unwrap();
+        let night = Local.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap();
+
+        let schedules = Schedules::from_config(&config("")).unwrap();
+        assert_eq!(
+            schedules.next_job(evening),
+            Some((
+                Local.with_ymd_and_hms(2024, 3, 5, 6, 0, 0).unwrap(),
+                Job::Fetch
+            ))
+        );
+
+        let schedules = Schedules::from_config(&config(
+            "[ntfy]\ntopic = \"jate\"\n[daemon]\nnotify = \"0 30 20 * * *\"",
+        ))
+        .unwrap();
+        assert_eq!(
+            schedules.next_job(evening),
+            Some((
+                Local.with_ymd_and_hms(2024, 3, 4, 20, 30, 0).unwrap(),
+                Job::Notify
+            ))
+        );
+        assert_eq!(
+            schedules.next_job(night).map(|(_, job)| job),
+            Some(Job::Fetch)
+        );
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        assert!(Schedules::from_config(&config("[daemon]\nfetch = \"daily\"")).is_err());
+    }
+}
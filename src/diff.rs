@@ -0,0 +1,275 @@
+use crate::models::{ProductGroup, TrashService};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Identifies a service across fetches
+type ServiceKey<'a> = (&'a str, i32);
+
+fn service_key(service: &TrashService) -> ServiceKey<'_> {
+    (&service.customer_number, service.position)
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ServiceRef {
+    pub customer_number: String,
+    pub position: i32,
+    pub name: String,
+    pub product_group: Option<ProductGroup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+impl From<&TrashService> for ServiceRef {
+    fn from(service: &TrashService) -> Self {
+        Self {
+            customer_number: service.customer_number.clone(),
+            position: service.position,
+            name: service.name.clone(),
+            product_group: service
+                .tariff
+                .as_ref()
+                .and_then(|tariff| tariff.product_group.clone()),
+            address: service.address.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Change<T> {
+    pub service: ServiceRef,
+    pub old: Option<T>,
+    pub new: Option<T>,
+}
+
+impl Change<Decimal> {
+    /// Relative price change in percent, `None` unless both prices are known
+    pub fn percent(&self) -> Option<Decimal> {
+        let (old, new) = (self.old?, self.new?);
+        if old.is_zero() {
+            return None;
+        }
+        Some(((new - old) / old * Decimal::ONE_HUNDRED).round_dp(1))
+    }
+}
+
+/// Differences between two fetched service lists
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct ScheduleDiff {
+    pub added: Vec<ServiceRef>,
+    pub removed: Vec<ServiceRef>,
+    pub date_changes: Vec<Change<NaiveDate>>,
+    pub price_changes: Vec<Change<Decimal>>,
+}
+
+/// A single change of a `ScheduleDiff`, tagged with its kind
+#[derive(Debug, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum DiffRecord<'a> {
+    Added(&'a ServiceRef),
+    Removed(&'a ServiceRef),
+    Date(&'a Change<NaiveDate>),
+    Price(&'a Change<Decimal>),
+}
+
+impl ScheduleDiff {
+    /// Every change on its own, for JSON Lines
+    pub fn records(&self) -> impl Iterator<Item = DiffRecord<'_>> {
+        let added = self.added.iter().map(DiffRecord::Added);
+        let removed = self.removed.iter().map(DiffRecord::Removed);
+        let dates = self.date_changes.iter().map(DiffRecord::Date);
+        let prices = self.price_changes.iter().map(DiffRecord::Price);
+        added.chain(removed).chain(dates).chain(prices)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.date_changes.is_empty()
+            && self.price_changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.date_changes.len() + self.price_changes.len()
+    }
+
+    /// Product groups of the services with any change, in order
+    pub fn product_groups(&self) -> BTreeSet<&ProductGroup> {
+        let date_services = self.date_changes.iter().map(|change| &change.service);
+        let price_services = self.price_changes.iter().map(|change| &change.service);
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .chain(date_services)
+            .chain(price_services)
+            .filter_map(|service| service.product_group.as_ref())
+            .collect()
+    }
+}
+
+impl fmt::Display for ServiceRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Some(address) => write!(f, "{} ({}, {})", self.name, self.customer_number, address),
+            None => write!(f, "{} ({})", self.name, self.customer_number),
+        }
+    }
+}
+
+fn display_or_none<T: fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+impl fmt::Display for ScheduleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for service in &self.added {
+            writeln!(f, "Added: {}", service)?;
+        }
+        for service in &self.removed {
+            writeln!(f, "Removed: {}", service)?;
+        }
+        for change in &self.date_changes {
+            writeln!(
+                f,
+                "Date moved: {}: {} -> {}",
+                change.service,
+                display_or_none(&change.old),
+                display_or_none(&change.new)
+            )?;
+        }
+        for change in &self.price_changes {
+            writeln!(f, "Price changed: {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change<Decimal> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.service,
+            display_or_none(&self.old.map(|p| format!("{:.2} €", p))),
+            display_or_none(&self.new.map(|p| format!("{:.2} €", p)))
+        )?;
+        match self.percent() {
+            Some(percent) => write!(f, " ({:+} %)", percent),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Compare services of two fetches, matching them by customer number and position
+pub fn diff_services(old: &[TrashService], new: &[TrashService]) -> ScheduleDiff {
+    let old_by_key: BTreeMap<ServiceKey, &TrashService> =
+        old.iter().map(|s| (service_key(s), s)).collect();
+    let new_by_key: BTreeMap<ServiceKey, &TrashService> =
+        new.iter().map(|s| (service_key(s), s)).collect();
+
+    let mut diff = ScheduleDiff::default();
+
+    for (key, new_service) in &new_by_key {
+        let Some(old_service) = old_by_key.get(key) else {
+            diff.added.push(ServiceRef::from(*new_service));
+            continue;
+        };
+
+        if old_service.next_date != new_service.next_date {
+            diff.date_changes.push(Change {
+                service: ServiceRef::from(*new_service),
+                old: old_service.next_date,
+                new: new_service.next_date,
+            });
+        }
+
+        if old_service.price != new_service.price {
+            diff.price_changes.push(Change {
+                service: ServiceRef::from(*new_service),
+                old: old_service.price,
+                new: new_service.price,
+            });
+        }
+    }
+
+    for (key, old_service) in &old_by_key {
+        if !new_by_key.contains_key(key) {
+            diff.removed.push(ServiceRef::from(*old_service));
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(pos: i32, next_date: &str, price: &str) -> TrashService {
+        TrashService::builder(&format!("Service {}", pos), next_date)
+            .customer("02-2891001-01")
+            .position(pos)
+            .price(price.parse().unwrap())
+            .build()
+    }
+
+    #[test]
+    fn test_diff_services() {
+        let old = vec![
+            service(1, "2024-03-05", "10.0"),
+            service(2, "2024-03-06", "12.0"),
+            service(3, "2024-03-07", "8.0"),
+        ];
+        let new = vec![
+            service(1, "2024-03-05", "10.0"),
+            service(2, "2024-03-07", "12.5"),
+            service(4, "2024-03-08", "5.0"),
+        ];
+
+        let diff = diff_services(&old, &new);
+
+        assert_eq!(diff.len(), 4);
+        assert_eq!(diff.added[0].position, 4);
+        assert_eq!(diff.removed[0].position, 3);
+        assert_eq!(
+            diff.date_changes[0].old,
+            NaiveDate::from_ymd_opt(2024, 3, 6)
+        );
+        assert_eq!(
+            diff.date_changes[0].new,
+            NaiveDate::from_ymd_opt(2024, 3, 7)
+        );
+        assert_eq!(diff.price_changes[0].new, Some(Decimal::new(125, 1)));
+
+        let text = diff.to_string();
+        assert!(text.contains("Added: Service 4 (02-2891001-01)"));
+        assert!(text.contains("Date moved: Service 2 (02-2891001-01): 2024-03-06 -> 2024-03-07"));
+        assert!(
+            text.contains("Price changed: Service 2 (02-2891001-01): 12.00 € -> 12.50 € (+4.2 %)")
+        );
+
+        let records: Vec<serde_json::Value> = diff
+            .records()
+            .map(|record| serde_json::to_value(record).unwrap())
+            .collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0]["change"], "added");
+        assert_eq!(records[0]["position"], 4);
+        assert_eq!(records[2]["change"], "date");
+        assert_eq!(records[2]["new"], "2024-03-07");
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let services = vec![service(1, "2024-03-05", "10.0")];
+        assert!(diff_services(&services, &services).is_empty());
+    }
+}
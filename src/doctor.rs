@@ -0,0 +1,184 @@
+use crate::client::{ExtranetProvider, PjhoyClientBuilder, Provider};
+use crate::config::{load_config, Credentials};
+use crate::cookies::{CookieJar, COOKIES_FILE};
+use crate::i18n::tr;
+use crate::storage::FileStorage;
+use reqwest::Url;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Outcome of a single diagnostic check
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: String, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: String, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.passed { " OK " } else { "FAIL" };
+        write!(f, "[{}] {}", mark, self.name)?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that the config file, or the environment, gives a valid config
+fn check_config(config_dir: &Path) -> (Check, Option<Credentials>) {
+    let name = tr!("doctor-config");
+    let path = ["config.toml", "config.toml.age"]
+        .iter()
+        .map(|name| config_dir.join(name))
+        .find(|path| path.exists());
+    match (load_config(config_dir, None), path) {
+        (Ok(config), Some(path)) => (Check::pass(name, path.display().to_string()), Some(config)),
+        (Ok(config), None) => (Check::pass(name, tr!("doctor-config-env")), Some(config)),
+        (Err(e), _) => (Check::fail(name, format!("{:#}", e)), None),
+    }
+}
+
+/// Report whether saved session cookies exist and when they were written
+fn check_cookies(state_dir: &Path) -> Check {
+    let name = tr!("doctor-cookies");
+    let path = state_dir.join(COOKIES_FILE);
+    let Ok(metadata) = std::fs::metadata(&path) else {
+        return Check::fail(name, tr!("doctor-no-cookies"));
+    };
+    if metadata.len() == 0 {
+        return Check::fail(name, tr!("doctor-no-cookies"));
+    }
+    let saved_at = metadata
+        .modified()
+        .map(|time| {
+            chrono::DateTime::<chrono::Local>::from(time)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    Check::pass(name, tr!("cookies-saved-at", saved_at))
+}
+
+/// Resolve the extranet host and open a TCP connection to it
+async fn check_reachable(url: &Url) -> Check {
+    let name = tr!("doctor-network");
+    let Some(host) = url.host_str() else {
+        return Check::fail(name, url.to_string());
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    let address = format!("{}:{}", host, port);
+
+    let connect = tokio::net::TcpStream::connect(&address);
+    match tokio::time::timeout(Duration::from_secs(10), connect).await {
+        Ok(Ok(_)) => Check::pass(name, address),
+        Ok(Err(e)) => Check::fail(name, format!("{}: {}", address, e)),
+        Err(_) => Check::fail(name, tr!("doctor-timeout", address)),
+    }
+}
+
+/// Request the extranet root over HTTPS, which includes the TLS handshake
+async fn check_https(client: &reqwest::Client, url: &Url) -> Check {
+    let name = tr!("doctor-https");
+    match client.get(url.clone()).send().await {
+        Ok(response) => Check::pass(name, format!("{} {}", url, response.status())),
+        Err(e) => Check::fail(name, format!("{:#}", anyhow::Error::new(e))),
+    }
+}
+
+/// Check that a file can be created in `dir`
+fn check_writable(dir: &Path) -> Check {
+    let name = tr!("doctor-writable", dir.display());
+    let probe = dir.join(".pjhoy-doctor");
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            Check::pass(name, "")
+        }
+        Err(e) => Check::fail(name, e.to_string()),
+    }
+}
+
+/// Run every check. Network checks use the configured provider and HTTP
+/// settings when the config is valid, PJHOY defaults otherwise.
+pub async fn diagnose(config_dir: &Path, state_dir: &Path, output_path: &Path) -> Vec<Check> {
+    let (config_check, config) = check_config(config_dir);
+    let mut checks = vec![config_check, check_cookies(state_dir)];
+
+    let mut provider: Box<dyn Provider> = Box::new(ExtranetProvider::pjhoy());
+    let mut client = reqwest::Client::new();
+    if let Some(config) = config {
+        // A broken cookies file is reported above and must not hide the network checks
+        let pjhoy = PjhoyClientBuilder::from_config(config, Arc::new(FileStorage::new(state_dir)))
+            .and_then(|builder| builder.cookie_jar(Arc::new(CookieJar::default())).build());
+        match pjhoy {
+            Ok(pjhoy) => {
+                provider = pjhoy.provider;
+                client = pjhoy.client;
+            }
+            Err(e) => checks.push(Check::fail(tr!("doctor-http"), format!("{:#}", e))),
+        }
+    }
+
+    match Url::parse(provider.base_url()) {
+        Ok(url) => {
+            let reachable = check_reachable(&url).await;
+            let passed = reachable.passed;
+            checks.push(reachable);
+            if passed {
+                checks.push(check_https(&client, &url).await);
+            }
+        }
+        Err(e) => checks.push(Check::fail(tr!("doctor-network"), e.to_string())),
+    }
+
+    checks.push(check_writable(state_dir));
+    let output_dir = output_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if output_dir != state_dir {
+        checks.push(check_writable(output_dir));
+    }
+
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_config_and_cookies() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let (config_check, config) = check_config(dir);
+        assert!(!config_check.passed);
+        assert!(config.is_none());
+        assert!(!check_cookies(dir).passed);
+        assert!(check_writable(dir).passed);
+        assert!(config_check.to_string().starts_with("[FAIL]"));
+    }
+}
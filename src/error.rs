@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+/// Errors of the extranet client, for callers that need to tell failures
+/// apart without digging through an error chain
+#[derive(Debug, thiserror::Error)]
+pub enum PjhoyError {
+    /// The extranet rejected the login, either with an error status or by
+    /// answering with its login page again
+    #[error("Login failed: {0}")]
+    AuthFailed(String),
+
+    /// The saved session is no longer accepted and a new login is needed
+    #[error("Session expired")]
+    SessionExpired,
+
+    /// The request could not be sent or the extranet answered with an error status
+    #[error("{context}")]
+    Http {
+        context: String,
+        #[source]
+        source: Option<reqwest::Error>,
+    },
+
+    /// A response, cookie file or session was not in the expected format
+    #[error("{context}")]
+    Parse {
+        context: String,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+
+    /// The client was configured with values it cannot use
+    #[error("{0}")]
+    Config(String),
+
+    /// A file in the data directory could not be read or written
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, PjhoyError>;
+
+impl PjhoyError {
+    pub fn http(context: impl Display, source: reqwest::Error) -> Self {
+        Self::Http {
+            context: context.to_string(),
+            source: Some(source),
+        }
+    }
+
+    pub fn parse(
+        context: impl Display,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self::Parse {
+            context: context.to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// A format error without an underlying cause
+    pub fn invalid(context: impl Display) -> Self {
+        Self::Parse {
+            context: context.to_string(),
+            source: None,
+        }
+    }
+
+    pub fn io(context: impl Display, source: std::io::Error) -> Self {
+        Self::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::InvalidConfig;
+use crate::error::PjhoyError;
+use crate::lock::AlreadyRunning;
+
+/// Everything went fine and nothing changed
+pub const SUCCESS: u8 = 0;
+/// Any error not covered by a more specific code
+pub const FAILURE: u8 = 1;
+/// `is-pickup`, `today` or `tomorrow` found no pickup, like grep finding
+/// no match
+pub const NO_PICKUP: u8 = 1;
+/// Login was rejected or the session could not be renewed
+pub const AUTH_FAILURE: u8 = 2;
+/// The extranet could not be reached or the request failed
+pub const NETWORK_FAILURE: u8 = 3;
+/// The configuration file is missing or invalid
+pub const CONFIG_ERROR: u8 = 4;
+/// Fetch succeeded and the schedule differs from the previous fetch
+pub const SCHEDULE_CHANGED: u8 = 10;
+/// Invalid command line arguments (EX_USAGE)
+pub const USAGE: u8 = 64;
+/// Another instance is running, try again later (EX_TEMPFAIL)
+pub const LOCKED: u8 = 75;
+
+/// Exit code for a failed run, decided by the first recognized cause
+pub fn from_error(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        match cause.downcast_ref::<PjhoyError>() {
+            Some(PjhoyError::AuthFailed(_) | PjhoyError::SessionExpired) => return AUTH_FAILURE,
+            Some(PjhoyError::Http { .. }) => return NETWORK_FAILURE,
+            Some(PjhoyError::Config(_)) => return CONFIG_ERROR,
+            _ => {}
+        }
+        if cause.is::<reqwest::Error>() {
+            return NETWORK_FAILURE;
+        }
+        if cause.is::<AlreadyRunning>() {
+            return LOCKED;
+        }
+        if cause.is::<InvalidConfig>() || cause.is::<::config::ConfigError>() {
+            return CONFIG_ERROR;
+        }
+    }
+    FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_from_error() {
+        let err =
+            anyhow::Error::new(PjhoyError::SessionExpired).context("Request failed after login");
+        assert_eq!(from_error(&err), AUTH_FAILURE);
+
+        let err = anyhow::Error::new(PjhoyError::AuthFailed("401 Unauthorized".to_string()));
+        assert_eq!(from_error(&err), AUTH_FAILURE);
+
+        let err = anyhow::Error::new(PjhoyError::Http {
+            context: "Failed to fetch invoices: 503 Service Unavailable".to_string(),
+            source: None,
+        });
+        assert_eq!(from_error(&err), NETWORK_FAILURE);
+
+        let err = anyhow::Error::new(::config::ConfigError::NotFound("username".to_string()));
+        assert_eq!(from_error(&err), CONFIG_ERROR);
+
+        let err = anyhow::Error::new(AlreadyRunning {
+            path: "pjhoy.lock".into(),
+            pid: None,
+        });
+        assert_eq!(from_error(&err), LOCKED);
+
+        let err: anyhow::Result<()> = Err(anyhow::anyhow!("boom")).context("Failed");
+        assert_eq!(from_error(&err.unwrap_err()), FAILURE);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::calendar::{calendar_name, feed_items, EventOptions, FeedItem};
+use crate::models::TrashService;
+use crate::service::xml_escape;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Output of `pjhoy export`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FeedFormat {
+    /// Atom 1.0
+    Atom,
+    /// RSS 2.0
+    Rss,
+    /// JSON Feed 1.1
+    Json,
+}
+
+impl FeedFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+/// JSON Feed document, see https://www.jsonfeed.org/version/1.1/
+#[derive(Debug, Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home_page_url: Option<&'a str>,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    title: String,
+    content_text: &'a str,
+    date_modified: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<&'a str>,
+    /// The pickup date as such, for scripts that do not parse titles
+    #[serde(rename = "_pjhoy")]
+    pjhoy: PickupExtension,
+}
+
+/// Custom extension object of an item
+#[derive(Debug, Serialize)]
+struct PickupExtension {
+    date: NaiveDate,
+}
+
+/// Title, link and the time of the data of a feed
+#[derive(Debug)]
+struct FeedInfo {
+    title: String,
+    /// Link to the extranet
+    link: Option<String>,
+    /// When the services were fetched
+    updated: DateTime<Utc>,
+}
+
+/// Entry title with the date, which feed readers do not show otherwise
+fn entry_title(item: &FeedItem) -> String {
+    format!("{} {}", item.date.format("%d.%m.%Y"), item.title)
+}
+
+/// Feed of the pickups from `today` on, named like the calendar.
+/// `updated` is when the services were fetched.
+pub fn generate(
+    format: FeedFormat,
+    services: &[TrashService],
+    options: &EventOptions,
+    updated: DateTime<Utc>,
+    today: NaiveDate,
+) -> String {
+    let info = FeedInfo {
+        title: calendar_name(options),
+        link: options.url.clone(),
+        updated,
+    };
+    render(format, &info, &feed_items(services, options, today))
+}
+
+fn render(format: FeedFormat, info: &FeedInfo, items: &[FeedItem]) -> String {
+    match format {
+        FeedFormat::Atom => atom(info, items),
+        FeedFormat::Rss => rss(info, items),
+        FeedFormat::Json => json(info, items),
+    }
+}
+
+fn atom(info: &FeedInfo, items: &[FeedItem]) -> String {
+    let updated = info.updated.format("%Y-%m-%dT%H:%M:%SZ");
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "  <id>urn:pjhoy:pickups</id>");
+    let _ = writeln!(xml, "  <title>{}</title>", xml_escape(&info.title));
+    let _ = writeln!(xml, "  <updated>{}</updated>", updated);
+    let _ = writeln!(xml, "  <author><name>pjhoy</name></author>");
+    if let Some(link) = &info.link {
+        let _ = writeln!(xml, "  <link href=\"{}\"/>", xml_escape(link));
+    }
+    for item in items {
+        xml.push_str("  <entry>\n");
+        let _ = writeln!(xml, "    <id>urn:pjhoy:{}</id>", xml_escape(&item.id));
+        let _ = writeln!(xml, "    <title>{}</title>", xml_escape(&entry_title(item)));
+        let _ = writeln!(xml, "    <updated>{}</updated>", updated);
+        if let Some(category) = &item.category {
+            let _ = writeln!(xml, "    <category term=\"{}\"/>", xml_escape(category));
+        }
+        let _ = writeln!(
+            xml,
+            "    <content type=\"text\">{}</content>",
+            xml_escape(&item.content)
+        );
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rss(info: &FeedInfo, items: &[FeedItem]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n<channel>\n");
+    let _ = writeln!(xml, "  <title>{}</title>", xml_escape(&info.title));
+    let _ = writeln!(
+        xml,
+        "  <link>{}</link>",
+        xml_escape(info.link.as_deref().unwrap_or_default())
+    );
+    let _ = writeln!(
+        xml,
+        "  <description>{}</description>",
+        xml_escape(&info.title)
+    );
+    let _ = writeln!(
+        xml,
+        "  <lastBuildDate>{}</lastBuildDate>",
+        info.updated.to_rfc2822()
+    );
+    for item in items {
+        xml.push_str("  <item>\n");
+        let _ = writeln!(
+            xml,
+            "    <guid isPermaLink=\"false\">urn:pjhoy:{}</guid>",
+            xml_escape(&item.id)
+        );
+        let _ = writeln!(xml, "    <title>{}</title>", xml_escape(&entry_title(item)));
+        if let Some(category) = &item.category {
+            let _ = writeln!(xml, "    <category>{}</category>", xml_escape(category));
+        }
+        let _ = writeln!(
+            xml,
+            "    <description>{}</description>",
+            xml_escape(&item.content)
+        );
+        xml.push_str("  </item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn json(info: &FeedInfo, items: &[FeedItem]) -> String {
+    let updated = info.updated.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &info.title,
+        home_page_url: info.link.as_deref(),
+        items: items
+            .iter()
+            .map(|item| JsonFeedItem {
+                id: &item.id,
+                title: entry_title(item),
+                content_text: &item.content,
+                date_modified: updated.clone(),
+                tags: item.category.as_deref().into_iter().collect(),
+                pjhoy: PickupExtension { date: item.date },
+            })
+            .collect(),
+    };
+    let mut json = serde_json::to_string_pretty(&feed).expect("feed serializes");
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let info = FeedInfo {
+            title: "Jätekalenteri".to_string(),
+            link: Some("https://extranet.example/?a=1&b=2".to_string()),
+            updated: DateTime::from_timestamp(1_709_539_200, 0).unwrap(),
+        };
+        let items = vec![FeedItem {
+            id: "pjhoy_02-2891001-01_1_1_2024-03-06".to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 3, 6).unwrap(),
+            title: "🍃 Biojäte".to_string(),
+            content: "Biojäte <140 l>".to_string(),
+            category: Some("Biojäte".to_string()),
+        }];
+
+        let xml = render(FeedFormat::Atom, &info, &items);
+        assert!(xml.contains("<link href=\"https://extranet.example/?a=1&amp;b=2\"/>"));
+        assert!(xml.contains("<updated>2024-03-04T08:00:00Z</updated>"));
+        assert!(xml.contains(
+            "  <entry>\n    <id>urn:pjhoy:pjhoy_02-2891001-01_1_1_2024-03-06</id>\n    <title>06.03.2024 🍃 Biojäte</title>\n"
+        ));
+        assert!(xml.contains("<content type=\"text\">Biojäte &lt;140 l&gt;</content>"));
+
+        let xml = render(FeedFormat::Rss, &info, &items);
+        assert!(xml.contains("<lastBuildDate>Mon, 4 Mar 2024 08:00:00 +0000</lastBuildDate>"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(FeedFormat::Json, &info, &items)).unwrap();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(json["items"][0]["title"], "06.03.2024 🍃 Biojäte");
+        assert_eq!(json["items"][0]["tags"], serde_json::json!(["Biojäte"]));
+        assert_eq!(json["items"][0]["_pjhoy"]["date"], "2024-03-06");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::metrics::{Metrics, SessionState};
+use chrono::{DateTime, Duration, Local, Utc};
+use cron::Schedule;
+use serde::Serialize;
+
+/// Time a scheduled fetch may take, including a retry, before the saved
+/// data counts as stale
+const FETCH_GRACE_MINUTES: i64 = 60;
+
+/// Answer of `/healthz`
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Health {
+    pub healthy: bool,
+    pub session: SessionState,
+    pub last_fetch: Option<DateTime<Utc>>,
+    /// A scheduled fetch is overdue, or nothing has been fetched yet
+    pub stale: bool,
+}
+
+/// Unhealthy when the session has expired or the data is stale, so that a
+/// restart or an alert is due. An unknown session, e.g. when the extranet
+/// could not be reached, does not count.
+pub fn check(metrics: &Metrics, fetch_schedule: &Schedule, now: DateTime<Utc>) -> Health {
+    let session = metrics.session();
+    let last_fetch = metrics.last_fetch();
+    let stale = match last_fetch {
+        Some(fetched) => fetch_schedule
+            .after(&fetched.with_timezone(&Local))
+            .next()
+            .is_some_and(|due| due + Duration::minutes(FETCH_GRACE_MINUTES) < now),
+        None => true,
+    };
+    Health {
+        healthy: session != SessionState::Expired && !stale,
+        session,
+        last_fetch,
+        stale,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::parse_schedule;
+
+    #[test]
+    fn test_check() {
+        // Hourly, so that the local timezone does not matter
+        let schedule = parse_schedule("0 0 * * * *").unwrap();
+        let metrics = Metrics::default();
+        let now = DateTime::parse_from_rfc3339("2024-03-04T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(check(&metrics, &schedule, now).stale);
+
+        metrics.set_last_fetch(now - Duration::minutes(50));
+        metrics.set_session(SessionState::Valid);
+        assert_eq!(
+            check(&metrics, &schedule, now),
+            Health {
+                healthy: true,
+                session: SessionState::Valid,
+                last_fetch: Some(now - Duration::minutes(50)),
+                stale: false,
+            }
+        );
+
+        metrics.set_last_fetch(now - Duration::minutes(150));
+        assert!(!check(&metrics, &schedule, now).healthy);
+        metrics.set_last_fetch(now);
+        metrics.set_session(SessionState::Expired);
+        assert!(!check(&metrics, &schedule, now).healthy);
+    }
+}
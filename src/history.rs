@@ -0,0 +1,261 @@
+use crate::models::{ProductGroup, TrashService};
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection};
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+
+pub const HISTORY_FILE: &str = "history.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS fetches (
+    id INTEGER PRIMARY KEY,
+    fetched_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS services (
+    fetch_id INTEGER NOT NULL REFERENCES fetches(id),
+    customer_number TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    service_type INTEGER,
+    name TEXT NOT NULL,
+    product_group TEXT,
+    next_date TEXT,
+    price REAL,
+    interval_weeks TEXT,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS services_fetch_id ON services(fetch_id);
+";
+
+/// A single stored fetch
+#[derive(Debug)]
+pub struct Fetch {
+    pub id: i64,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// A pickup date that was announced for a service and has since passed
+#[derive(Debug, Serialize)]
+pub struct PastPickup {
+    pub date: NaiveDate,
+    pub customer_number: String,
+    pub name: String,
+    pub product_group: Option<ProductGroup>,
+    /// First fetch listing this date as the next pickup
+    pub first_seen: DateTime<Utc>,
+    /// Last fetch listing this date as the next pickup
+    pub last_seen: DateTime<Utc>,
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)
+        .context("Invalid fetch timestamp in history")?
+        .with_timezone(&Utc))
+}
+
+/// SQLite database of every fetched service list
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    /// Open the history database of the storage. Storage without files
+    /// gets a database that lives as long as the returned value.
+    pub fn open(storage: &dyn Storage) -> Result<Self> {
+        let conn = match storage.path(HISTORY_FILE) {
+            Some(path) => {
+                Connection::open(&path).context(format!("Failed to open history {:?}", path))?
+            }
+            None => Connection::open_in_memory().context("Failed to open history")?,
+        };
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("Failed to initialize history database")?;
+        Ok(Self { conn })
+    }
+
+    /// Store a fetched service list, returning the id of the new fetch
+    pub fn record(&mut self, services: &[TrashService], fetched_at: DateTime<Utc>) -> Result<i64> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO fetches (fetched_at) VALUES (?1)",
+            params![fetched_at.to_rfc3339()],
+        )?;
+        let fetch_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO services (fetch_id, customer_number, position, service_type, name,
+                                       product_group, next_date, price, interval_weeks, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for service in services {
+                insert.execute(params![
+                    fetch_id,
+                    service.customer_number,
+                    service.position,
+                    service.service_type,
+                    service.name,
+                    service
+                        .tariff
+                        .as_ref()
+                        .and_then(|tariff| tariff.product_group.as_ref())
+                        .map(ProductGroup::code),
+                    service
+                        .next_date
+                        .map(|date| date.format("%Y-%m-%d").to_string()),
+                    service.price.and_then(|price| price.to_f64()),
+                    service.interval,
+                    serde_json::to_string(service)?,
+                ])?;
+            }
+        }
+
+        tx.commit().context("Failed to store fetch in history")?;
+        Ok(fetch_id)
+    }
+
+    /// The `count` most recent fetches, oldest first
+    pub fn latest_fetches(&self, count: usize) -> Result<Vec<Fetch>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, fetched_at FROM fetches ORDER BY id DESC LIMIT ?1")?;
+        let mut fetches = statement
+            .query_map(params![count as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|row| {
+                let (id, fetched_at) = row?;
+                Ok(Fetch {
+                    id,
+                    fetched_at: parse_timestamp(&fetched_at)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        fetches.reverse();
+        Ok(fetches)
+    }
+
+    /// Services as they were stored for the given fetch
+    pub fn load_fetch(&self, fetch_id: i64) -> Result<Vec<TrashService>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT data FROM services WHERE fetch_id = ?1 ORDER BY rowid")?;
+        let services = statement
+            .query_map(params![fetch_id], |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to load services from history")?;
+        Ok(services)
+    }
+
+    /// Pickup dates before `today` seen in any fetch, optionally limited to one product group
+    pub fn past_pickups(
+        &self,
+        today: NaiveDate,
+        group: Option<&ProductGroup>,
+    ) -> Result<Vec<PastPickup>> {
+        let mut statement = self.conn.prepare(
+            "SELECT s.next_date, s.customer_number, s.name, s.product_group,
+                    MIN(f.fetched_at), MAX(f.fetched_at)
+             FROM services s JOIN fetches f ON f.id = s.fetch_id
+             WHERE s.next_date IS NOT NULL AND s.next_date < ?1
+               AND (?2 IS NULL OR s.product_group = ?2)
+             GROUP BY s.customer_number, s.position, s.next_date
+             ORDER BY s.next_date, s.customer_number, s.position",
+        )?;
+        let rows = statement.query_map(
+            params![
+                today.format("%Y-%m-%d").to_string(),
+                group.map(ProductGroup::code)
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            },
+        )?;
+
+        rows.map(|row| {
+            let (date, customer_number, name, product_group, first_seen, last_seen) = row?;
+            Ok(PastPickup {
+                date: NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .context("Invalid pickup date in history")?,
+                customer_number,
+                name,
+                product_group: product_group.map(ProductGroup::from),
+                first_seen: parse_timestamp(&first_seen)?,
+                last_seen: parse_timestamp(&last_seen)?,
+            })
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use rust_decimal::Decimal;
+
+    fn service(next_date: &str) -> TrashService {
+        TrashService::builder("Biojäte", next_date)
+            .customer("02-2891001-01")
+            .price(Decimal::new(105, 1))
+            .group("BIO")
+            .build()
+    }
+
+    #[test]
+    fn test_record_and_load() -> Result<()> {
+        let mut store = HistoryStore::open(&MemoryStorage::default())?;
+
+        store.record(&[service("2024-03-05")], Utc::now())?;
+        store.record(&[service("2024-03-19")], Utc::now())?;
+        let latest_id = store.record(&[service("2024-04-02")], Utc::now())?;
+
+        let fetches = store.latest_fetches(2)?;
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[1].id, latest_id);
+
+        let services = store.load_fetch(fetches[0].id)?;
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].next_date, NaiveDate::from_ymd_opt(2024, 3, 19));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_past_pickups() -> Result<()> {
+        let mut store = HistoryStore::open(&MemoryStorage::default())?;
+
+        store.record(&[service("2024-03-05")], Utc::now())?;
+        store.record(&[service("2024-03-05")], Utc::now())?;
+        store.record(&[service("2024-03-19")], Utc::now())?;
+        store.record(&[service("2024-04-02")], Utc::now())?;
+
+        let today = NaiveDate::from_ymd_opt(2024, 3, 25).unwrap();
+        let pickups = store.past_pickups(today, Some(&ProductGroup::Bio))?;
+
+        let dates: Vec<String> = pickups.iter().map(|p| p.date.to_string()).collect();
+        assert_eq!(dates, vec!["2024-03-05", "2024-03-19"]);
+        assert!(pickups[0].first_seen <= pickups[0].last_seen);
+
+        assert!(store
+            .past_pickups(today, Some(&ProductGroup::Mixed))?
+            .is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// Easter Sunday of a year (anonymous Gregorian algorithm)
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).expect("valid Easter date")
+}
+
+/// First `weekday` on or after the given day of the month
+fn weekday_from(year: i32, month: u32, day: u32, weekday: Weekday) -> NaiveDate {
+    let date = NaiveDate::from_ymd_opt(year, month, day).expect("valid date");
+    let offset = (7 + weekday.num_days_from_monday() - date.weekday().num_days_from_monday()) % 7;
+    date + Duration::days(i64::from(offset))
+}
+
+/// Finnish public holidays and the eves commonly treated as such, with
+/// their Finnish names
+pub fn finnish_holidays(year: i32) -> Vec<(NaiveDate, &'static str)> {
+    let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).expect("valid date");
+    let easter = easter(year);
+    let midsummer_eve = weekday_from(year, 6, 19, Weekday::Fri);
+
+    vec![
+        (date(1, 1), "Uudenvuodenpäivä"),
+        (date(1, 6), "Loppiainen"),
+        (easter - Duration::days(2), "Pitkäperjantai"),
+        (easter, "Pääsiäispäivä"),
+        (easter + Duration::days(1), "2. pääsiäispäivä"),
+        (date(5, 1), "Vappu"),
+        (easter + Duration::days(39), "Helatorstai"),
+        (easter + Duration::days(49), "Helluntaipäivä"),
+        (midsummer_eve, "Juhannusaatto"),
+        (midsummer_eve + Duration::days(1), "Juhannuspäivä"),
+        (weekday_from(year, 10, 31, Weekday::Sat), "Pyhäinpäivä"),
+        (date(12, 6), "Itsenäisyyspäivä"),
+        (date(12, 24), "Jouluaatto"),
+        (date(12, 25), "Joulupäivä"),
+        (date(12, 26), "Tapaninpäivä"),
+    ]
+}
+
+/// Name of the Finnish public holiday falling on `date`
+pub fn finnish_holiday(date: NaiveDate) -> Option<&'static str> {
+    finnish_holidays(date.year())
+        .into_iter()
+        .find(|(holiday, _)| *holiday == date)
+        .map(|(_, name)| name)
+}
+
+/// First weekday on or after `date` that is not a public holiday
+pub fn next_working_day(mut date: NaiveDate) -> NaiveDate {
+    while matches!(date.weekday(), Weekday::Sat | Weekday::Sun) || finnish_holiday(date).is_some() {
+        date += Duration::days(1);
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_easter() {
+        assert_eq!(easter(2024), date(2024, 3, 31));
+        assert_eq!(easter(2025), date(2025, 4, 20));
+        assert_eq!(easter(2038), date(2038, 4, 25));
+    }
+
+    #[test]
+    fn test_finnish_holiday() {
+        assert_eq!(finnish_holiday(date(2024, 5, 9)), Some("Helatorstai"));
+        assert_eq!(finnish_holiday(date(2024, 6, 21)), Some("Juhannusaatto"));
+        assert_eq!(finnish_holiday(date(2024, 11, 2)), Some("Pyhäinpäivä"));
+        assert_eq!(finnish_holiday(date(2024, 12, 6)), Some("Itsenäisyyspäivä"));
+        assert_eq!(finnish_holiday(date(2024, 3, 5)), None);
+    }
+
+    #[test]
+    fn test_next_working_day() {
+        // Good Friday and Easter Monday surround the weekend
+        assert_eq!(next_working_day(date(2024, 3, 29)), date(2024, 4, 2));
+        assert_eq!(next_working_day(date(2024, 3, 5)), date(2024, 3, 5));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::config::HooksConfig;
+use crate::diff::ScheduleDiff;
+use crate::models::ProductGroup;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Point after a fetch where a configured command runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    Change,
+    FetchSuccess,
+    FetchFailure,
+}
+
+impl Hook {
+    /// Name of the setting, also given to the command as `PJHOY_HOOK`
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Change => "on_change",
+            Hook::FetchSuccess => "on_fetch_success",
+            Hook::FetchFailure => "on_fetch_failure",
+        }
+    }
+
+    fn command(self, config: &HooksConfig) -> Option<&str> {
+        match self {
+            Hook::Change => config.on_change.as_deref(),
+            Hook::FetchSuccess => config.on_fetch_success.as_deref(),
+            Hook::FetchFailure => config.on_fetch_failure.as_deref(),
+        }
+        .filter(|command| !command.trim().is_empty())
+    }
+}
+
+/// What a fetch did, for the environment of the hooks
+#[derive(Debug, Default)]
+pub struct FetchReport {
+    pub exit_code: u8,
+    /// Number of services fetched
+    pub services: usize,
+    /// Where the services were saved, if they were
+    pub services_file: Option<PathBuf>,
+    /// Changes since the previous fetch, `None` without one to compare to
+    pub diff: Option<ScheduleDiff>,
+    /// The error of a failed fetch
+    pub error: Option<String>,
+}
+
+/// `PJHOY_*` variables describing `report` to the command of `hook`
+pub fn environment(
+    hook: Hook,
+    calendar: &Path,
+    report: &FetchReport,
+) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("PJHOY_HOOK", hook.name().to_string()),
+        ("PJHOY_CALENDAR", calendar.display().to_string()),
+        ("PJHOY_EXIT_CODE", report.exit_code.to_string()),
+    ];
+    if let Some(error) = &report.error {
+        env.push(("PJHOY_ERROR", error.clone()));
+    } else {
+        env.push(("PJHOY_SERVICES", report.services.to_string()));
+    }
+    if let Some(path) = &report.services_file {
+        env.push(("PJHOY_SERVICES_FILE", path.display().to_string()));
+    }
+    if let Some(diff) = &report.diff {
+        let groups: Vec<&str> = diff
+            .product_groups()
+            .into_iter()
+            .map(ProductGroup::code)
+            .collect();
+        env.extend([
+            ("PJHOY_CHANGES", diff.len().to_string()),
+            ("PJHOY_ADDED", diff.added.len().to_string()),
+            ("PJHOY_REMOVED", diff.removed.len().to_string()),
+            ("PJHOY_DATE_CHANGES", diff.date_changes.len().to_string()),
+            ("PJHOY_PRICE_CHANGES", diff.price_changes.len().to_string()),
+            ("PJHOY_CHANGED_GROUPS", groups.join(",")),
+        ]);
+    }
+    env
+}
+
+/// Run the command of `hook` with `sh -c`, if one is configured. The
+/// `on_change` command gets the diff as JSON on stdin, like the webhook.
+pub async fn run(
+    config: &HooksConfig,
+    hook: Hook,
+    calendar: &Path,
+    report: &FetchReport,
+) -> Result<()> {
+    let Some(command) = hook.command(config) else {
+        return Ok(());
+    };
+    let stdin = match (&report.diff, hook) {
+        (Some(diff), Hook::Change) => Some(serde_json::to_vec(diff)?),
+        _ => None,
+    };
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(environment(hook, calendar, report))
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {} hook", hook.name()))?;
+    if let (Some(data), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // A command not reading its stdin is fine
+        let _ = pipe.write_all(&data).await;
+    }
+
+    let timeout = config
+        .timeout_secs
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+    let status = tokio::time::timeout(timeout, child.wait())
+        .await
+        .with_context(|| format!("{} hook timed out after {:?}", hook.name(), timeout))??;
+    if !status.success() {
+        bail!("{} hook failed: {}", hook.name(), status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{Change, ServiceRef};
+
+    #[test]
+    fn test_environment() {
+        let service = |group: ProductGroup| ServiceRef {
+            customer_number: "02-2891001-01".to_string(),
+            position: 1,
+            name: "Astia".to_string(),
+            product_group: Some(group),
+            address: None,
+        };
+        let diff = ScheduleDiff {
+            removed: vec![service(ProductGroup::Mixed)],
+            date_changes: vec![Change {
+                service: service(ProductGroup::Bio),
+                old: None,
+                new: None,
+            }],
+            ..ScheduleDiff::default()
+        };
+        let report = FetchReport {
+            exit_code: 10,
+            services: 3,
+            diff: Some(diff),
+            ..FetchReport::default()
+        };
+
+        let env = environment(Hook::Change, Path::new("/tmp/pjhoy.ics"), &report);
+        let value = |name: &str| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value("PJHOY_HOOK"), Some("on_change"));
+        assert_eq!(value("PJHOY_CALENDAR"), Some("/tmp/pjhoy.ics"));
+        assert_eq!(value("PJHOY_SERVICES"), Some("3"));
+        assert_eq!(value("PJHOY_CHANGES"), Some("2"));
+        assert_eq!(value("PJHOY_CHANGED_GROUPS"), Some("SEK,BIO"));
+        assert_eq!(value("PJHOY_ERROR"), None);
+    }
+}
@@ -0,0 +1,450 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// Language of user-facing texts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Fi,
+    En,
+}
+
+impl Language {
+    /// Language of the user's locale, if it is one of the supported ones
+    pub fn from_locale() -> Option<Self> {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())?;
+        Self::from_locale_name(&locale)
+    }
+
+    fn from_locale_name(locale: &str) -> Option<Self> {
+        match locale.get(..2) {
+            Some("fi") => Some(Language::Fi),
+            Some("en") => Some(Language::En),
+            _ => None,
+        }
+    }
+}
+
+/// Console messages with their Finnish and English texts. `{}` marks
+/// an argument, filled in order.
+const MESSAGES: &[(&str, &str, &str)] = &[
+    (
+        "login-ok",
+        "Kirjautuminen onnistui ja evästeet tallennettu.",
+        "Login successful and cookies saved.",
+    ),
+    (
+        "logged-out",
+        "Kirjauduttu ulos ja evästeet poistettu.",
+        "Logged out and cookies deleted.",
+    ),
+    (
+        "cookies-imported",
+        "{} evästettä tuotu ja istunto tallennettu.",
+        "Imported {} cookies and saved the session.",
+    ),
+    (
+        "session-imported",
+        "Istunto otettu käyttöön ({} evästettä).",
+        "Session taken over ({} cookies).",
+    ),
+    (
+        "cookies-exported",
+        "Evästeet kirjoitettu tiedostoon {}",
+        "Cookies written to {}",
+    ),
+    (
+        "session-expired",
+        "Istunto vanhentunut, kirjaudutaan uudelleen...",
+        "Session expired, attempting to login...",
+    ),
+    (
+        "login-retry",
+        "Kirjautuminen onnistui, yritetään uudelleen...",
+        "Login successful, retrying...",
+    ),
+    (
+        "login-retry-failed",
+        "Kirjautuminen epäonnistui uudelleenyrityksessä",
+        "Failed to login during retry",
+    ),
+    (
+        "request-failed-after-login",
+        "Pyyntö epäonnistui kirjautumisen jälkeen",
+        "Request failed after login",
+    ),
+    ("yes-no", "[k/E]", "[y/N]"),
+    (
+        "no-customer-numbers",
+        "Asiakasnumeroilla 00-{} ei löytynyt palveluita",
+        "No services found for customer numbers 00-{}",
+    ),
+    (
+        "found-customer-numbers",
+        "Löydetyt asiakasnumerot: {}",
+        "Found customer numbers: {}",
+    ),
+    (
+        "config-up-to-date",
+        "Asetukset ovat jo ajan tasalla.",
+        "Config is already up to date.",
+    ),
+    (
+        "save-customer-numbers",
+        "Tallennetaanko ne tiedostoon config.toml?",
+        "Save them to config.toml?",
+    ),
+    ("config-saved", "Asetus {} tallennettu", "Setting {} saved"),
+    (
+        "customer-numbers-saved",
+        "Asiakasnumerot tallennettu: {}",
+        "Customer numbers saved to {}",
+    ),
+    (
+        "address-fetch-failed",
+        "Varoitus: noutoosoitteiden haku epäonnistui: {}",
+        "Warning: could not fetch pickup addresses: {}",
+    ),
+    (
+        "fetched-services",
+        "Haettu {} jätehuoltopalvelua",
+        "Fetched {} trash services",
+    ),
+    (
+        "loaded-services",
+        "Luettu {} jätehuoltopalvelua tiedostosta",
+        "Loaded {} trash services from file",
+    ),
+    ("daemon-next-run", "Seuraava {}: {}", "Next {} at {}"),
+    (
+        "server-listening",
+        "Mittarit osoitteessa {}/metrics",
+        "Serving metrics at {}/metrics",
+    ),
+    (
+        "tls-incomplete",
+        "daemon.tls_cert ja daemon.tls_key on asetettava yhdessä",
+        "daemon.tls_cert and daemon.tls_key must be set together",
+    ),
+    (
+        "daemon-refresh",
+        "Kalenteri on vanhentunut, {} nyt",
+        "Calendar is stale, running {} now",
+    ),
+    ("daemon-job-failed", "{} epäonnistui: {}", "{} failed: {}"),
+    (
+        "alert-failed",
+        "Hälytyksen lähetys epäonnistui: {}",
+        "Sending the alert failed: {}",
+    ),
+    ("state-file-moved", "Siirretty {} -> {}", "Moved {} to {}"),
+    (
+        "debug-bundle-saved",
+        "Raportti tallennettu: {}. Tarkista sen sisältö ennen jakamista.",
+        "Report saved to {}. Check its contents before sharing.",
+    ),
+    (
+        "up-to-date",
+        "Uusin versio {} on jo käytössä",
+        "Already running the latest version {}",
+    ),
+    (
+        "update-available",
+        "Uusi versio {} saatavilla: pjhoy self-update",
+        "Version {} is available: pjhoy self-update",
+    ),
+    ("updated", "Päivitetty versioon {}: {}", "Updated to {}: {}"),
+    ("bar-today", "tänään", "today"),
+    ("bar-tomorrow", "huomenna", "tomorrow"),
+    ("bar-days", "{} pv", "{}d"),
+    (
+        "watch-header",
+        "Päivitetty {}, tiedot haettu {}",
+        "Updated {}, data fetched {}",
+    ),
+    (
+        "watch-fetch-failed",
+        "Haku epäonnistui: {}",
+        "Fetch failed: {}",
+    ),
+    (
+        "watch-no-pickups",
+        "Ei tyhjennyksiä seuraavan {} viikon aikana",
+        "No pickups in the next {} weeks",
+    ),
+    ("purge-nothing", "Ei poistettavaa", "Nothing to delete"),
+    (
+        "purge-confirm",
+        "Poistetaanko {} tiedostoa?",
+        "Delete these {} files?",
+    ),
+    ("purge-done", "Poistettu {} tiedostoa", "Deleted {} files"),
+    ("service-written", "Kirjoitettu {}", "Wrote {}"),
+    (
+        "service-jobs",
+        "Kukin komento ajetaan erikseen [daemon]-osion aikataululla: {}",
+        "One job per command, on the schedules of the [daemon] section: {}",
+    ),
+    (
+        "service-enable",
+        "Ota käyttöön komennolla: {}",
+        "Enable with: {}",
+    ),
+    (
+        "pickup-moved",
+        "Tyhjennys siirtyy: {} {} -> {}",
+        "Pickup moved: {} {} -> {}",
+    ),
+    ("price-changed", "Hinta muuttunut: {}", "Price changed: {}"),
+    (
+        "schedule-changed",
+        "Aikataulu muuttunut {} jälkeen: {} muutos(ta)",
+        "Schedule changed since {}: {} change(s)",
+    ),
+    (
+        "webhook-notified",
+        "Webhook kutsuttu: {}",
+        "Webhook notified: {}",
+    ),
+    (
+        "webhook-failed",
+        "Webhook-kutsu epäonnistui: {}",
+        "Calling the webhook failed: {}",
+    ),
+    ("hook-failed", "Komento epäonnistui: {}", "Hook failed: {}"),
+    (
+        "calendar-saved",
+        "Kalenteri tallennettu: {}",
+        "Calendar saved to: {}",
+    ),
+    (
+        "calendar-write-failed",
+        "Kalenterin tallennus epäonnistui",
+        "Failed to write calendar file",
+    ),
+    (
+        "mqtt-published",
+        "Noutopäivät julkaistu MQTT-välittäjään {}",
+        "Published pickup dates to MQTT broker {}",
+    ),
+    (
+        "mqtt-failed",
+        "Julkaisu MQTT-välittäjään epäonnistui: {}",
+        "Publishing to the MQTT broker failed: {}",
+    ),
+    (
+        "parsed-json-saved",
+        "Jäsennetyt palvelut tallennettu: {}",
+        "Parsed services JSON saved to: {}",
+    ),
+    (
+        "raw-json-saved",
+        "Alkuperäinen JSON-vastaus tallennettu: {}",
+        "Original raw JSON data saved to: {}",
+    ),
+    (
+        "services-file-missing",
+        "{} puuttuu datahakemistosta",
+        "{} not found in data directory",
+    ),
+    (
+        "read-failed",
+        "Tiedoston {} luku epäonnistui",
+        "Failed to read {}",
+    ),
+    (
+        "parse-failed",
+        "Tiedoston {} jäsennys epäonnistui",
+        "Failed to parse {}",
+    ),
+    (
+        "write-failed",
+        "Tiedoston {} kirjoitus epäonnistui",
+        "Failed to write {}",
+    ),
+    (
+        "invoices-parse-failed",
+        "Laskujen jäsennys epäonnistui",
+        "Failed to parse invoices",
+    ),
+    ("due", "eräpäivä", "due"),
+    (
+        "ntfy-not-configured",
+        "ntfy-asetuksia ei ole määritetty",
+        "ntfy is not configured",
+    ),
+    (
+        "email-not-configured",
+        "Sähköpostiasetuksia ei ole määritetty",
+        "Email is not configured",
+    ),
+    (
+        "slack-not-configured",
+        "Slack-asetuksia ei ole määritetty",
+        "Slack is not configured",
+    ),
+    (
+        "no-notifiers",
+        "Ilmoituskanavia ei ole määritetty",
+        "No notifiers configured",
+    ),
+    (
+        "notifications-sent",
+        "Lähetetty {} ilmoitus(ta)",
+        "Sent {} notification(s)",
+    ),
+    (
+        "no-stored-fetches",
+        "Tallennettuja hakuja ei ole vertailtavaksi",
+        "No stored fetches to compare",
+    ),
+    (
+        "one-stored-fetch",
+        "Vain yksi haku tallennettuna",
+        "Only one stored fetch available",
+    ),
+    ("listed", "listalla", "listed"),
+    ("lint-ok", "Ei ongelmia: {}", "No problems found: {}"),
+    (
+        "lint-problems",
+        "Löytyi {} ongelma(a)",
+        "Found {} problem(s)",
+    ),
+    ("session-valid", "Istunto on voimassa", "Session is valid"),
+    (
+        "session-invalid",
+        "Istunto ei ole voimassa, kirjaudu sisään: pjhoy login",
+        "Session is not valid, log in with: pjhoy login",
+    ),
+    (
+        "cookies-saved-at",
+        "Evästeet tallennettu: {}",
+        "Cookies saved: {}",
+    ),
+    ("last-fetched-at", "Viimeisin haku: {}", "Last fetched: {}"),
+    ("never", "ei koskaan", "never"),
+    (
+        "no-customers",
+        "  Ei asiakasnumeroita",
+        "  No customer numbers",
+    ),
+    ("doctor-config", "Asetustiedosto", "Config file"),
+    (
+        "doctor-config-env",
+        "ympäristömuuttujista PJHOY_*",
+        "from PJHOY_* environment variables",
+    ),
+    ("doctor-cookies", "Istunto", "Session"),
+    (
+        "doctor-no-cookies",
+        "ei tallennettuja evästeitä, kirjaudu sisään: pjhoy login",
+        "no saved cookies, log in with: pjhoy login",
+    ),
+    ("doctor-http", "HTTP-asetukset", "HTTP settings"),
+    ("doctor-network", "Yhteys extranetiin", "Extranet reachable"),
+    ("doctor-timeout", "{}: aikakatkaisu", "{}: timed out"),
+    ("doctor-https", "HTTPS ja TLS", "HTTPS and TLS"),
+    (
+        "doctor-writable",
+        "Kirjoitusoikeus {}",
+        "Write access to {}",
+    ),
+    (
+        "doctor-failed",
+        "{} tarkistus(ta) epäonnistui",
+        "{} check(s) failed",
+    ),
+];
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Select the language of console messages for the rest of the run
+pub fn set_language(lang: Language) {
+    let _ = LANGUAGE.set(lang);
+}
+
+fn language() -> Language {
+    LANGUAGE.get().copied().unwrap_or_default()
+}
+
+fn translate(lang: Language, key: &str, args: &[&dyn Display]) -> String {
+    let Some((_, finnish, english)) = MESSAGES.iter().find(|(id, _, _)| *id == key) else {
+        return key.to_string();
+    };
+    let text = match lang {
+        Language::Fi => finnish,
+        Language::En => english,
+    };
+
+    let mut args = args.iter();
+    let mut parts = text.split("{}");
+    let mut output = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            output.push_str(&arg.to_string());
+        }
+        output.push_str(part);
+    }
+    output
+}
+
+/// Message `key` in the selected language with `{}`s replaced by `args`
+pub fn message(key: &str, args: &[&dyn Display]) -> String {
+    translate(language(), key, args)
+}
+
+/// Translate a console message, e.g. `tr!("calendar-saved", path.display())`
+macro_rules! tr {
+    ($key:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::message($key, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_have_matching_arguments() {
+        for (key, finnish, english) in MESSAGES {
+            assert_eq!(
+                finnish.matches("{}").count(),
+                english.matches("{}").count(),
+                "argument count of {} differs",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(
+            translate(Language::Fi, "fetched-services", &[&3]),
+            "Haettu 3 jätehuoltopalvelua"
+        );
+        assert_eq!(
+            translate(Language::En, "schedule-changed", &[&"2024-03-01", &2]),
+            "Schedule changed since 2024-03-01: 2 change(s)"
+        );
+        assert_eq!(translate(Language::En, "no-such-key", &[]), "no-such-key");
+    }
+
+    #[test]
+    fn test_locale_name() {
+        assert_eq!(
+            Language::from_locale_name("fi_FI.UTF-8"),
+            Some(Language::Fi)
+        );
+        assert_eq!(
+            Language::from_locale_name("en_GB.UTF-8"),
+            Some(Language::En)
+        );
+        assert_eq!(Language::from_locale_name("C.UTF-8"), None);
+    }
+}
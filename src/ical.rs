@@ -0,0 +1,438 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::fmt;
+
+/// Content line of an iCalendar component, with the value still escaped
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Property {
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+/// iCalendar component such as VCALENDAR or VEVENT
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    /// First property with the given name
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Subcomponents with the given name, e.g. the VEVENTs of a calendar
+    pub fn components_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Component> {
+        self.components
+            .iter()
+            .filter(move |component| component.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Join folded lines: a line starting with a space or tab continues the previous one
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push((index + 1, line.to_string())),
+        }
+    }
+    lines
+}
+
+/// Split the parameters off an escaped property name, respecting quotes
+fn parse_line(line: &str) -> Option<Property> {
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (key.to_uppercase(), value.trim_matches('"').to_string())
+        })
+        .collect();
+
+    Some(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+/// Parse an iCalendar document into its top-level component
+pub fn parse(text: &str) -> Result<Component> {
+    let mut stack: Vec<Component> = Vec::new();
+    let mut root = None;
+
+    for (line_number, line) in unfold(text) {
+        let property = parse_line(&line)
+            .ok_or_else(|| anyhow!("Line {}: malformed content line", line_number))?;
+
+        match property.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: property.value.to_uppercase(),
+                properties: Vec::new(),
+                components: Vec::new(),
+            }),
+            "END" => {
+                let component = stack
+                    .pop()
+                    .ok_or_else(|| anyhow!("Line {}: END without BEGIN", line_number))?;
+                if !component.name.eq_ignore_ascii_case(&property.value) {
+                    return Err(anyhow!(
+                        "Line {}: END:{} closes {}",
+                        line_number,
+                        property.value,
+                        component.name
+                    ));
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None if root.is_none() => root = Some(component),
+                    None => {
+                        return Err(anyhow!(
+                            "Line {}: more than one top-level component",
+                            line_number
+                        ))
+                    }
+                }
+            }
+            _ => stack
+                .last_mut()
+                .ok_or_else(|| anyhow!("Line {}: property outside a component", line_number))?
+                .properties
+                .push(property),
+        }
+    }
+
+    if let Some(component) = stack.pop() {
+        return Err(anyhow!("{} is not closed", component.name));
+    }
+    root.ok_or_else(|| anyhow!("No calendar found"))
+}
+
+/// Write a content line, folded to 75 octets as RFC 5545 requires
+fn write_folded(f: &mut fmt::Formatter<'_>, line: &str) -> fmt::Result {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            f.write_str("\r\n ")?;
+            width = 1;
+        }
+        write!(f, "{}", c)?;
+        width += c.len_utf8();
+    }
+    f.write_str("\r\n")
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.name.clone();
+        for (key, value) in &self.params {
+            if value.contains([':', ';', ',']) {
+                line.push_str(&format!(";{}=\"{}\"", key, value));
+            } else {
+                line.push_str(&format!(";{}={}", key, value));
+            }
+        }
+        line.push(':');
+        line.push_str(&self.value);
+        write_folded(f, &line)
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BEGIN:{}\r\n", self.name)?;
+        for property in &self.properties {
+            write!(f, "{}", property)?;
+        }
+        for component in &self.components {
+            write!(f, "{}", component)?;
+        }
+        write!(f, "END:{}\r\n", self.name)
+    }
+}
+
+/// Undo the escaping of a TEXT value
+pub fn unescape_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+/// Problem found in an iCalendar file
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    /// 1-based line number of the physical line, if the issue has one
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Properties whose values are TEXT and must escape `,`, `;` and `\`
+const TEXT_PROPERTIES: &[&str] = &["SUMMARY", "DESCRIPTION", "LOCATION", "COMMENT"];
+
+/// Check the escaping of a TEXT value, returning the first problem
+fn text_escaping_issue(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\\' | ';' | ',' | 'n' | 'N') => {}
+                Some(other) => return Some(format!("invalid escape sequence \\{}", other)),
+                None => return Some("value ends with a lone backslash".to_string()),
+            },
+            ';' | ',' => return Some(format!("unescaped '{}'", c)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Check an iCalendar file for RFC 5545 problems
+pub fn lint(bytes: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let issue = |line, message: String| Issue { line, message };
+
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            // Usually a line folded in the middle of a multi-byte character
+            let line = bytes[..e.valid_up_to()]
+                .iter()
+                .filter(|b| **b == b'\n')
+                .count()
+                + 1;
+            issues.push(issue(Some(line), "invalid UTF-8".to_string()));
+            return issues;
+        }
+    };
+
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let number = Some(index + 1);
+        let content = match line.strip_suffix("\r\n") {
+            Some(content) => content,
+            None if line.ends_with('\n') => {
+                issues.push(issue(
+                    number,
+                    "line ends with LF instead of CRLF".to_string(),
+                ));
+                line.trim_end_matches('\n')
+            }
+            None => {
+                issues.push(issue(
+                    number,
+                    "last line is not terminated by CRLF".to_string(),
+                ));
+                line
+            }
+        };
+        if content.len() > 75 {
+            issues.push(issue(
+                number,
+                format!(
+                    "line is {} octets long, longer than 75 and not folded",
+                    content.len()
+                ),
+            ));
+        }
+    }
+
+    let calendar = match parse(text) {
+        Ok(calendar) => calendar,
+        Err(e) => {
+            issues.push(issue(None, e.to_string()));
+            return issues;
+        }
+    };
+
+    if calendar.name != "VCALENDAR" {
+        issues.push(issue(
+            None,
+            format!("top-level component is {}, not VCALENDAR", calendar.name),
+        ));
+    }
+    for required in ["VERSION", "PRODID"] {
+        if calendar.property(required).is_none() {
+            issues.push(issue(None, format!("calendar has no {}", required)));
+        }
+    }
+
+    let mut uids: Vec<&str> = Vec::new();
+    for (index, event) in calendar.components_named("VEVENT").enumerate() {
+        let label = match event.property("UID") {
+            Some(uid) => uid.value.clone(),
+            None => format!("event #{}", index + 1),
+        };
+        for required in ["UID", "DTSTAMP", "DTSTART"] {
+            if event.property(required).is_none() {
+                issues.push(issue(None, format!("{} has no {}", label, required)));
+            }
+        }
+        if let Some(uid) = event.property("UID") {
+            if uids.contains(&uid.value.as_str()) {
+                issues.push(issue(None, format!("duplicate UID {}", uid.value)));
+            }
+            uids.push(&uid.value);
+        }
+        for property in &event.properties {
+            if !TEXT_PROPERTIES.contains(&property.name.as_str()) {
+                continue;
+            }
+            if let Some(problem) = text_escaping_issue(&property.value) {
+                issues.push(issue(
+                    None,
+                    format!("{} {}: {}", label, property.name, problem),
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r\n\
+        VERSION:2.0\r\n\
+        BEGIN:VEVENT\r\n\
+        UID:pjhoy_12345_1_1_2024-03-05\r\n\
+        DTSTART;VALUE=DATE:20240305\r\n\
+        DESCRIPTION:Biojäte\\nHinta: 12.55 € (sis. ALV)\\n2 viikon välein. Tämä rivi\r\n \
+        on taitettu\r\n\
+        END:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let calendar = parse(CALENDAR)?;
+
+        assert_eq!(calendar.name, "VCALENDAR");
+        let event = calendar.components_named("VEVENT").next().unwrap();
+        let dtstart = event.property("DTSTART").unwrap();
+        assert_eq!(
+            dtstart.params,
+            vec![("VALUE".to_string(), "DATE".to_string())]
+        );
+        assert_eq!(dtstart.value, "20240305");
+        assert!(event
+            .property("DESCRIPTION")
+            .unwrap()
+            .value
+            .ends_with("Tämä rivion taitettu"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let calendar = parse(CALENDAR)?;
+        let written = calendar.to_string();
+
+        assert!(written.lines().all(|line| line.len() <= 75));
+        assert_eq!(parse(&written)?, calendar);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        let error = parse("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nEND:VCALENDAR\r\n").unwrap_err();
+        assert_eq!(error.to_string(), "Line 3: END:VCALENDAR closes VEVENT");
+        assert!(parse("BEGIN:VCALENDAR\r\n").is_err());
+    }
+
+    #[test]
+    fn test_lint_problems() {
+        let calendar = "BEGIN:VCALENDAR\n\
+            VERSION:2.0\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:a\r\n\
+            SUMMARY:Bio, Seka\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        let issues: Vec<String> = lint(calendar.as_bytes())
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+
+        assert_eq!(
+            issues,
+            vec![
+                "line 1: line ends with LF instead of CRLF",
+                "calendar has no PRODID",
+                "a has no DTSTAMP",
+                "a has no DTSTART",
+                "a SUMMARY: unescaped ','",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_split_character() {
+        // "ä" folded between its two bytes
+        let mut bytes = b"BEGIN:VCALENDAR\r\nSUMMARY:J\xc3".to_vec();
+        bytes.extend_from_slice(b"\r\n \xa4te\r\nEND:VCALENDAR\r\n");
+
+        assert_eq!(
+            lint(&bytes),
+            vec![Issue {
+                line: Some(2),
+                message: "invalid UTF-8".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unescape_text() {
+        assert_eq!(
+            unescape_text("Kotikatu 1\\, Tampere\\nHinta\\; 12 \\\\ 3"),
+            "Kotikatu 1, Tampere\nHinta; 12 \\ 3"
+        );
+    }
+}
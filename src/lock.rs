@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+pub const LOCK_FILE: &str = "pjhoy.lock";
+
+/// Another pjhoy process holds the lock
+#[derive(Debug)]
+pub struct AlreadyRunning {
+    pub path: PathBuf,
+    pub pid: Option<u32>,
+}
+
+impl std::fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Another pjhoy instance is running")?;
+        if let Some(pid) = self.pid {
+            write!(f, " (pid {})", pid)?;
+        }
+        write!(f, ", lock {:?}", self.path)
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// Advisory lock on a file of the data directory, keeping overlapping runs
+/// from writing the cookies and the calendar at the same time. Released when
+/// dropped or when the process dies.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Take the lock in `dir`, failing at once if it is held
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .context(format!("Failed to open {:?}", path))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(AlreadyRunning {
+                    path,
+                    pid: pid.trim().parse().ok(),
+                }
+                .into());
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).context(format!("Failed to lock {:?}", path));
+            }
+        }
+
+        // For the message of the instance that finds the lock taken
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .context(format!("Failed to write {:?}", path))?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_fails() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+
+        let lock = RunLock::acquire(dir)?;
+        let err = RunLock::acquire(dir).unwrap_err();
+        let running = err
+            .downcast_ref::<AlreadyRunning>()
+            .expect("AlreadyRunning");
+        assert_eq!(running.pid, Some(std::process::id()));
+
+        drop(lock);
+        RunLock::acquire(dir)?;
+        Ok(())
+    }
+}
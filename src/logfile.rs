@@ -0,0 +1,138 @@
+use crate::storage::rotate;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// When to start a new `--log-file`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    /// Older files kept as `pjhoy.log.1`, `pjhoy.log.2`...
+    pub keep: usize,
+}
+
+/// Log file moved aside as `<path>.1` once it grows too big or old
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    /// Creation time of the current file, as far as the platform tells
+    started: SystemTime,
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    let started = metadata
+        .created()
+        .or_else(|_| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+    Ok((file, metadata.len(), started))
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, rotating it first if it is already due
+    pub fn open(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let (file, size, started) = open_append(&path)?;
+        let mut log = Self {
+            path,
+            rotation,
+            file,
+            size,
+            started,
+        };
+        if log.due(0, SystemTime::now()) {
+            log.rotate()?;
+        }
+        Ok(log)
+    }
+
+    /// Whether writing `incoming` bytes at `now` should go to a new file.
+    /// An empty file is never rotated, however long the line.
+    fn due(&self, incoming: usize, now: SystemTime) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| self.size + incoming as u64 > max);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| now.duration_since(self.started).is_ok_and(|age| age >= max));
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        rotate(&self.path, self.rotation.keep)?;
+        (self.file, self.size, _) = open_append(&self.path)?;
+        self.started = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len(), SystemTime::now()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_by_size() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pjhoy.log");
+        let rotation = Rotation {
+            max_bytes: Some(10),
+            keep: 2,
+            ..Rotation::default()
+        };
+
+        let mut log = RotatingFile::open(path.clone(), rotation)?;
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(line.as_bytes())?;
+        }
+        assert_eq!(std::fs::read_to_string(&path)?, "fourth\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("pjhoy.log.1"))?,
+            "third\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("pjhoy.log.2"))?,
+            "second\n"
+        );
+        assert!(!dir.path().join("pjhoy.log.3").exists());
+
+        // Reopening a file past its age limit starts a new one
+        drop(log);
+        let rotation = Rotation {
+            max_age: Some(Duration::ZERO),
+            keep: 2,
+            ..Rotation::default()
+        };
+        RotatingFile::open(path.clone(), rotation)?;
+        assert_eq!(std::fs::read_to_string(&path)?, "");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("pjhoy.log.1"))?,
+            "fourth\n"
+        );
+        Ok(())
+    }
+}
@@ -1,106 +1,1575 @@
+mod account;
+mod archive;
+#[cfg(feature = "blocking")]
+mod blocking;
+mod bundle;
 mod calendar;
+mod cassette;
 mod client;
 mod config;
+mod contract;
+mod cookies;
+mod costs;
+mod daemon;
+mod diff;
+mod doctor;
+mod error;
+mod exit;
+mod feed;
+mod health;
+mod history;
+mod holidays;
+mod hooks;
+mod i18n;
+mod ical;
+mod lock;
+mod logfile;
+mod metrics;
 mod models;
+mod month;
+mod mqtt;
+mod notify;
+mod output;
+mod purge;
+mod server;
+mod service;
+mod status;
+mod statusbar;
+mod storage;
+mod upcoming;
+#[cfg(feature = "self-update")]
+mod update;
 
-use crate::client::{PjhoyClient, SessionExpired};
+use crate::cassette::Cassette;
+use crate::client::{PjhoyClient, PjhoyClientBuilder};
 use crate::config::load_config;
-use crate::models::TrashService;
+use crate::history::HistoryStore;
+use crate::hooks::Hook;
+use crate::i18n::{tr, Language};
+use crate::models::{Invoice, ProductGroup, TrashService};
+use crate::output::report;
+use crate::storage::{FileStorage, MemoryStorage, Storage};
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::future::Future;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 const SERVICES_FILE: &str = "services.json";
+const ICS_FILE: &str = "pjhoy.ics";
 const SERVICES_FULL_FILE: &str = "services_full.json";
 
 #[derive(Parser, Debug)]
 #[command(name = "pjhoy")]
 #[command(about = "Pirkanmaan Jätehuolto Oy utility", long_about = None)]
 struct Cli {
-    /// Output ICS calendar file path
+    /// Output ICS calendar file path, defaults to pjhoy.ics in the data
+    /// directory
     #[arg(long, short)]
     output: Option<PathBuf>,
 
+    /// Directory of the calendar instead of the platform data directory
+    /// (~/.local/share/pjhoy)
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Directory for the session, saved services and history instead of
+    /// the state_dir setting or the platform state directory
+    /// (~/.local/state/pjhoy)
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+
     /// ICS refresh interval (e.g., P1D)
     #[arg(long)]
     ics_interval: Option<String>,
 
+    /// HTTP connection timeout in seconds
+    #[arg(long, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// HTTP request timeout in seconds
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Save every extranet response to numbered files in DIR
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer extranet requests from responses saved with --record instead
+    /// of the network, keeping cookies and history in memory only
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Fixed DTSTAMP of calendar events (RFC 3339) for reproducible output,
+    /// defaults to SOURCE_DATE_EPOCH if set
+    #[arg(long, value_name = "TIME")]
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Leave emojis out of calendar event summaries
+    #[arg(long)]
+    no_emoji: bool,
+
+    /// Language of messages and calendar categories, defaults to the locale
+    #[arg(long, value_enum)]
+    lang: Option<Language>,
+
+    /// Read the extranet password from the first line of stdin
+    #[arg(long)]
+    password_stdin: bool,
+
+    /// Log more details to stderr (-v debug, -vv trace), overridden by RUST_LOG
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only print errors and the requested data, no progress messages
+    #[arg(short, long, global = true, conflicts_with = "porcelain")]
+    quiet: bool,
+
+    /// Print progress messages as stable tab-separated `key value...` lines for scripts
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    /// Also write the log and progress messages with timestamps to this
+    /// file, e.g. with --quiet for cron jobs
+    #[arg(long, value_name = "FILE", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Start a new log file once it would grow past this many megabytes
+    #[arg(long, value_name = "MB", global = true, requires = "log_file")]
+    log_max_size: Option<u64>,
+
+    /// Start a new log file once it is this many days old
+    #[arg(long, value_name = "DAYS", global = true, requires = "log_file")]
+    log_max_age: Option<u64>,
+
+    /// Number of rotated log files to keep as FILE.1, FILE.2...
+    #[arg(long, value_name = "N", global = true, default_value_t = 5)]
+    log_keep: usize,
+
+    /// Color listings by product group
+    #[arg(long, value_enum, global = true, value_name = "WHEN", default_value_t = output::ColorChoice::Auto)]
+    color: output::ColorChoice,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// One JSON object per line, e.g. per service or pickup
+    Jsonl,
+}
+
+/// Output formats for tabular listings
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ListFormat {
+    Text,
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    Csv,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Login to PJHOY extranet and save session cookies
-    Login,
-    /// Fetch trash schedule and update calendar
-    Fetch {
-        /// Save parsed services JSON to data directory
-        #[arg(long = "save-json", short = 'j')]
-        save_parsed: bool,
+    Login {
+        /// Take over a browser session from a Netscape cookies.txt file
+        /// instead of logging in with the password
+        #[arg(long, value_name = "FILE")]
+        import_cookies: Option<PathBuf>,
+    },
+    /// End the session and delete the saved cookies
+    Logout,
+    /// Manage the saved session cookies
+    Cookies {
+        #[command(subcommand)]
+        command: CookiesCommand,
+    },
+    /// Move the logged in session to another machine
+    Session {
+        #[command(subcommand)]
+        command: SessionCommand,
+    },
+    /// Show the logged in account with its customer numbers and addresses
+    Whoami {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Check the config, session, network and file permissions
+    Doctor,
+    /// Save an anonymized report to attach to a bug report: versions, the
+    /// redacted config, diagnostics, the last saved responses and logs
+    DebugBundle {
+        /// Archive to write, pjhoy-debug-<time>.tar.gz by default
+        file: Option<PathBuf>,
+    },
+    /// Replace this executable with the latest release on GitHub after
+    /// checking its SHA-256 checksum
+    #[cfg(feature = "self-update")]
+    SelfUpdate {
+        /// Only tell whether a newer release is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Delete the session, saved services, history and calendar, e.g. before
+    /// handing the machine over to someone else
+    Purge {
+        /// Delete the config too
+        #[arg(long)]
+        config: bool,
+
+        /// Delete without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Show or change settings in config.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check whether the saved session is still valid and when data was last fetched
+    Status {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Print the next pickup as one line for Waybar, i3blocks or polybar,
+    /// from the services saved by the last fetch
+    StatusBar {
+        #[arg(long, value_enum, default_value_t = statusbar::BarFormat::Waybar)]
+        format: statusbar::BarFormat,
+    },
+    /// Show the next pickup of each product group, from the services saved
+    /// by the last fetch
+    Next {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List the pickups of the coming weeks as a table grouped by week or
+    /// customer number, with totals
+    Upcoming {
+        /// Number of weeks to include, starting today
+        #[arg(long, default_value_t = 4)]
+        weeks: u32,
+
+        #[arg(long, value_enum, default_value_t = upcoming::GroupBy::Week)]
+        group_by: upcoming::GroupBy,
+
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
 
-        /// Save original raw JSON response to data directory
-        #[arg(long = "save-original-json", short = 'r')]
-        save_original: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
+    /// Keep the upcoming pickups on screen, redrawn from the saved services
+    /// and fetched again every few hours
+    Watch(WatchArgs),
+    /// Show a month as a grid like `cal`, with icons on pickup days
+    Cal {
+        /// Month to show (YYYY-MM) instead of the current one
+        #[arg(value_parser = month::parse_month, value_name = "MONTH")]
+        month: Option<chrono::NaiveDate>,
+
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
+    },
+    /// Print today's pickups, exiting with 1 when there are none
+    Today {
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
+    },
+    /// Print tomorrow's pickups, exiting with 1 when there are none
+    Tomorrow {
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
+    },
+    /// Exit with 0 when there is a pickup on the day, 1 when not
+    IsPickup {
+        /// Check tomorrow instead of today
+        #[arg(long, conflicts_with = "date")]
+        tomorrow: bool,
+
+        /// Check this date (YYYY-MM-DD) instead of today
+        #[arg(long, value_name = "DATE")]
+        date: Option<chrono::NaiveDate>,
+
+        /// Only count these product groups (e.g. BIO,SEK)
+        #[arg(long, value_delimiter = ',', value_name = "GROUPS")]
+        group: Vec<ProductGroup>,
+    },
+    /// Find the customer numbers of the account and offer to save them to the config
+    Discover {
+        /// Highest customer number suffix to probe
+        #[arg(long, default_value_t = 30)]
+        max: u32,
+
+        /// Save to the config without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Fetch trash schedule and update calendar
+    Fetch(FetchArgs),
     /// Generate ICS calendar from current data
-    Calendar,
+    Calendar {
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
+
+        #[command(flatten)]
+        window: calendar::EventWindow,
+
+        /// Keep past events of the existing calendar file instead of overwriting it
+        #[arg(long)]
+        merge: bool,
+    },
+    /// Print the upcoming pickups as a feed for feed readers
+    Export {
+        #[arg(long, value_enum, default_value_t = feed::FeedFormat::Atom)]
+        format: feed::FeedFormat,
+
+        #[command(flatten)]
+        filter: calendar::GroupFilter,
+
+        #[command(flatten)]
+        window: calendar::EventWindow,
+    },
+    /// List invoices of the account
+    Invoices {
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+    /// Send reminders for pickups happening tomorrow
+    Notify(NotifyArgs),
+    /// Keep running, fetching and sending reminders on the schedules of the
+    /// [daemon] config
+    Daemon {
+        /// Serve the calendar, its feeds at /feed.xml and /feed.json,
+        /// Prometheus metrics at /metrics and a health check at /healthz on
+        /// this address, e.g. 127.0.0.1:9464
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<std::net::SocketAddr>,
+
+        /// Serve HTTPS with this PEM certificate chain
+        #[arg(long, value_name = "FILE", requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+
+        /// PKCS#8 PEM private key of the certificate
+        #[arg(long, value_name = "FILE", requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+    },
+    /// Write user service definitions running fetch and notify on the
+    /// schedules of the [daemon] config
+    InstallService {
+        /// Service manager, defaults to launchd on macOS and systemd elsewhere
+        #[arg(long, value_enum)]
+        manager: Option<service::ServiceManager>,
+
+        /// Print the definitions instead of installing them
+        #[arg(long)]
+        print: bool,
+    },
+    /// Compare two fetched service lists, by default the last two stored fetches
+    Diff {
+        /// Older services JSON file
+        old: Option<PathBuf>,
+
+        /// Newer services JSON file, defaults to the latest stored fetch
+        new: Option<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List past pickup dates recorded in the fetch history
+    History {
+        /// Only show services of this product group (e.g. BIO)
+        #[arg(long)]
+        group: Option<ProductGroup>,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Summarize costs of upcoming pickups per product group and customer number
+    Costs {
+        /// Number of months to include, starting today
+        #[arg(long, default_value_t = 12)]
+        months: u32,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Show service agreement details: container, interval, tariff and validity
+    Contract {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Read a calendar written by pjhoy back into JSON
+    Parse {
+        /// ICS file to read
+        file: PathBuf,
+    },
+    /// Work with ICS files
+    Ics {
+        #[command(subcommand)]
+        command: IcsCommand,
+    },
+    /// Project the yearly cost of each service from its interval and price
+    Forecast {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
 }
 
-/// Load trash schedule from trash_schedule.json file in data directory
-fn load_trash_services(data_dir: &Path) -> Result<Vec<TrashService>> {
-    let file_path = data_dir.join(SERVICES_FILE);
-    if !file_path.exists() {
-        return Err(anyhow::anyhow!(
-            "{} not found in data directory",
-            SERVICES_FILE
-        ));
+impl Commands {
+    /// Whether the command may write the session or the output files and
+    /// must not overlap with another run. The daemon locks for each job,
+    /// purge before it starts deleting and the commands reading the saved
+    /// services when they have to fetch them first.
+    fn needs_lock(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Doctor
+                | Commands::DebugBundle { .. }
+                | Commands::Purge { .. }
+                | Commands::Config { .. }
+                | Commands::Status { .. }
+                | Commands::StatusBar { .. }
+                | Commands::Next { .. }
+                | Commands::Cal { .. }
+                | Commands::Upcoming { .. }
+                | Commands::Today { .. }
+                | Commands::Tomorrow { .. }
+                | Commands::Watch(_)
+                | Commands::IsPickup { .. }
+                | Commands::Daemon { .. }
+                | Commands::InstallService { .. }
+                | Commands::Diff { .. }
+                | Commands::History { .. }
+                | Commands::Parse { .. }
+                | Commands::Ics { .. }
+        )
     }
+}
+
+/// Options of `pjhoy fetch`
+#[derive(clap::Args, Debug, Default)]
+struct FetchArgs {
+    /// Save parsed services JSON to data directory
+    #[arg(long = "save-json", short = 'j')]
+    save_parsed: bool,
+
+    /// Save original raw JSON response to data directory
+    #[arg(long = "save-original-json", short = 'r')]
+    save_original: bool,
 
-    let schedule_data =
-        std::fs::read_to_string(&file_path).context(format!("Failed to read {:?}", file_path))?;
+    /// Only fetch these customer numbers (e.g. 01,03) instead of the configured ones
+    #[arg(long = "customer", value_delimiter = ',', value_name = "NUMBERS")]
+    customers: Vec<String>,
 
-    let services: Vec<TrashService> =
-        serde_json::from_str(&schedule_data).context(format!("Failed to parse {:?}", file_path))?;
+    /// Read the raw response from a file saved with --save-original-json
+    /// instead of the extranet
+    #[arg(long, value_name = "FILE", conflicts_with = "customers")]
+    from_file: Option<PathBuf>,
 
+    #[command(flatten)]
+    filter: calendar::GroupFilter,
+
+    #[command(flatten)]
+    window: calendar::EventWindow,
+
+    /// Keep past events of the existing calendar file instead of overwriting it
+    #[arg(long)]
+    merge: bool,
+}
+
+/// Options of `pjhoy watch`
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Number of weeks to show, starting today
+    #[arg(long, default_value_t = 4)]
+    weeks: u32,
+
+    #[arg(long, value_enum, default_value_t = upcoming::GroupBy::Week)]
+    group_by: upcoming::GroupBy,
+
+    #[command(flatten)]
+    filter: calendar::GroupFilter,
+
+    /// Seconds between redraws from the saved services
+    #[arg(long, value_name = "SECS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+
+    /// Hours after which the services are fetched from the extranet again
+    #[arg(long, value_name = "HOURS", default_value_t = 6, value_parser = clap::value_parser!(u64).range(1..))]
+    fetch_hours: u64,
+}
+
+/// Options of `pjhoy notify`
+#[derive(clap::Args, Debug, Default)]
+struct NotifyArgs {
+    /// Send a digest of the coming week instead of tomorrow's reminders
+    #[arg(long)]
+    digest: bool,
+
+    /// Notify through ntfy
+    #[arg(long)]
+    ntfy: bool,
+
+    /// Notify by email
+    #[arg(long)]
+    email: bool,
+
+    /// Post to the Slack incoming webhook
+    #[arg(long)]
+    slack: bool,
+
+    /// Show a desktop notification
+    #[arg(long)]
+    desktop: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum IcsCommand {
+    /// Check a calendar for RFC 5545 problems, by default the generated one
+    Lint {
+        /// ICS file to check
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the config file with passwords and tokens hidden
+    Show,
+    /// Print the value of a setting, e.g. `calendar.emoji`
+    Get { key: String },
+    /// Change a setting, e.g. `customer_numbers '["00","01"]'`, after validating it
+    Set { key: String, value: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum CookiesCommand {
+    /// Write the session cookies in the Netscape cookies.txt format used by curl and wget
+    Export {
+        /// File to write instead of stdout
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionCommand {
+    /// Print the session as a single line to give to `session import`
+    Export,
+    /// Take over a session exported on another machine, read from stdin if not given
+    Import { session: Option<String> },
+}
+
+/// Load the services saved by the last fetch
+fn load_trash_services(storage: &dyn Storage) -> Result<Vec<TrashService>> {
+    let Some(schedule_data) = storage.read(SERVICES_FILE)? else {
+        return Err(anyhow::anyhow!(tr!("services-file-missing", SERVICES_FILE)));
+    };
+
+    let services: Vec<TrashService> = serde_json::from_slice(&schedule_data)
+        .with_context(|| tr!("parse-failed", storage.location(SERVICES_FILE)))?;
+
+    Ok(services)
+}
+
+/// The services saved by the last fetch, fetched and saved once when there
+/// are none so that later runs stay offline. The fetch holds the run lock in
+/// `state_dir`, as it writes the cookies and the services like `fetch` does.
+async fn cached_services(client: &mut PjhoyClient, state_dir: &Path) -> Result<Vec<TrashService>> {
+    let storage = client.storage.clone();
+    if storage.read(SERVICES_FILE)?.is_some() {
+        return load_trash_services(storage.as_ref());
+    }
+    let _lock = lock::RunLock::acquire(state_dir)?;
+    let raw = with_relogin(client, |client| Box::pin(client.fetch_trash_services())).await?;
+    let services = client.parse_services(&raw)?;
+    save_parsed_json(&services, storage.as_ref()).await?;
     Ok(services)
 }
 
-/// Save the parsed services JSON to the schedule file in the data directory
-async fn save_parsed_json(services: &[TrashService], data_dir: &Path) -> Result<()> {
-    let file_path = data_dir.join(SERVICES_FILE);
+/// Redraw the upcoming pickups from the saved services until Ctrl-C,
+/// fetching them again when they are older than `fetch_hours`. A failed
+/// fetch is shown above the table and retried after as long.
+async fn watch(
+    client: &mut PjhoyClient,
+    config: &config::Credentials,
+    args: WatchArgs,
+    state_dir: &Path,
+    ics_interval: Option<&str>,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+    output_path: &Path,
+) -> Result<()> {
+    let storage = client.storage.clone();
+    let options = calendar::EventOptions::from_config(config);
+    let fetch_every = std::time::Duration::from_secs(args.fetch_hours * 3600);
+    let mut last_attempt: Option<std::time::Instant> = None;
+    let mut fetch_error: Option<String> = None;
+
+    loop {
+        let stale = storage
+            .modified(SERVICES_FILE)
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|age| age >= fetch_every);
+        if stale && last_attempt.is_none_or(|at| at.elapsed() >= fetch_every) {
+            last_attempt = Some(std::time::Instant::now());
+            let result = async {
+                let _lock = lock::RunLock::acquire(state_dir)?;
+                let args = FetchArgs {
+                    save_parsed: true,
+                    ..Default::default()
+                };
+                fetch(client, config, args, ics_interval, dtstamp, output_path).await
+            }
+            .await;
+            fetch_error = result.err().map(|e| format!("{:#}", e));
+        }
+
+        let services = match storage.read(SERVICES_FILE)? {
+            Some(_) => args.filter.apply(&load_trash_services(storage.as_ref())?),
+            None => Vec::new(),
+        };
+        let now = chrono::Local::now();
+        let today = now.date_naive();
+        let until = today + chrono::Duration::weeks(args.weeks.into()) - chrono::Duration::days(1);
+        let table = upcoming::upcoming(&services, &options, today, until, args.group_by);
+        let fetched = storage.modified(SERVICES_FILE).map_or_else(
+            || "-".to_string(),
+            |modified| {
+                chrono::DateTime::<chrono::Local>::from(modified)
+                    .format("%d.%m.%Y %H:%M")
+                    .to_string()
+            },
+        );
+
+        // Clear the screen and draw from the top left corner
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{}",
+            tr!("watch-header", now.format("%d.%m.%Y %H:%M"), fetched)
+        );
+        if let Some(error) = &fetch_error {
+            println!("{}", tr!("watch-fetch-failed", error));
+        }
+        println!();
+        if table.groups.is_empty() {
+            println!("{}", tr!("watch-no-pickups", args.weeks));
+        } else {
+            print!("{}", table);
+        }
+        std::io::stdout().flush()?;
+
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(args.interval)) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// Print the groups picked up on `date`, one per line, returning the exit
+/// code of `today` and `tomorrow`
+async fn print_pickups_on(
+    client: &mut PjhoyClient,
+    config: &config::Credentials,
+    filter: &calendar::GroupFilter,
+    date: chrono::NaiveDate,
+    state_dir: &Path,
+) -> Result<u8> {
+    let services = filter.apply(&cached_services(client, state_dir).await?);
+    let options = calendar::EventOptions::from_config(config);
+    let pickups = statusbar::pickups_on(&services, &options, date);
+    for (icon, name) in &pickups {
+        println!("{} {}", icon, name);
+    }
+    Ok(if pickups.is_empty() {
+        exit::NO_PICKUP
+    } else {
+        exit::SUCCESS
+    })
+}
+
+/// When the services were last saved, for feeds; `dtstamp` when given for
+/// reproducible output
+fn saved_at(
+    storage: &dyn Storage,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+) -> chrono::DateTime<chrono::Utc> {
+    dtstamp
+        .or_else(|| storage.modified(SERVICES_FILE).map(chrono::DateTime::from))
+        .unwrap_or_else(chrono::Utc::now)
+}
+
+/// Load services from a parsed or raw services JSON file
+fn load_services_file(file_path: &Path) -> Result<Vec<TrashService>> {
+    let schedule_data = std::fs::read_to_string(file_path)
+        .with_context(|| tr!("read-failed", file_path.display()))?;
+
+    let services: Vec<TrashService> = serde_json::from_str(&schedule_data)
+        .with_context(|| tr!("parse-failed", file_path.display()))?;
+
+    Ok(services)
+}
+
+/// Save the parsed services JSON as the schedule of the last fetch
+async fn save_parsed_json(services: &[TrashService], storage: &dyn Storage) -> Result<()> {
     let json_string = serde_json::to_string_pretty(services)
         .context("Failed to serialize parsed services to JSON")?;
 
-    std::fs::write(&file_path, json_string)
-        .context(format!("Failed to write JSON to {:?}", file_path))?;
+    storage
+        .write(SERVICES_FILE, json_string.as_bytes())
+        .with_context(|| tr!("write-failed", storage.location(SERVICES_FILE)))?;
 
-    println!("Parsed services JSON saved to: {:?}", file_path);
+    report!("parsed-json-saved", storage.location(SERVICES_FILE));
 
     Ok(())
 }
 
-/// Save the raw JSON response to a file in the data directory
+/// Save the raw JSON response next to the parsed schedule
 async fn save_raw_json(
     raw_json: &serde_json::Value,
     filename: &str,
-    data_dir: &Path,
+    storage: &dyn Storage,
 ) -> Result<()> {
-    let file_path = data_dir.join(filename);
     let json_string =
         serde_json::to_string_pretty(raw_json).context("Failed to serialize raw JSON to string")?;
 
-    std::fs::write(&file_path, json_string)
-        .context(format!("Failed to write JSON to {:?}", file_path))?;
+    storage
+        .write(filename, json_string.as_bytes())
+        .with_context(|| tr!("write-failed", storage.location(filename)))?;
 
-    println!("Original raw JSON data saved to: {:?}", file_path);
+    report!("raw-json-saved", storage.location(filename));
 
     Ok(())
 }
 
+/// Quote a CSV field if it contains separators, quotes or newlines
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn invoices_csv(invoices: &[Invoice]) -> String {
+    let mut csv = String::from(
+        "invoice_number,customer_number,invoice_date,due_date,total_amount,status,reference\n",
+    );
+    for invoice in invoices {
+        let fields = [
+            invoice.invoice_number.clone(),
+            invoice.customer_number.clone().unwrap_or_default(),
+            invoice
+                .invoice_date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            invoice
+                .due_date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            invoice
+                .total_amount
+                .map(|amount| format!("{:.2}", amount))
+                .unwrap_or_default(),
+            invoice.status.clone().unwrap_or_default(),
+            invoice.reference.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Write the calendar file, keeping the past events of the existing one
+/// when merging
+fn write_calendar(
+    path: &Path,
+    calendar: String,
+    merge: bool,
+    config: &config::Credentials,
+) -> Result<()> {
+    let merge = merge
+        || config
+            .calendar
+            .as_ref()
+            .and_then(|calendar| calendar.merge)
+            .unwrap_or(false);
+
+    let content = if merge && path.exists() {
+        let existing =
+            std::fs::read_to_string(path).with_context(|| tr!("read-failed", path.display()))?;
+        calendar::merge_calendars(&existing, &calendar, chrono::Local::now().date_naive())?
+    } else {
+        calendar
+    };
+
+    let backups = config
+        .calendar
+        .as_ref()
+        .and_then(|calendar| calendar.backups)
+        .unwrap_or(0);
+    storage::write_atomic(path, content.as_bytes(), backups)
+        .with_context(|| tr!("calendar-write-failed"))?;
+    report!("calendar-saved", path.display());
+    Ok(())
+}
+
+/// Calendar event settings from the config, overridden by command line options
+fn event_options(
+    config: &config::Credentials,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+    window: &calendar::EventWindow,
+) -> calendar::EventOptions {
+    let options = calendar::EventOptions::from_config(config);
+    calendar::EventOptions {
+        dtstamp,
+        until: window.last_day(chrono::Local::now().date_naive()),
+        occurrences: window.occurrences.or(options.occurrences),
+        ..options
+    }
+}
+
+/// Deliver an alert of a fetch through the configured ntfy, email and
+/// Slack. A failing notifier is logged and does not keep the others from
+/// trying.
+async fn send_alert(
+    config: &config::Credentials,
+    notifier: &reqwest::Client,
+    message: &notify::Message,
+) {
+    let warn = |result: Result<()>| {
+        if let Err(e) = result {
+            tracing::warn!("{}", tr!("alert-failed", format!("{:#}", e)));
+        }
+    };
+    if let Some(ntfy_config) = &config.ntfy {
+        warn(notify::send_ntfy(notifier, ntfy_config, message).await);
+    }
+    if let Some(email_config) = &config.email {
+        warn(notify::send_email(email_config, message).await);
+    }
+    if let Some(slack_config) = &config.slack {
+        warn(notify::send_slack(notifier, slack_config, message).await);
+    }
+}
+
+/// Fetch the services and regenerate the calendar, returning the exit code.
+/// The configured hooks run after it either way.
+async fn fetch(
+    client: &mut PjhoyClient,
+    config: &config::Credentials,
+    args: FetchArgs,
+    ics_interval: Option<&str>,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+    output_path: &Path,
+) -> Result<u8> {
+    let result = update(client, config, args, ics_interval, dtstamp, output_path).await;
+
+    if let Some(hooks_config) = &config.hooks {
+        let failure;
+        let (report, run): (_, &[Hook]) = match &result {
+            Ok(report) if report.diff.as_ref().is_some_and(|diff| !diff.is_empty()) => {
+                (report, &[Hook::Change, Hook::FetchSuccess])
+            }
+            Ok(report) => (report, &[Hook::FetchSuccess]),
+            Err(e) => {
+                failure = hooks::FetchReport {
+                    exit_code: exit::from_error(e),
+                    error: Some(format!("{:#}", e)),
+                    ..Default::default()
+                };
+                (&failure, &[Hook::FetchFailure])
+            }
+        };
+        // A failing hook does not fail the fetch
+        for hook in run {
+            if let Err(e) = hooks::run(hooks_config, *hook, output_path, report).await {
+                tracing::warn!("{}", tr!("hook-failed", format!("{:#}", e)));
+            }
+        }
+    }
+
+    result.map(|report| report.exit_code)
+}
+
+/// The fetch itself, describing what it did for the hooks
+async fn update(
+    client: &mut PjhoyClient,
+    config: &config::Credentials,
+    args: FetchArgs,
+    ics_interval: Option<&str>,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+    output_path: &Path,
+) -> Result<hooks::FetchReport> {
+    let FetchArgs {
+        save_parsed,
+        save_original,
+        customers,
+        from_file,
+        filter,
+        window,
+        merge,
+    } = args;
+    let storage = client.storage.clone();
+    let mut exit_code = exit::SUCCESS;
+
+    let services_json = if let Some(file) = &from_file {
+        let text =
+            std::fs::read_to_string(file).with_context(|| tr!("read-failed", file.display()))?;
+        serde_json::from_str(&text).with_context(|| tr!("parse-failed", file.display()))?
+    } else if customers.is_empty() {
+        with_relogin(client, |client| Box::pin(client.fetch_trash_services())).await?
+    } else {
+        with_relogin(client, |client| {
+            let customers = customers.clone();
+            Box::pin(async move { client.fetch_services_for(&customers).await })
+        })
+        .await?
+    };
+
+    let mut services = client.parse_services(&services_json)?;
+
+    // Addresses only enrich the output, so a failure is not fatal
+    if from_file.is_none() {
+        match client.fetch_customers().await {
+            Ok(customers) => {
+                for service in &mut services {
+                    service.address = customers
+                        .iter()
+                        .find(|customer| customer.customer_number == service.customer_number)
+                        .and_then(|customer| customer.address());
+                }
+            }
+            Err(e) => {
+                tracing::warn!("{}", tr!("address-fetch-failed", format!("{:#}", e)))
+            }
+        }
+        report!("fetched-services", services.len());
+    } else {
+        report!("loaded-services", services.len());
+    }
+
+    // Compare against the previous fetch. A partial fetch would show the
+    // other customer numbers as removed, so it is kept out of the history,
+    // and so is a response read from a file.
+    let mut history = if customers.is_empty() && from_file.is_none() {
+        Some(HistoryStore::open(storage.as_ref())?)
+    } else {
+        None
+    };
+    let schedule_diff = match &history {
+        Some(history) => match history.latest_fetches(1)?.pop() {
+            Some(previous) => Some((
+                previous.fetched_at,
+                diff::diff_services(&history.load_fetch(previous.id)?, &services),
+            )),
+            None => None,
+        },
+        None => None,
+    };
+
+    let moved = schedule_diff.as_ref().map_or_else(Vec::new, |(_, diff)| {
+        notify::moved_pickups(
+            &diff.date_changes,
+            &services,
+            chrono::Local::now().date_naive(),
+        )
+    });
+    if let Some((previous_fetched_at, schedule_diff)) =
+        schedule_diff.as_ref().filter(|(_, d)| !d.is_empty())
+    {
+        exit_code = exit::SCHEDULE_CHANGED;
+        report!(
+            "schedule-changed",
+            previous_fetched_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M"),
+            schedule_diff.len()
+        );
+
+        // Tariffs change without notice, so price changes are not left
+        // to a --quiet log
+        for change in &schedule_diff.price_changes {
+            tracing::warn!("{}", tr!("price-changed", change));
+        }
+
+        // Likewise pickups moved by holidays, unlike the next date following
+        // a pickup
+        for pickup in &moved {
+            tracing::warn!(
+                "{}",
+                tr!(
+                    "pickup-moved",
+                    pickup.name,
+                    pickup.from.format("%d.%m.%Y"),
+                    pickup.to.format("%d.%m.%Y")
+                )
+            );
+        }
+    }
+
+    let ics_interval = ics_interval
+        .or(config.ics_interval.as_deref())
+        .unwrap_or("P1D");
+
+    let calendar_services = filter.apply(&services);
+    let calendar = calendar::generate_calendar(
+        &calendar_services,
+        Some(ics_interval),
+        &event_options(config, dtstamp, &window),
+    )?;
+
+    // Save calendar file
+    write_calendar(output_path, calendar.to_string(), merge, config)?;
+
+    // Save parsed JSON if requested
+    if save_parsed {
+        save_parsed_json(&services, storage.as_ref()).await?;
+    }
+
+    // Save original JSON if requested
+    if save_original {
+        save_raw_json(&services_json, SERVICES_FULL_FILE, storage.as_ref()).await?;
+    }
+
+    // Stored only once the calendar and the services are written, so that a
+    // failed run is diffed again by the next one
+    if let Some(history) = &mut history {
+        history.record(&services, chrono::Utc::now())?;
+    }
+
+    // Published and notified last and without failing the fetch, so that an
+    // unreachable endpoint costs neither the calendar nor the saved services
+    if let Some(mqtt_config) = &config.mqtt {
+        match mqtt::publish_services(mqtt_config, &config.username, &services).await {
+            Ok(()) => report!("mqtt-published", mqtt_config.host),
+            Err(e) => tracing::warn!("{}", tr!("mqtt-failed", format!("{:#}", e))),
+        }
+    }
+    if let Some(schedule_diff) = schedule_diff
+        .as_ref()
+        .map(|(_, diff)| diff)
+        .filter(|diff| !diff.is_empty())
+    {
+        let notifier = client::notifier_client(config)?;
+        if let Some(webhook_config) = &config.webhook {
+            match notify::send_webhook(&notifier, webhook_config, schedule_diff).await {
+                Ok(()) => report!("webhook-notified", webhook_config.url),
+                Err(e) => tracing::warn!("{}", tr!("webhook-failed", format!("{:#}", e))),
+            }
+        }
+        if let Some(message) = notify::price_change_message(&schedule_diff.price_changes) {
+            send_alert(config, &notifier, &message).await;
+        }
+        for message in notify::moved_messages(&moved) {
+            send_alert(config, &notifier, &message).await;
+        }
+    }
+
+    Ok(hooks::FetchReport {
+        exit_code,
+        services: services.len(),
+        services_file: save_parsed.then(|| storage.path(SERVICES_FILE)).flatten(),
+        diff: schedule_diff.map(|(_, diff)| diff),
+        error: None,
+    })
+}
+
+/// Send reminders of the pickups in the saved services
+async fn notify_pickups(
+    config: &config::Credentials,
+    storage: &dyn Storage,
+    args: NotifyArgs,
+) -> Result<()> {
+    let NotifyArgs {
+        digest,
+        ntfy,
+        email,
+        slack,
+        desktop,
+    } = args;
+
+    let services = load_trash_services(storage)?;
+
+    // Without explicit channels, use every configured notifier
+    let all_channels = !(ntfy || email || slack || desktop);
+    let ntfy_config = config.ntfy.as_ref().filter(|_| ntfy || all_channels);
+    let email_config = config.email.as_ref().filter(|_| email || all_channels);
+    let slack_config = config.slack.as_ref().filter(|_| slack || all_channels);
+
+    if ntfy && ntfy_config.is_none() {
+        return Err(anyhow::anyhow!(tr!("ntfy-not-configured")));
+    }
+    if email && email_config.is_none() {
+        return Err(anyhow::anyhow!(tr!("email-not-configured")));
+    }
+    if slack && slack_config.is_none() {
+        return Err(anyhow::anyhow!(tr!("slack-not-configured")));
+    }
+    if ntfy_config.is_none() && email_config.is_none() && slack_config.is_none() && !desktop {
+        return Err(anyhow::anyhow!(tr!("no-notifiers")));
+    }
+
+    let today = chrono::Local::now().date_naive();
+    let messages = if digest {
+        let reminders = notify::upcoming(&services, today, today + chrono::Duration::days(7));
+        notify::digest_message(&reminders).into_iter().collect()
+    } else {
+        notify::reminder_messages(&notify::due_tomorrow(&services, today))
+    };
+
+    let notifier = client::notifier_client(config)?;
+    for message in &messages {
+        if let Some(ntfy_config) = ntfy_config {
+            notify::send_ntfy(&notifier, ntfy_config, message).await?;
+        }
+        if let Some(email_config) = email_config {
+            notify::send_email(email_config, message).await?;
+        }
+        if let Some(slack_config) = slack_config {
+            notify::send_slack(&notifier, slack_config, message).await?;
+        }
+        if desktop {
+            notify::send_desktop(message)?;
+        }
+    }
+
+    report!("notifications-sent", messages.len());
+
+    Ok(())
+}
+
+/// An account the daemon runs jobs for, the main one or a tenant
+struct DaemonAccount {
+    /// Tenant name, `None` for the main account
+    name: Option<String>,
+    client: PjhoyClient,
+    config: config::Credentials,
+    schedules: daemon::Schedules,
+    state_dir: PathBuf,
+    output_path: PathBuf,
+    metrics: Arc<metrics::Metrics>,
+    /// Fetch requests of the server for a stale calendar
+    refresh: Arc<tokio::sync::Notify>,
+}
+
+impl DaemonAccount {
+    fn new(
+        name: Option<String>,
+        client: PjhoyClient,
+        config: config::Credentials,
+        state_dir: PathBuf,
+        output_path: PathBuf,
+    ) -> Result<Self> {
+        let schedules = daemon::Schedules::from_config(&config)?;
+        Ok(Self {
+            name,
+            client,
+            config,
+            schedules,
+            state_dir,
+            output_path,
+            metrics: Arc::default(),
+            refresh: Arc::default(),
+        })
+    }
+
+    /// The tenants of `[tenants]`, each keeping its state and calendar in
+    /// its config directory unless the config says otherwise
+    fn tenants(config: &config::Credentials) -> Result<Vec<Self>> {
+        let mut tenants = Vec::new();
+        for (name, dir) in config.tenants.iter().flatten() {
+            let dir = std::path::absolute(dir)
+                .context(format!("Invalid directory of tenant {}", name))?;
+            let mut tenant = config::load_tenant_config(&dir)
+                .with_context(|| format!("Failed to load the config of tenant {}", name))?;
+            tenant.lang = config.lang;
+            let state_dir = tenant.state_dir.clone().unwrap_or_else(|| dir.clone());
+            std::fs::create_dir_all(&state_dir)
+                .context(format!("Could not create state directory {:?}", state_dir))?;
+            let client = PjhoyClient::new(tenant.clone(), state_dir.clone())?;
+            let output_path = dir.join(ICS_FILE);
+            tenants.push(Self::new(
+                Some(name.clone()),
+                client,
+                tenant,
+                state_dir,
+                output_path,
+            )?);
+        }
+        Ok(tenants)
+    }
+
+    /// The calendar at `/pjhoy.ics` and the feeds at `/feed.xml` and
+    /// `/feed.json` for the main account, at `/calendars/<name>.ics`,
+    /// `.xml` and `.json` for tenants
+    fn served_calendar(&self) -> server::ServedCalendar {
+        let (url_path, feed_path) = match &self.name {
+            Some(name) => (
+                format!("calendars/{}.ics", name),
+                format!("calendars/{}", name),
+            ),
+            None => (
+                self.output_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| ICS_FILE.to_string()),
+                "feed".to_string(),
+            ),
+        };
+        let ttl_hours = self
+            .config
+            .daemon
+            .as_ref()
+            .and_then(|daemon| daemon.ttl_hours);
+        server::ServedCalendar {
+            url_path,
+            feed_path,
+            path: self.output_path.clone(),
+            token: self
+                .config
+                .calendar
+                .as_ref()
+                .and_then(|calendar| calendar.token.clone()),
+            refresh: ttl_hours.map(|hours| {
+                let ttl = std::time::Duration::from_secs(u64::from(hours) * 3600);
+                server::Refresh::new(ttl, self.refresh.clone())
+            }),
+            storage: self.client.storage.clone(),
+            options: calendar::EventOptions::from_config(&self.config),
+        }
+    }
+
+    /// Run the fetch and notify jobs on the schedules of the config. A
+    /// failed job is logged and retried on its next run.
+    async fn run_jobs(
+        &mut self,
+        ics_interval: Option<&str>,
+        dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) {
+        let storage = self.client.storage.clone();
+        // Tells the tenants apart in messages
+        let label = |job: daemon::Job| match &self.name {
+            Some(name) => format!("{} {}", name, job),
+            None => job.to_string(),
+        };
+        loop {
+            let next = self.schedules.next_job(chrono::Local::now());
+            if let Some((when, job)) = next {
+                report!(
+                    "daemon-next-run",
+                    label(job),
+                    when.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            let scheduled = async {
+                match next {
+                    Some((when, job)) => {
+                        let wait = (when - chrono::Local::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep(wait).await;
+                        job
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            let job = tokio::select! {
+                job = scheduled => job,
+                _ = self.refresh.notified() => {
+                    report!("daemon-refresh", label(daemon::Job::Fetch));
+                    daemon::Job::Fetch
+                }
+            };
+            let job_name = label(job);
+
+            let _lock = match lock::RunLock::acquire(&self.state_dir) {
+                Ok(lock) => lock,
+                Err(e) => {
+                    tracing::error!("{}", tr!("daemon-job-failed", job_name, format!("{:#}", e)));
+                    continue;
+                }
+            };
+            let result = match job {
+                daemon::Job::Fetch => {
+                    // Reminders are sent from the saved services
+                    let args = FetchArgs {
+                        save_parsed: true,
+                        ..Default::default()
+                    };
+                    let result = fetch(
+                        &mut self.client,
+                        &self.config,
+                        args,
+                        ics_interval,
+                        dtstamp,
+                        &self.output_path,
+                    )
+                    .await;
+                    self.metrics.record_fetch(result.is_ok());
+                    if result
+                        .as_ref()
+                        .is_err_and(|e| exit::from_error(e) == exit::AUTH_FAILURE)
+                    {
+                        self.metrics.set_session(metrics::SessionState::Expired);
+                    }
+                    // Nobody watches the daemon, so failures are alerted
+                    if let Err(e) = &result {
+                        let message = notify::failure_message(&format!("{:#}", e));
+                        match client::notifier_client(&self.config) {
+                            Ok(notifier) => send_alert(&self.config, &notifier, &message).await,
+                            Err(e) => {
+                                tracing::warn!("{}", tr!("alert-failed", format!("{:#}", e)))
+                            }
+                        }
+                    }
+                    result.map(|_| ())
+                }
+                daemon::Job::Notify => {
+                    notify_pickups(&self.config, storage.as_ref(), NotifyArgs::default()).await
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("{}", tr!("daemon-job-failed", job_name, format!("{:#}", e)));
+            }
+        }
+    }
+}
+
+/// Run the jobs of the main account and the tenants side by side until
+/// interrupted, serving their calendars and the metrics of the main
+/// account when `listen` is given
+async fn run_daemon(
+    mut main: DaemonAccount,
+    mut tenants: Vec<DaemonAccount>,
+    listen: Option<server::Listen>,
+    ics_interval: Option<&str>,
+    dtstamp: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<()> {
+    if let Some(listen) = listen {
+        let storage = main.client.storage.clone();
+        if let Some(fetch) = HistoryStore::open(storage.as_ref())?
+            .latest_fetches(1)?
+            .pop()
+        {
+            main.metrics.set_last_fetch(fetch.fetched_at);
+        }
+        main.metrics
+            .set_session(match main.client.session_valid().await {
+                Ok(true) => metrics::SessionState::Valid,
+                Ok(false) => metrics::SessionState::Expired,
+                Err(_) => metrics::SessionState::Unknown,
+            });
+        let state = server::ServerState {
+            storage,
+            metrics: main.metrics.clone(),
+            calendars: std::iter::once(&main)
+                .chain(&tenants)
+                .map(DaemonAccount::served_calendar)
+                .collect(),
+            fetch_schedule: main.schedules.fetch().clone(),
+        };
+        let url = listen.url();
+        tokio::spawn(server::bind(listen, Arc::new(state)).await?);
+        report!("server-listening", url);
+    }
+
+    let jobs = std::iter::once(&mut main)
+        .chain(&mut tenants)
+        .map(|account| account.run_jobs(ics_interval, dtstamp));
+    tokio::select! {
+        _ = futures::future::join_all(jobs) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
+
+/// Move the files older versions kept in the data directory over to the
+/// state directory. The moves hold the run lock, as a fetch or the daemon
+/// may be writing the files.
+fn move_state_files(data_dir: &Path, state_dir: &Path) -> Result<()> {
+    if data_dir == state_dir {
+        return Ok(());
+    }
+    let moves: Vec<(PathBuf, PathBuf)> = [
+        cookies::COOKIES_FILE,
+        cookies::LEGACY_COOKIES_FILE,
+        history::HISTORY_FILE,
+        SERVICES_FILE,
+        SERVICES_FULL_FILE,
+        client::FAILED_RESPONSE_FILE,
+    ]
+    .into_iter()
+    .map(|name| (data_dir.join(name), state_dir.join(name)))
+    .filter(|(from, to)| from.exists() && !to.exists())
+    .collect();
+    if moves.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock::RunLock::acquire(state_dir)?;
+    for (from, to) in moves {
+        // Checked again now that no other run is writing them
+        if !from.exists() || to.exists() {
+            continue;
+        }
+        // Renaming fails across filesystems
+        std::fs::rename(&from, &to)
+            .or_else(|_| std::fs::copy(&from, &to).and_then(|_| std::fs::remove_file(&from)))
+            .context(format!("Failed to move {:?} to {:?}", from, to))?;
+        report!("state-file-moved", from.display(), to.display());
+    }
+    Ok(())
+}
+
+/// Run an authenticated request, logging in and retrying once if the session has expired
+async fn with_relogin<T>(
+    client: &mut PjhoyClient,
+    request: impl for<'a> Fn(&'a PjhoyClient) -> Pin<Box<dyn Future<Output = error::Result<T>> + 'a>>,
+) -> Result<T> {
+    match request(client).await {
+        Ok(value) => Ok(value),
+        Err(error::PjhoyError::SessionExpired) => {
+            report!("session-expired");
+            client
+                .login()
+                .await
+                .with_context(|| tr!("login-retry-failed"))?;
+            report!("login-retry");
+            request(client)
+                .await
+                .with_context(|| tr!("request-failed-after-login"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} {} ", question, tr!("yes-no"));
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_lowercase().as_str(),
+        "y" | "yes" | "k" | "kyllä"
+    ))
+}
+
+/// Log to stderr, and with `--log-file` also with timestamps to the file,
+/// where the progress messages go as well
+fn init_logging(verbose: u8, log_file: Option<&Path>, rotation: logfile::Rotation) -> Result<()> {
+    let filter = |default: &str| {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default))
+    };
+    let stderr_filter = filter(match verbose {
+        0 => "warn",
+        1 => "warn,pjhoy=debug",
+        _ => "debug,pjhoy=trace",
+    })
+    // Printed to stdout already
+    .add_directive(format!("{}=off", output::REPORT_TARGET).parse()?);
+    let stderr = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let stderr = if verbose == 0 {
+        stderr.without_time().with_target(false).boxed()
+    } else {
+        stderr.boxed()
+    };
+
+    let file = match log_file {
+        Some(path) => {
+            let file = logfile::RotatingFile::open(path.to_path_buf(), rotation)
+                .context(format!("Failed to open log file {:?}", path))?;
+            let file_filter = filter(match verbose {
+                0 => "warn,pjhoy=info",
+                1 => "warn,pjhoy=debug",
+                _ => "debug,pjhoy=trace",
+            });
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(std::sync::Mutex::new(file))
+                    .with_ansi(false)
+                    .with_filter(file_filter),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr.with_filter(stderr_filter))
+        .with(file)
+        .init();
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(if e.use_stderr() {
+                exit::USAGE
+            } else {
+                exit::SUCCESS
+            });
+        }
+    };
+
+    match run(cli).await {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            tracing::error!(target: output::REPORT_TARGET, "{:#}", e);
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit::from_error(&e))
+        }
+    }
+}
+
+/// Run the command, returning the exit code of a successful run
+async fn run(cli: Cli) -> Result<u8> {
+    let rotation = logfile::Rotation {
+        max_bytes: cli.log_max_size.map(|megabytes| megabytes * 1024 * 1024),
+        max_age: cli
+            .log_max_age
+            .map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60)),
+        keep: cli.log_keep,
+    };
+    init_logging(cli.verbose, cli.log_file.as_deref(), rotation)?;
+    output::set_color(cli.color);
+    output::set_mode(if cli.porcelain {
+        output::Mode::Porcelain
+    } else if cli.quiet
+        || matches!(
+            cli.command,
+            Commands::StatusBar { .. }
+                | Commands::Watch(_)
+                | Commands::IsPickup { .. }
+                | Commands::Today { .. }
+                | Commands::Tomorrow { .. }
+                | Commands::Next {
+                    format: OutputFormat::Json | OutputFormat::Jsonl
+                }
+                | Commands::Upcoming {
+                    format: OutputFormat::Json | OutputFormat::Jsonl,
+                    ..
+                }
+        )
+    {
+        // Status bars and sensors take whatever is printed as their value
+        output::Mode::Quiet
+    } else {
+        output::Mode::Normal
+    });
 
     // Setup state
     let proj_dirs = config::get_project_dirs()?;
@@ -108,75 +1577,410 @@ async fn main() -> Result<()> {
     let config_dir = proj_dirs.config_dir().to_path_buf();
     std::fs::create_dir_all(&config_dir).context("Could not create config directory")?;
 
-    let data_dir = proj_dirs.data_dir().to_path_buf();
+    // Resolved so that messages and generated services show full paths,
+    // not ones relative to wherever cron happened to start
+    let data_dir = match &cli.data_dir {
+        Some(dir) => {
+            std::path::absolute(dir).context(format!("Invalid data directory {:?}", dir))?
+        }
+        None => proj_dirs.data_dir().to_path_buf(),
+    };
     std::fs::create_dir_all(&data_dir).context("Could not create data directory")?;
+    let output_path = match &cli.output {
+        Some(path) => {
+            std::path::absolute(path).context(format!("Invalid output path {:?}", path))?
+        }
+        None => data_dir.join(ICS_FILE),
+    };
+
+    let password = if cli.password_stdin {
+        let mut password = String::new();
+        std::io::stdin()
+            .read_line(&mut password)
+            .context("Failed to read password from stdin")?;
+        Some(password.trim_end_matches(['\n', '\r']).to_string())
+    } else {
+        None
+    };
+    let config = load_config(&config_dir, password);
+
+    let lang = cli
+        .lang
+        .or(config.as_ref().ok().and_then(|config| config.lang))
+        .or_else(Language::from_locale)
+        .unwrap_or_default();
+    i18n::set_language(lang);
+
+    let state_dir = match cli.state_dir.clone().or_else(|| {
+        config
+            .as_ref()
+            .ok()
+            .and_then(|config| config.state_dir.clone())
+    }) {
+        Some(dir) => {
+            std::path::absolute(&dir).context(format!("Invalid state directory {:?}", dir))?
+        }
+        None => config::default_state_dir(&proj_dirs),
+    };
+    std::fs::create_dir_all(&state_dir).context("Could not create state directory")?;
+    move_state_files(&data_dir, &state_dir)?;
+
+    // Diagnostics must work without a valid config
+    if let Commands::Doctor = cli.command {
+        let checks = doctor::diagnose(&config_dir, &state_dir, &output_path).await;
+        for check in &checks {
+            println!("{}", check);
+        }
+        let failed = checks.iter().filter(|check| !check.passed).count();
+        if failed > 0 {
+            return Err(anyhow::anyhow!(tr!("doctor-failed", failed)));
+        }
+        return Ok(exit::SUCCESS);
+    }
+
+    #[cfg(feature = "self-update")]
+    if let Commands::SelfUpdate { check } = cli.command {
+        match update::check().await? {
+            None => report!("up-to-date", env!("CARGO_PKG_VERSION")),
+            Some(update) if check => report!("update-available", update.version),
+            Some(update) => {
+                let exe = update::install(&update).await?;
+                report!("updated", update.version, exe.display());
+            }
+        }
+        return Ok(exit::SUCCESS);
+    }
+
+    // Bug reports are often about a broken config
+    if let Commands::DebugBundle { file } = &cli.command {
+        let file = file.clone().unwrap_or_else(|| {
+            let time = chrono::Local::now().format("%Y%m%d-%H%M%S");
+            PathBuf::from(format!("pjhoy-debug-{}.tar.gz", time))
+        });
+        let bundle =
+            bundle::create(&config_dir, &state_dir, &output_path, config.as_ref().ok()).await?;
+        std::fs::write(&file, bundle).context(format!("Failed to write {:?}", file))?;
+        report!("debug-bundle-saved", file.display());
+        return Ok(exit::SUCCESS);
+    }
+
+    // Everything goes, whatever state the config is in
+    if let Commands::Purge {
+        config: with_config,
+        yes,
+    } = cli.command
+    {
+        let _lock = lock::RunLock::acquire(&state_dir)?;
+        let config_dir = with_config.then_some(config_dir.as_path());
+        let files = purge::stored_files(&state_dir, &output_path, config_dir);
+        if files.is_empty() {
+            report!("purge-nothing");
+            return Ok(exit::SUCCESS);
+        }
+        for file in &files {
+            println!("{}", file.display());
+        }
+        if yes || confirm(&tr!("purge-confirm", files.len()))? {
+            let mut dirs = vec![state_dir.as_path(), data_dir.as_path()];
+            dirs.extend(config_dir);
+            purge::delete(&files, &dirs)?;
+            report!("purge-done", files.len());
+        }
+        return Ok(exit::SUCCESS);
+    }
+
+    // The config command is used to fix an invalid config
+    if let Commands::Config { command } = &cli.command {
+        match command {
+            ConfigCommand::Show => print!("{}", config::show_config(&config_dir)?),
+            ConfigCommand::Get { key } => {
+                println!("{}", config::get_config_value(&config_dir, key)?)
+            }
+            ConfigCommand::Set { key, value } => {
+                config::set_config_value(&config_dir, key, value)?;
+                report!("config-saved", key);
+            }
+        }
+        return Ok(exit::SUCCESS);
+    }
+
+    let mut config = config?;
+    config.lang = Some(lang);
+
+    // Command line options take precedence over the config file
+    if cli.connect_timeout.is_some() || cli.timeout.is_some() {
+        let http = config.http.get_or_insert_with(Default::default);
+        http.connect_timeout_secs = cli.connect_timeout.or(http.connect_timeout_secs);
+        http.timeout_secs = cli.timeout.or(http.timeout_secs);
+    }
+    if cli.no_emoji {
+        config.calendar.get_or_insert_with(Default::default).emoji = Some(false);
+    }
+    // Overlapping runs would corrupt the cookies and the calendar
+    let _lock = if cli.command.needs_lock() {
+        Some(lock::RunLock::acquire(&state_dir)?)
+    } else {
+        None
+    };
+    let mut client = match (&cli.record, &cli.replay) {
+        (None, None) => PjhoyClient::new(config.clone(), state_dir.clone())?,
+        (Some(dir), _) => PjhoyClientBuilder::from_config(
+            config.clone(),
+            Arc::new(FileStorage::new(state_dir.clone())),
+        )?
+        .cassette(Cassette::record(dir)?)
+        .build()?,
+        // A replayed login must not replace the real session
+        (None, Some(dir)) => {
+            PjhoyClientBuilder::from_config(config.clone(), Arc::new(MemoryStorage::default()))?
+                .cassette(Cassette::replay(dir)?)
+                .build()?
+        }
+    };
+    let storage = client.storage.clone();
 
-    let config = load_config(&config_dir)?;
-    let mut client = PjhoyClient::new(config.clone(), data_dir.clone())?;
+    let dtstamp = match cli.dtstamp {
+        Some(dtstamp) => Some(dtstamp),
+        None => calendar::source_date_epoch()?,
+    };
 
-    // Determine output path for ICS file
-    let output_path = cli.output.unwrap_or_else(|| data_dir.join("pjhoy.ics"));
+    let mut exit_code = exit::SUCCESS;
 
     match cli.command {
-        Commands::Login => {
-            client.login().await?;
-            println!("Login successful and cookies saved.");
+        Commands::Login { import_cookies } => match import_cookies {
+            Some(file) => {
+                let text =
+                    std::fs::read_to_string(&file).context(format!("Failed to read {:?}", file))?;
+                let count = client.import_cookies(&text).await?;
+                report!("cookies-imported", count);
+            }
+            None => {
+                client.login().await?;
+                report!("login-ok");
+            }
+        },
+        Commands::Doctor
+        | Commands::DebugBundle { .. }
+        | Commands::Purge { .. }
+        | Commands::Config { .. } => {
+            unreachable!("handled before loading the config")
+        }
+        #[cfg(feature = "self-update")]
+        Commands::SelfUpdate { .. } => unreachable!("handled before loading the config"),
+        Commands::Logout => {
+            client.logout().await?;
+            report!("logged-out");
         }
-        Commands::Fetch {
-            save_parsed,
-            save_original,
+        Commands::Cookies {
+            command: CookiesCommand::Export { file },
         } => {
-            let services_json = match client.fetch_trash_services().await {
-                Ok(json) => json,
-                Err(e) => {
-                    if e.downcast_ref::<SessionExpired>().is_some() {
-                        println!("Session expired, attempting to login...");
-                        client
-                            .login()
-                            .await
-                            .context("Failed to login during retry")?;
-                        println!("Login successful, retrying fetch...");
-                        client
-                            .fetch_trash_services()
-                            .await
-                            .context("Failed to fetch services after login")?
-                    } else {
-                        return Err(e);
-                    }
+            let cookies = client.cookie_jar.to_netscape();
+            match file {
+                Some(file) => {
+                    storage::write_atomic(&file, cookies.as_bytes(), 0)
+                        .context(format!("Failed to write {:?}", file))?;
+                    report!("cookies-exported", file.display());
                 }
+                None => print!("{}", cookies),
+            }
+        }
+        Commands::Session { command } => match command {
+            SessionCommand::Export => println!("{}", client.cookie_jar.export_session()?),
+            SessionCommand::Import { session } => {
+                let session = match session {
+                    Some(session) => session,
+                    None => {
+                        let mut session = String::new();
+                        std::io::stdin()
+                            .read_to_string(&mut session)
+                            .context("Failed to read the session from stdin")?;
+                        session
+                    }
+                };
+                let count = client.import_session(&session).await?;
+                report!("session-imported", count);
+            }
+        },
+        Commands::Whoami { format } => {
+            let customers =
+                with_relogin(&mut client, |client| Box::pin(client.fetch_customers())).await?;
+            let account = account::Account {
+                username: config.username.clone(),
+                customers,
             };
 
-            let services: Vec<TrashService> = serde_json::from_value(services_json.clone())?;
-
-            println!("Fetched {} trash services", services.len());
+            match format {
+                OutputFormat::Text => print!("{}", account),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&account)?),
+                OutputFormat::Jsonl => output::print_json_lines(&account.customers)?,
+            }
+        }
+        Commands::Status { format } => {
+            let status = status::Status {
+                session_valid: client.session_valid().await?,
+                cookies_saved_at: client.cookies_saved_at().map(chrono::DateTime::from),
+                last_fetched_at: HistoryStore::open(storage.as_ref())?
+                    .latest_fetches(1)?
+                    .pop()
+                    .map(|fetch| fetch.fetched_at),
+            };
 
-            let ics_interval = cli
-                .ics_interval
-                .as_deref()
-                .or(config.ics_interval.as_deref())
-                .unwrap_or("P1D");
+            match format {
+                OutputFormat::Text => print!("{}", status),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&status)?),
+                OutputFormat::Jsonl => output::print_json_lines([&status])?,
+            }
+            if !status.session_valid {
+                exit_code = exit::AUTH_FAILURE;
+            }
+        }
+        Commands::StatusBar { format } => {
+            let services = cached_services(&mut client, &state_dir).await?;
+            let options = calendar::EventOptions::from_config(&config);
+            let today = chrono::Local::now().date_naive();
+            let next = statusbar::next_pickup(&services, &options, today);
+            println!("{}", statusbar::render(next.as_ref(), format));
+        }
+        Commands::Next { format } => {
+            let services = cached_services(&mut client, &state_dir).await?;
+            let today = chrono::Local::now().date_naive();
+            let next = statusbar::next_by_group(&services, today);
 
-            let calendar = calendar::generate_calendar(&services, Some(ics_interval))?;
+            match format {
+                OutputFormat::Text => {
+                    for (group, pickup) in &next {
+                        let color = group
+                            .parse::<ProductGroup>()
+                            .ok()
+                            .and_then(|group| group.color());
+                        println!(
+                            "{} {}  {}",
+                            output::paint(&format!("{:<6}", group), color),
+                            pickup.date.format("%d.%m.%Y"),
+                            statusbar::when(pickup.in_days)
+                        );
+                    }
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&next)?),
+                OutputFormat::Jsonl => {
+                    output::print_json_lines(next.iter().map(|(group, pickup)| {
+                        serde_json::json!({
+                            "group": group,
+                            "date": pickup.date,
+                            "in_days": pickup.in_days,
+                        })
+                    }))?
+                }
+            }
+        }
+        Commands::Upcoming {
+            weeks,
+            group_by,
+            filter,
+            format,
+        } => {
+            let services = filter.apply(&cached_services(&mut client, &state_dir).await?);
+            let options = calendar::EventOptions::from_config(&config);
+            let today = chrono::Local::now().date_naive();
+            let until = today + chrono::Duration::weeks(weeks.into()) - chrono::Duration::days(1);
+            let table = upcoming::upcoming(&services, &options, today, until, group_by);
 
-            // Save calendar file
-            let calendar_content = calendar.to_string();
-            std::fs::write(&output_path, calendar_content)
-                .context("Failed to write calendar file")?;
-            println!("Calendar saved to: {:?}", output_path);
+            match format {
+                OutputFormat::Text => print!("{}", table),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&table)?),
+                OutputFormat::Jsonl => {
+                    output::print_json_lines(table.groups.iter().flat_map(|group| &group.pickups))?
+                }
+            }
+        }
+        Commands::Watch(args) => {
+            watch(
+                &mut client,
+                &config,
+                args,
+                &state_dir,
+                cli.ics_interval.as_deref(),
+                dtstamp,
+                &output_path,
+            )
+            .await?;
+        }
+        Commands::Cal { month, filter } => {
+            let services = filter.apply(&cached_services(&mut client, &state_dir).await?);
+            let options = calendar::EventOptions::from_config(&config);
+            let first =
+                month.unwrap_or_else(|| month::first_of_month(chrono::Local::now().date_naive()));
+            print!("{}", month::render(&services, &options, first));
+        }
+        Commands::Today { filter } => {
+            let today = chrono::Local::now().date_naive();
+            exit_code = print_pickups_on(&mut client, &config, &filter, today, &state_dir).await?;
+        }
+        Commands::Tomorrow { filter } => {
+            let tomorrow = chrono::Local::now().date_naive() + chrono::Duration::days(1);
+            exit_code =
+                print_pickups_on(&mut client, &config, &filter, tomorrow, &state_dir).await?;
+        }
+        Commands::IsPickup {
+            tomorrow,
+            date,
+            group,
+        } => {
+            let today = chrono::Local::now().date_naive();
+            let date = match (date, tomorrow) {
+                (Some(date), _) => date,
+                (None, true) => today + chrono::Duration::days(1),
+                (None, false) => today,
+            };
+            let filter = calendar::GroupFilter {
+                only: group,
+                ..Default::default()
+            };
+            let services = filter.apply(&cached_services(&mut client, &state_dir).await?);
+            if !statusbar::is_pickup_day(&services, date) {
+                exit_code = exit::NO_PICKUP;
+            }
+        }
+        Commands::Discover { max, yes } => {
+            client.login().await?;
+            let customer_numbers = client.discover_customer_numbers(max).await?;
 
-            // Save parsed JSON if requested
-            if save_parsed {
-                save_parsed_json(&services, &data_dir).await?;
+            if customer_numbers.is_empty() {
+                return Err(anyhow::anyhow!(tr!(
+                    "no-customer-numbers",
+                    format!("{:02}", max)
+                )));
             }
+            report!("found-customer-numbers", customer_numbers.join(", "));
 
-            // Save original JSON if requested
-            if save_original {
-                save_raw_json(&services_json, SERVICES_FULL_FILE, &data_dir).await?;
+            if customer_numbers == config.customer_numbers {
+                report!("config-up-to-date");
+            } else if yes || confirm(&tr!("save-customer-numbers"))? {
+                config::save_customer_numbers(&config_dir, &customer_numbers)?;
+                report!(
+                    "customer-numbers-saved",
+                    config_dir.join("config.toml").display()
+                );
             }
         }
-        Commands::Calendar => {
+        Commands::Fetch(args) => {
+            exit_code = fetch(
+                &mut client,
+                &config,
+                args,
+                cli.ics_interval.as_deref(),
+                dtstamp,
+                &output_path,
+            )
+            .await?;
+        }
+        Commands::Calendar {
+            filter,
+            window,
+            merge,
+        } => {
             // Load trash schedule from data directory
-            let services = load_trash_services(&data_dir)?;
+            let services = load_trash_services(storage.as_ref())?;
 
             // Generate calendar from the loaded services
             let ics_interval = cli
@@ -185,16 +1989,241 @@ async fn main() -> Result<()> {
                 .or(config.ics_interval.as_deref())
                 .unwrap_or("P1D");
 
-            let calendar = calendar::generate_calendar(&services, Some(ics_interval))?;
+            let calendar_services = filter.apply(&services);
+            let calendar = calendar::generate_calendar(
+                &calendar_services,
+                Some(ics_interval),
+                &event_options(&config, dtstamp, &window),
+            )?;
 
             // Save calendar
-            let calendar_content = calendar.to_string();
-            std::fs::write(&output_path, calendar_content)
-                .context("Failed to write calendar file")?;
+            write_calendar(&output_path, calendar.to_string(), merge, &config)?;
+        }
+        Commands::Export {
+            format,
+            filter,
+            window,
+        } => {
+            let services = filter.apply(&load_trash_services(storage.as_ref())?);
+            let options = event_options(&config, dtstamp, &window);
+            let updated = saved_at(storage.as_ref(), dtstamp);
+            let today = chrono::Local::now().date_naive();
+            print!(
+                "{}",
+                feed::generate(format, &services, &options, updated, today)
+            );
+        }
+        Commands::Invoices { format } => {
+            let invoices_json =
+                with_relogin(&mut client, |client| Box::pin(client.fetch_invoices())).await?;
+            let invoices: Vec<Invoice> = serde_json::from_value(invoices_json)
+                .with_context(|| tr!("invoices-parse-failed"))?;
+            models::report_unknown_fields(
+                "invoice",
+                invoices
+                    .iter()
+                    .flat_map(|invoice| invoice.unknown.keys().cloned()),
+            );
 
-            println!("Calendar saved to: {:?}", output_path);
+            match format {
+                ListFormat::Text => {
+                    for invoice in &invoices {
+                        println!(
+                            "{:<12} {:<10} {} {:<10} {:>10} {}",
+                            invoice.invoice_number,
+                            invoice
+                                .invoice_date
+                                .map_or_else(|| "-".to_string(), |date| date.to_string()),
+                            tr!("due"),
+                            invoice
+                                .due_date
+                                .map_or_else(|| "-".to_string(), |date| date.to_string()),
+                            invoice
+                                .total_amount
+                                .map(|amount| format!("{:.2} €", amount))
+                                .unwrap_or_default(),
+                            invoice.status.as_deref().unwrap_or(""),
+                        );
+                    }
+                }
+                ListFormat::Json => println!("{}", serde_json::to_string_pretty(&invoices)?),
+                ListFormat::Jsonl => output::print_json_lines(&invoices)?,
+                ListFormat::Csv => print!("{}", invoices_csv(&invoices)),
+            }
+        }
+        Commands::Notify(args) => notify_pickups(&config, storage.as_ref(), args).await?,
+        Commands::Daemon {
+            listen,
+            tls_cert,
+            tls_key,
+        } => {
+            let addr = match listen {
+                Some(addr) => Some(addr),
+                None => daemon::listen_address(&config)?,
+            };
+            let daemon_config = config.daemon.clone().unwrap_or_default();
+            let tls = match (
+                tls_cert.or(daemon_config.tls_cert),
+                tls_key.or(daemon_config.tls_key),
+            ) {
+                (Some(cert), Some(key)) => Some(server::tls_acceptor(&cert, &key)?),
+                (None, None) => None,
+                _ => anyhow::bail!(tr!("tls-incomplete")),
+            };
+            let listen = addr.map(|addr| server::Listen { addr, tls });
+            let tenants = DaemonAccount::tenants(&config)?;
+            let main =
+                DaemonAccount::new(None, client, config, state_dir.clone(), output_path.clone())?;
+            run_daemon(main, tenants, listen, cli.ics_interval.as_deref(), dtstamp).await?
+        }
+        Commands::InstallService { manager, print } => {
+            let manager = manager.unwrap_or_default();
+            let schedules = daemon::Schedules::from_config(&config)?;
+            let exe = std::env::current_exe().context("Could not locate the pjhoy executable")?;
+            let files =
+                service::service_files(manager, &schedules, &exe, &output_path, &state_dir)?;
+            // Fetches and reminders keep their own schedules, like the
+            // daemon's jobs, instead of one combined command
+            let jobs: Vec<String> = schedules
+                .jobs()
+                .into_iter()
+                .map(|(job, _)| format!("pjhoy {}", job))
+                .collect();
+            report!("service-jobs", jobs.join(", "));
+            if print {
+                for file in &files {
+                    println!("# {}\n{}", file.name, file.contents);
+                }
+            } else {
+                let dir = manager.install_dir()?;
+                std::fs::create_dir_all(&dir).context(format!("Failed to create {:?}", dir))?;
+                for file in &files {
+                    let path = dir.join(&file.name);
+                    std::fs::write(&path, &file.contents)
+                        .context(format!("Failed to write {:?}", path))?;
+                    report!("service-written", path.display());
+                }
+                report!("service-enable", manager.enable_command(&dir, &files));
+            }
+        }
+        Commands::Diff { old, new, format } => {
+            let (old_services, new_services) = match (old, new) {
+                (Some(old), Some(new)) => (load_services_file(&old)?, load_services_file(&new)?),
+                (old, None) => {
+                    let history = HistoryStore::open(storage.as_ref())?;
+                    let mut latest = history.latest_fetches(2)?;
+                    let Some(latest_fetch) = latest.pop() else {
+                        return Err(anyhow::anyhow!(tr!("no-stored-fetches")));
+                    };
+                    let old_services = match (old, latest.pop()) {
+                        (Some(old), _) => load_services_file(&old)?,
+                        (None, Some(previous_fetch)) => history.load_fetch(previous_fetch.id)?,
+                        (None, None) => return Err(anyhow::anyhow!(tr!("one-stored-fetch"))),
+                    };
+                    (old_services, history.load_fetch(latest_fetch.id)?)
+                }
+                (None, Some(_)) => unreachable!("clap fills positional arguments in order"),
+            };
+
+            let schedule_diff = diff::diff_services(&old_services, &new_services);
+
+            match format {
+                OutputFormat::Text => print!("{}", schedule_diff),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&schedule_diff)?),
+                OutputFormat::Jsonl => output::print_json_lines(schedule_diff.records())?,
+            }
+        }
+        Commands::History { group, format } => {
+            let history = HistoryStore::open(storage.as_ref())?;
+            let today = chrono::Local::now().date_naive();
+            let pickups = history.past_pickups(today, group.as_ref())?;
+
+            match format {
+                OutputFormat::Text => {
+                    for pickup in &pickups {
+                        println!(
+                            "{}  {} ({})  {} {} – {}",
+                            pickup.date,
+                            output::paint(
+                                &pickup.name,
+                                pickup.product_group.as_ref().and_then(ProductGroup::color)
+                            ),
+                            pickup.customer_number,
+                            tr!("listed"),
+                            pickup
+                                .first_seen
+                                .with_timezone(&chrono::Local)
+                                .format("%Y-%m-%d"),
+                            pickup
+                                .last_seen
+                                .with_timezone(&chrono::Local)
+                                .format("%Y-%m-%d"),
+                        );
+                    }
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&pickups)?),
+                OutputFormat::Jsonl => output::print_json_lines(&pickups)?,
+            }
+        }
+        Commands::Costs { months, format } => {
+            let services = load_trash_services(storage.as_ref())?;
+            let today = chrono::Local::now().date_naive();
+            let report = costs::cost_report(&services, today, months, &config.vat_rates());
+
+            match format {
+                OutputFormat::Text => print!("{}", report),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                OutputFormat::Jsonl => output::print_json_lines(report.records())?,
+            }
+        }
+        Commands::Contract { format } => {
+            let services = load_trash_services(storage.as_ref())?;
+            let contracts: Vec<contract::ServiceContract> = services
+                .iter()
+                .map(contract::ServiceContract::from)
+                .collect();
+
+            match format {
+                OutputFormat::Text => print!("{}", contract::ContractTable(&contracts)),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&contracts)?),
+                OutputFormat::Jsonl => output::print_json_lines(&contracts)?,
+            }
+        }
+        Commands::Parse { file } => {
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| tr!("read-failed", file.display()))?;
+            let events = calendar::parse_events(&text)
+                .with_context(|| tr!("parse-failed", file.display()))?;
+
+            println!("{}", serde_json::to_string_pretty(&events)?);
+        }
+        Commands::Ics {
+            command: IcsCommand::Lint { file },
+        } => {
+            let path = file.unwrap_or(output_path);
+            let bytes = std::fs::read(&path).with_context(|| tr!("read-failed", path.display()))?;
+            let issues = ical::lint(&bytes);
+
+            for issue in &issues {
+                println!("{}", issue);
+            }
+            if !issues.is_empty() {
+                return Err(anyhow::anyhow!(tr!("lint-problems", issues.len())));
+            }
+            report!("lint-ok", path.display());
+        }
+        Commands::Forecast { format } => {
+            let services = load_trash_services(storage.as_ref())?;
+            let today = chrono::Local::now().date_naive();
+            let forecast = costs::forecast(&services, today, &config.vat_rates());
+
+            match format {
+                OutputFormat::Text => print!("{}", forecast),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&forecast)?),
+                OutputFormat::Jsonl => output::print_json_lines(&forecast.services)?,
+            }
         }
     }
 
-    Ok(())
+    Ok(exit_code)
 }
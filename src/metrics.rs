@@ -0,0 +1,163 @@
+use crate::models::TrashService;
+use crate::statusbar::next_by_group;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicU8, Ordering};
+
+/// Whether the saved session works, as last seen by the daemon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    #[default]
+    Unknown,
+    Valid,
+    Expired,
+}
+
+/// Fetch statistics of the daemon, shared with the HTTP server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    fetch_successes: AtomicU64,
+    fetch_failures: AtomicU64,
+    /// Unix time of the last successful fetch, 0 before the first one
+    last_fetch: AtomicI64,
+    /// A `SessionState` discriminant
+    session: AtomicU8,
+}
+
+impl Metrics {
+    /// Count a fetch run, remembering when the last one succeeded
+    pub fn record_fetch(&self, success: bool) {
+        if success {
+            self.fetch_successes.fetch_add(1, Ordering::Relaxed);
+            self.set_last_fetch(Utc::now());
+            self.set_session(SessionState::Valid);
+        } else {
+            self.fetch_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Time of the last successful fetch, e.g. from the history on startup
+    pub fn set_last_fetch(&self, at: DateTime<Utc>) {
+        self.last_fetch.store(at.timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn last_fetch(&self) -> Option<DateTime<Utc>> {
+        match self.last_fetch.load(Ordering::Relaxed) {
+            0 => None,
+            seconds => DateTime::from_timestamp(seconds, 0),
+        }
+    }
+
+    pub fn set_session(&self, state: SessionState) {
+        self.session.store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn session(&self) -> SessionState {
+        match self.session.load(Ordering::Relaxed) {
+            1 => SessionState::Valid,
+            2 => SessionState::Expired,
+            _ => SessionState::Unknown,
+        }
+    }
+
+    /// Prometheus text exposition of the counters and of the next pickup of
+    /// each product group among `services`
+    pub fn render(&self, services: &[TrashService], today: NaiveDate) -> String {
+        let mut text = String::new();
+
+        family(
+            &mut text,
+            "pjhoy_next_pickup_days",
+            "gauge",
+            "Days until the next pickup of the product group",
+        );
+        for (group, pickup) in next_by_group(services, today) {
+            let _ = writeln!(
+                text,
+                "pjhoy_next_pickup_days{{group=\"{}\"}} {}",
+                label_value(&group),
+                pickup.in_days
+            );
+        }
+
+        family(
+            &mut text,
+            "pjhoy_fetches_total",
+            "counter",
+            "Fetch runs of the daemon by result",
+        );
+        for (result, count) in [
+            ("success", &self.fetch_successes),
+            ("failure", &self.fetch_failures),
+        ] {
+            let _ = writeln!(
+                text,
+                "pjhoy_fetches_total{{result=\"{}\"}} {}",
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        if let Some(last_fetch) = self.last_fetch() {
+            family(
+                &mut text,
+                "pjhoy_last_fetch_timestamp_seconds",
+                "gauge",
+                "Unix time of the last successful fetch",
+            );
+            let _ = writeln!(
+                text,
+                "pjhoy_last_fetch_timestamp_seconds {}",
+                last_fetch.timestamp()
+            );
+        }
+        text
+    }
+}
+
+/// HELP and TYPE lines of a metric family
+fn family(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// Escape a label value as the exposition format requires
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let service: TrashService = serde_json::from_value(serde_json::json!({
+            "ASTNextDate": "2024-03-06",
+            "ASTNimi": "Biojäte",
+            "ASTAsnro": "02-2891001-01",
+            "ASTPos": 1,
+            "ASTTyyppi": null,
+            "ASTHinta": 5.1,
+            "ASTVali": "2",
+            "tariff": { "productgroup": "BIO" },
+        }))
+        .unwrap();
+        let metrics = Metrics::default();
+        metrics.record_fetch(false);
+        metrics.set_last_fetch(DateTime::from_timestamp(1_709_700_000, 0).unwrap());
+
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let text = metrics.render(&[service], today);
+        assert!(text.contains(
+            "# TYPE pjhoy_next_pickup_days gauge\npjhoy_next_pickup_days{group=\"BIO\"} 2\n"
+        ));
+        assert!(text.contains("pjhoy_fetches_total{result=\"success\"} 0\n"));
+        assert!(text.contains("pjhoy_fetches_total{result=\"failure\"} 1\n"));
+        assert!(text.ends_with("pjhoy_last_fetch_timestamp_seconds 1709700000\n"));
+    }
+}
@@ -1,23 +1,584 @@
+use crate::i18n::Language;
+use chrono::NaiveDate;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
 
-// Struct to match the actual API response structure
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(non_snake_case)] // API uses camelCase field names
+/// VAT percentage in effect from a given date onwards
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VatRate {
+    /// First day the rate applies, `None` for "since forever"
+    pub from: Option<NaiveDate>,
+    pub percent: f64,
+}
+
+/// Date-aware VAT table used to add taxes to service prices
+#[derive(Debug, Clone, PartialEq)]
+pub struct VatRates(Vec<VatRate>);
+
+impl VatRates {
+    pub fn new(mut rates: Vec<VatRate>) -> Self {
+        rates.sort_by_key(|rate| rate.from);
+        Self(rates)
+    }
+
+    /// The same rate regardless of date
+    pub fn flat(percent: f64) -> Self {
+        Self(vec![VatRate {
+            from: None,
+            percent,
+        }])
+    }
+
+    /// Finnish general VAT rate history
+    pub fn finnish() -> Self {
+        Self::new(vec![
+            VatRate {
+                from: None,
+                percent: 24.0,
+            },
+            VatRate {
+                from: NaiveDate::from_ymd_opt(2024, 9, 1),
+                percent: 25.5,
+            },
+        ])
+    }
+
+    /// VAT percentage in effect on `date`
+    fn percent_on(&self, date: NaiveDate) -> f64 {
+        self.0
+            .iter()
+            .rev()
+            .find(|rate| rate.from.is_none_or(|from| from <= date))
+            .map(|rate| rate.percent)
+            .unwrap_or(0.0)
+    }
+
+    /// `net` price with the VAT in effect on `date` added, rounded to cents
+    pub fn gross_on(&self, net: Decimal, date: NaiveDate) -> Decimal {
+        let percent = Decimal::from_f64(self.percent_on(date)).unwrap_or_default();
+        (net + net * percent / Decimal::ONE_HUNDRED)
+            .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+    }
+}
+
+impl Default for VatRates {
+    fn default() -> Self {
+        Self::finnish()
+    }
+}
+
+/// Deserialize an optional extranet date. Dates come as `2024-01-15`,
+/// sometimes with a time of day appended, or in the Finnish `15.1.2024`
+/// form.
+fn api_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let date = value.get(..10).unwrap_or(value);
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%d.%m.%Y"))
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("invalid date {:?}", value)))
+}
+
+/// Waste product group of a tariff, identified by the extranet's code
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ProductGroup {
+    Mixed,
+    Bio,
+    Cardboard,
+    Plastic,
+    Paper,
+    Metal,
+    Glass,
+    Hazardous,
+    /// A code not known to this version
+    Other(String),
+}
+
+impl ProductGroup {
+    /// Every product group known to this version
+    pub const KNOWN: [ProductGroup; 8] = [
+        Self::Mixed,
+        Self::Bio,
+        Self::Cardboard,
+        Self::Plastic,
+        Self::Paper,
+        Self::Metal,
+        Self::Glass,
+        Self::Hazardous,
+    ];
+
+    /// Code, Finnish name, icon and English name of a known group
+    fn details(&self) -> Option<(&'static str, &'static str, &'static str, &'static str)> {
+        match self {
+            Self::Mixed => Some(("SEK", "Sekajäte", "🗑️", "Mixed waste")),
+            Self::Bio => Some(("BIO", "Biojäte", "🍃", "Biowaste")),
+            Self::Cardboard => Some(("KK", "Kartonki", "📦", "Cardboard")),
+            Self::Plastic => Some(("MU", "Muovi", "🔄", "Plastic")),
+            Self::Paper => Some(("PP", "Paperi", "📄", "Paper")),
+            Self::Metal => Some(("ME", "Metalli", "🔧", "Metal")),
+            Self::Glass => Some(("LA", "Lasi", "🥃", "Glass")),
+            Self::Hazardous => Some(("VU", "Vaarallinen jäte", "☣️", "Hazardous waste")),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// The extranet's code, e.g. `BIO`
+    pub fn code(&self) -> &str {
+        match self {
+            Self::Other(code) => code,
+            known => known.details().map_or("", |(code, ..)| code),
+        }
+    }
+
+    pub fn finnish_name(&self) -> Option<&'static str> {
+        self.details().map(|(_, name, ..)| name)
+    }
+
+    pub fn english_name(&self) -> Option<&'static str> {
+        self.details().map(|(.., name)| name)
+    }
+
+    /// Name in the given language, `None` for unknown groups
+    pub fn name(&self, lang: Language) -> Option<&'static str> {
+        match lang {
+            Language::Fi => self.finnish_name(),
+            Language::En => self.english_name(),
+        }
+    }
+
+    /// Emoji of the group, `None` for unknown groups
+    pub fn icon(&self) -> Option<&'static str> {
+        self.details().map(|(_, _, icon, _)| icon)
+    }
+
+    /// CSS color name of the group for calendar events (RFC 7986 `COLOR`)
+    /// and terminal listings, `None` for unknown groups
+    pub fn color(&self) -> Option<&'static str> {
+        match self {
+            Self::Mixed => Some("gray"),
+            Self::Bio => Some("green"),
+            Self::Cardboard => Some("olive"),
+            Self::Plastic => Some("purple"),
+            Self::Paper => Some("blue"),
+            Self::Metal => Some("silver"),
+            Self::Glass => Some("teal"),
+            Self::Hazardous => Some("red"),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// Material Design icon of the group for Home Assistant sensors
+    pub fn ha_icon(&self) -> &'static str {
+        match self {
+            Self::Mixed => "mdi:trash-can",
+            Self::Bio => "mdi:leaf",
+            Self::Cardboard => "mdi:package-variant",
+            Self::Plastic => "mdi:recycle",
+            Self::Paper => "mdi:newspaper",
+            Self::Metal => "mdi:wrench",
+            Self::Glass => "mdi:glass-fragile",
+            Self::Hazardous => "mdi:biohazard",
+            Self::Other(_) => "mdi:delete",
+        }
+    }
+
+    /// ntfy tag (emoji shortcode) of the group, also used as the Slack emoji
+    pub fn ntfy_tag(&self) -> &'static str {
+        match self {
+            Self::Mixed | Self::Other(_) => "wastebasket",
+            Self::Bio => "leaves",
+            Self::Cardboard => "package",
+            Self::Plastic => "recycle",
+            Self::Paper => "page_facing_up",
+            Self::Metal => "wrench",
+            Self::Glass => "tumbler_glass",
+            Self::Hazardous => "biohazard",
+        }
+    }
+
+    /// Group of a name in any supported language, e.g. a calendar category
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::KNOWN
+            .into_iter()
+            .find(|group| group.finnish_name() == Some(name) || group.english_name() == Some(name))
+    }
+}
+
+impl FromStr for ProductGroup {
+    type Err = Infallible;
+
+    /// Known codes are matched case-insensitively
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Ok(Self::KNOWN
+            .into_iter()
+            .find(|group| group.code().eq_ignore_ascii_case(code))
+            .unwrap_or_else(|| Self::Other(code.to_string())))
+    }
+}
+
+impl From<String> for ProductGroup {
+    fn from(code: String) -> Self {
+        let Ok(group) = code.parse();
+        group
+    }
+}
+
+impl From<ProductGroup> for String {
+    fn from(group: ProductGroup) -> Self {
+        group.code().to_string()
+    }
+}
+
+impl fmt::Display for ProductGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// A service of a customer number as returned by the services endpoint.
+/// Fields keep the extranet's names when serialized.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TrashService {
-    pub ASTNextDate: Option<String>, // Actual field name from API, can be null
-    pub ASTNimi: String,             // Service name
-    pub ASTAsnro: String,            // Customer number for uniqueness
-    pub ASTPos: i32,                 // Position for uniqueness
-    pub ASTTyyppi: Option<i32>,      // Service type ID
-    pub tariff: Option<Tariff>,      // Tariff information including productgroup
-    pub ASTHinta: Option<f64>,       // Cost, excluding taxes
-    pub ASTVali: String,             // Interval in weeks
+    /// Next pickup, null for services without scheduled pickups
+    #[serde(rename = "ASTNextDate", default, deserialize_with = "api_date")]
+    pub next_date: Option<NaiveDate>,
+    #[serde(rename = "ASTNimi")]
+    pub name: String,
+    #[serde(rename = "ASTAsnro")]
+    pub customer_number: String,
+    /// Position of the service within the customer number
+    #[serde(rename = "ASTPos")]
+    pub position: i32,
+    #[serde(rename = "ASTTyyppi")]
+    pub service_type: Option<i32>,
+    /// Tariff information including the product group
+    pub tariff: Option<Tariff>,
+    /// Price of one pickup, excluding taxes
+    #[serde(rename = "ASTHinta")]
+    pub price: Option<Decimal>,
+    /// Interval in weeks
+    #[serde(rename = "ASTVali")]
+    pub interval: String,
+    /// Number of containers
+    #[serde(rename = "ASTMaara", default)]
+    pub containers: Option<f64>,
+    /// Contract start date
+    #[serde(rename = "ASTAlkupvm", default, deserialize_with = "api_date")]
+    pub start_date: Option<NaiveDate>,
+    /// Contract end date, null while in force
+    #[serde(rename = "ASTLoppupvm", default, deserialize_with = "api_date")]
+    pub end_date: Option<NaiveDate>,
+    /// Pickup address from the customer details, not part of the services API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Fields the extranet returned that are not known to this version
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tariff {
-    pub productgroup: Option<String>, // Product group identifier
-    pub name: Option<String>,         // Tariff name
-                                      // Other tariff fields
+    #[serde(rename = "productgroup")]
+    pub product_group: Option<ProductGroup>,
+    pub name: Option<String>,
+    /// Container size, e.g. "240 l"
+    #[serde(rename = "containersize", default)]
+    pub container_size: Option<String>,
+    #[serde(rename = "validfrom", default, deserialize_with = "api_date")]
+    pub valid_from: Option<NaiveDate>,
+    #[serde(rename = "validto", default, deserialize_with = "api_date")]
+    pub valid_to: Option<NaiveDate>,
+    /// Fields the extranet returned that are not known to this version
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Invoice row as returned by the extranet's invoice endpoint
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invoice {
+    pub invoice_number: String,
+    pub customer_number: Option<String>,
+    #[serde(default, deserialize_with = "api_date")]
+    pub invoice_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "api_date")]
+    pub due_date: Option<NaiveDate>,
+    /// Total including VAT
+    pub total_amount: Option<Decimal>,
+    pub status: Option<String>,
+    pub reference: Option<String>,
+    /// Fields the extranet returned that are not known to this version
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Customer (pickup location) details
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Customer {
+    pub customer_number: String,
+    pub name: Option<String>,
+    pub street_address: Option<String>,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    /// Fields the extranet returned that are not known to this version
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+impl TrashService {
+    /// Color of the service's product group, see `ProductGroup::color`
+    pub fn color(&self) -> Option<&'static str> {
+        self.tariff
+            .as_ref()
+            .and_then(|tariff| tariff.product_group.as_ref())
+            .and_then(ProductGroup::color)
+    }
+
+    /// Pickup interval in weeks, `None` unless a positive number
+    pub fn interval_weeks(&self) -> Option<u32> {
+        self.interval.trim().parse().ok().filter(|weeks| *weeks > 0)
+    }
+
+    /// Names of the fields not known to this version, those of the tariff
+    /// prefixed with `tariff.`
+    pub fn unknown_fields(&self) -> Vec<String> {
+        let tariff = self.tariff.iter().flat_map(|tariff| tariff.unknown.keys());
+        self.unknown
+            .keys()
+            .cloned()
+            .chain(tariff.map(|key| format!("tariff.{}", key)))
+            .collect()
+    }
+}
+
+/// Builder of services for tests, defaulting what a test does not care
+/// about: customer "12345", position 1, a pickup every two weeks, no price
+/// and no tariff
+#[cfg(test)]
+pub struct ServiceBuilder {
+    service: TrashService,
+}
+
+#[cfg(test)]
+impl TrashService {
+    /// Start building a service named `name` with its next pickup on
+    /// `next_date`, e.g. "2024-03-05"
+    pub fn builder(name: &str, next_date: &str) -> ServiceBuilder {
+        ServiceBuilder {
+            service: TrashService {
+                next_date: next_date.parse().ok(),
+                name: name.to_string(),
+                customer_number: "12345".to_string(),
+                position: 1,
+                service_type: Some(1),
+                tariff: None,
+                price: None,
+                interval: "2".to_string(),
+                containers: None,
+                start_date: None,
+                end_date: None,
+                address: None,
+                unknown: Default::default(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+impl ServiceBuilder {
+    pub fn customer(mut self, customer_number: &str) -> Self {
+        self.service.customer_number = customer_number.to_string();
+        self
+    }
+
+    pub fn position(mut self, position: i32) -> Self {
+        self.service.position = position;
+        self
+    }
+
+    pub fn price(mut self, price: Decimal) -> Self {
+        self.service.price = Some(price);
+        self
+    }
+
+    pub fn interval(mut self, interval: &str) -> Self {
+        self.service.interval = interval.to_string();
+        self
+    }
+
+    pub fn address(mut self, address: &str) -> Self {
+        self.service.address = Some(address.to_string());
+        self
+    }
+
+    /// Product group by its code, e.g. "BIO"
+    pub fn group(mut self, code: &str) -> Self {
+        self.tariff().product_group = code.parse().ok();
+        self
+    }
+
+    /// Container size of the tariff, e.g. "140 l"
+    pub fn container_size(mut self, size: &str) -> Self {
+        self.tariff().container_size = Some(size.to_string());
+        self
+    }
+
+    pub fn build(self) -> TrashService {
+        self.service
+    }
+
+    fn tariff(&mut self) -> &mut Tariff {
+        self.service.tariff.get_or_insert_with(|| Tariff {
+            product_group: None,
+            name: None,
+            container_size: None,
+            valid_from: None,
+            valid_to: None,
+            unknown: Default::default(),
+        })
+    }
+}
+
+/// Report fields the extranet started returning that this version does not
+/// know, an early warning of API changes. Shown with `--verbose`.
+pub fn report_unknown_fields(kind: &str, fields: impl IntoIterator<Item = String>) {
+    let fields: std::collections::BTreeSet<String> = fields.into_iter().collect();
+    if !fields.is_empty() {
+        let fields: Vec<String> = fields.into_iter().collect();
+        tracing::info!(
+            "The extranet returned {} fields unknown to this version: {}",
+            kind,
+            fields.join(", ")
+        );
+    }
+}
+
+impl Customer {
+    /// Single line postal address, e.g. "Kotikatu 1, 33100 Tampere"
+    pub fn address(&self) -> Option<String> {
+        let street = self.street_address.as_deref()?.trim();
+        let locality = [self.postal_code.as_deref(), self.city.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if locality.is_empty() {
+            Some(street.to_string())
+        } else {
+            Some(format!("{}, {}", street, locality))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_fields_are_kept() {
+        let json = serde_json::json!({
+            "ASTNextDate": "2024-01-15",
+            "ASTNimi": "Biojäte",
+            "ASTAsnro": "02-2891001-01",
+            "ASTPos": 1,
+            "ASTTyyppi": null,
+            "ASTHinta": 5.0,
+            "ASTVali": "2",
+            "ASTUusi": "x",
+            "tariff": { "productgroup": "BIO", "name": null, "colour": "brown" }
+        });
+        let service: TrashService = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(service.unknown_fields(), ["ASTUusi", "tariff.colour"]);
+        assert_eq!(service.price, Some(Decimal::new(5, 0)));
+
+        // Saved services keep the unknown fields
+        let saved = serde_json::to_value(&service).unwrap();
+        assert_eq!(saved["ASTUusi"], "x");
+        assert_eq!(saved["tariff"]["colour"], "brown");
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        let json = serde_json::json!({
+            "ASTNextDate": "2024-01-15T00:00:00",
+            "ASTNimi": "Biojäte",
+            "ASTAsnro": "02-2891001-01",
+            "ASTPos": 1,
+            "ASTTyyppi": 3,
+            "ASTHinta": 10.5,
+            "ASTVali": "2",
+            "ASTAlkupvm": "1.3.2023",
+            "ASTLoppupvm": "",
+            "tariff": null
+        });
+        let service: TrashService = serde_json::from_value(json).unwrap();
+        assert_eq!(service.next_date, NaiveDate::from_ymd_opt(2024, 1, 15));
+        assert_eq!(service.start_date, NaiveDate::from_ymd_opt(2023, 3, 1));
+        assert_eq!(service.end_date, None);
+        assert_eq!(service.price, Some(Decimal::new(105, 1)));
+        assert_eq!(service.interval_weeks(), Some(2));
+
+        // Saved with the extranet's names and formats
+        let saved = serde_json::to_value(&service).unwrap();
+        assert_eq!(saved["ASTNextDate"], "2024-01-15");
+        assert_eq!(saved["ASTHinta"], 10.5);
+
+        let invalid = serde_json::json!({
+            "ASTNextDate": "huomenna", "ASTNimi": "", "ASTAsnro": "", "ASTPos": 1,
+            "ASTTyyppi": null, "tariff": null, "ASTHinta": null, "ASTVali": ""
+        });
+        assert!(serde_json::from_value::<TrashService>(invalid).is_err());
+    }
+
+    #[test]
+    fn test_gross_price() {
+        let rates = VatRates::finnish();
+        let net = Decimal::new(1050, 2);
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(
+            rates.gross_on(net, date(2024, 8, 31)),
+            Decimal::new(1302, 2)
+        );
+        assert_eq!(rates.gross_on(net, date(2024, 9, 1)), Decimal::new(1318, 2));
+    }
+
+    #[test]
+    fn test_product_group() {
+        let bio: ProductGroup = "bio".parse().unwrap();
+        assert_eq!(bio, ProductGroup::Bio);
+        assert_eq!(bio.to_string(), "BIO");
+        assert_eq!(bio.icon(), Some("🍃"));
+        assert_eq!(bio.name(Language::En), Some("Biowaste"));
+        assert_eq!(
+            ProductGroup::from_name("Sekajäte"),
+            Some(ProductGroup::Mixed)
+        );
+
+        let other: ProductGroup = "XY".parse().unwrap();
+        assert_eq!(other, ProductGroup::Other("XY".to_string()));
+        assert_eq!(other.code(), "XY");
+        assert_eq!(other.finnish_name(), None);
+
+        let json = serde_json::json!({ "productgroup": "VU", "name": null });
+        let tariff: Tariff = serde_json::from_value(json).unwrap();
+        assert_eq!(tariff.product_group, Some(ProductGroup::Hazardous));
+        assert_eq!(serde_json::to_value(&tariff).unwrap()["productgroup"], "VU");
+    }
 }
@@ -0,0 +1,175 @@
+use crate::calendar::{event_category, EventOptions};
+use crate::costs::pickup_dates;
+use crate::i18n::Language;
+use crate::models::TrashService;
+use crate::output::paint;
+use crate::statusbar::icon;
+use chrono::{Datelike, Duration, NaiveDate};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// First day of a `YYYY-MM` month given to `pjhoy cal`
+pub fn parse_month(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(&format!("{}-01", value), "%Y-%m-%d")
+        .map_err(|_| format!("{:?} is not a month like 2024-03", value))
+}
+
+/// First day of the month of `date`
+pub fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+fn month_name(lang: Language, month: u32) -> &'static str {
+    const FI: [&str; 12] = [
+        "Tammikuu",
+        "Helmikuu",
+        "Maaliskuu",
+        "Huhtikuu",
+        "Toukokuu",
+        "Kesäkuu",
+        "Heinäkuu",
+        "Elokuu",
+        "Syyskuu",
+        "Lokakuu",
+        "Marraskuu",
+        "Joulukuu",
+    ];
+    const EN: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    let names = match lang {
+        Language::Fi => FI,
+        Language::En => EN,
+    };
+    names[month as usize - 1]
+}
+
+fn weekday_names(lang: Language) -> [&'static str; 7] {
+    match lang {
+        Language::Fi => ["ma", "ti", "ke", "to", "pe", "la", "su"],
+        Language::En => ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"],
+    }
+}
+
+/// Terminal columns taken by `text`, counting emojis as two and their
+/// variation selectors as none
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c as u32 {
+            0xFE00..=0xFE0F | 0x200D => 0,
+            0x2600..=0x27BF | 0x1F000.. => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+fn pad(text: &str, columns: usize) -> String {
+    format!(
+        "{}{}",
+        text,
+        " ".repeat(columns.saturating_sub(width(text)))
+    )
+}
+
+/// The month starting on `first` as a Monday-first grid like `cal`, with
+/// the icons of the groups picked up after each day, and a legend of the
+/// icons below
+pub fn render(services: &[TrashService], options: &EventOptions, first: NaiveDate) -> String {
+    let last = first_of_month(first + Duration::days(31)) - Duration::days(1);
+
+    // Icons of each day, colored like the first group picked up
+    let mut icons: BTreeMap<NaiveDate, (String, Option<&str>)> = BTreeMap::new();
+    let mut legend: Vec<(String, String, Option<&str>)> = Vec::new();
+    for service in services {
+        let service_icon = icon(service, options);
+        for date in pickup_dates(service, first, last) {
+            let (day, color) = icons.entry(date).or_default();
+            if !day.contains(&service_icon) {
+                day.push_str(&service_icon);
+            }
+            if color.is_none() {
+                *color = service.color();
+            }
+            let name = event_category(service, options).unwrap_or_else(|| service.name.clone());
+            let entry = (service_icon.clone(), name, service.color());
+            if !legend.contains(&entry) {
+                legend.push(entry);
+            }
+        }
+    }
+
+    let cell = 2 + icons.values().map(|(day, _)| width(day)).max().unwrap_or(0);
+    let row_width = 7 * cell + 6;
+    let mut text = String::new();
+
+    let title = format!(
+        "{} {}",
+        month_name(options.lang, first.month()),
+        first.year()
+    );
+    let indent = row_width.saturating_sub(width(&title)) / 2;
+    let _ = writeln!(text, "{}{}", " ".repeat(indent), title);
+    let header: Vec<String> = weekday_names(options.lang)
+        .iter()
+        .map(|name| pad(name, cell))
+        .collect();
+    let _ = writeln!(text, "{}", header.join(" ").trim_end());
+
+    let mut row: Vec<String> =
+        vec![" ".repeat(cell); first.weekday().num_days_from_monday() as usize];
+    for date in first.iter_days().take_while(|date| *date <= last) {
+        let (day_icons, color) = icons.get(&date).cloned().unwrap_or_default();
+        let day = format!("{:>2}{}", date.day(), day_icons);
+        // Padded before painting, escape codes take no columns
+        let padding = " ".repeat(cell.saturating_sub(width(&day)));
+        row.push(format!("{}{}", paint(&day, color), padding));
+        if row.len() == 7 || date == last {
+            let _ = writeln!(text, "{}", row.join(" ").trim_end());
+            row.clear();
+        }
+    }
+
+    if !legend.is_empty() {
+        text.push('\n');
+        for (icon, name, color) in legend {
+            let _ = writeln!(text, "{} {}", icon, paint(&name, color));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let service = |group: &str, next_date: &str| {
+            TrashService::builder("Astia", next_date)
+                .group(group)
+                .build()
+        };
+        let services = [service("BIO", "2024-02-21"), service("SEK", "2024-03-06")];
+        let first = parse_month("2024-03").unwrap();
+
+        let text = render(&services, &EventOptions::default(), first);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "                 Maaliskuu 2024");
+        assert_eq!(lines[1], "ma     ti     ke     to     pe     la     su");
+        assert_eq!(lines[2], "                             1      2      3");
+        assert_eq!(lines[3], " 4      5      6🍃🗑️  7      8      9     10");
+        assert_eq!(lines[8], "🍃 Biojäte");
+        assert!(parse_month("2024-13").is_err());
+    }
+}
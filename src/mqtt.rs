@@ -0,0 +1,196 @@
+use crate::config::MqttConfig;
+use crate::models::{ProductGroup, TrashService};
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, QoS};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Next pickup of a single product group, aggregated over all its services
+#[derive(Debug, PartialEq)]
+struct GroupState<'a> {
+    next_date: NaiveDate,
+    services: Vec<&'a str>,
+}
+
+/// Pick the earliest upcoming pickup date for each product group
+fn group_states(services: &[TrashService]) -> BTreeMap<&ProductGroup, GroupState<'_>> {
+    let mut groups: BTreeMap<&ProductGroup, GroupState> = BTreeMap::new();
+
+    for service in services {
+        let Some(group) = service
+            .tariff
+            .as_ref()
+            .and_then(|tariff| tariff.product_group.as_ref())
+        else {
+            continue;
+        };
+        let Some(next_date) = service.next_date else {
+            continue;
+        };
+
+        let state = groups.entry(group).or_insert(GroupState {
+            next_date,
+            services: Vec::new(),
+        });
+        if next_date < state.next_date {
+            state.next_date = next_date;
+        }
+        state.services.push(&service.name);
+    }
+
+    groups
+}
+
+/// Topic and id part of the account logged in as `username`, so that the
+/// accounts of a daemon with tenants do not overwrite each other
+fn account_id(username: &str) -> String {
+    username
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Id of the sensor of `group` of an account in Home Assistant
+fn sensor_id(account: &str, group: &ProductGroup) -> String {
+    format!("pjhoy_{}_{}", account, group.code().to_lowercase())
+}
+
+/// Home Assistant discovery payload for one product group sensor
+fn discovery_payload(state_prefix: &str, account: &str, group: &ProductGroup) -> serde_json::Value {
+    let code = group.code();
+    let name = group.finnish_name().unwrap_or(code);
+
+    json!({
+        "name": name,
+        "unique_id": sensor_id(account, group),
+        "object_id": sensor_id(account, group),
+        "state_topic": format!("{}/{}/{}/state", state_prefix, account, code),
+        "json_attributes_topic": format!("{}/{}/{}/attributes", state_prefix, account, code),
+        "device_class": "date",
+        "icon": group.ha_icon(),
+        "device": {
+            "identifiers": [format!("pjhoy_{}", account)],
+            "name": format!("Jätehuolto {}", account),
+            "manufacturer": "Pirkanmaan Jätehuolto Oy",
+        },
+    })
+}
+
+/// Publish next pickup dates per product group of the account logged in as
+/// `username` and, unless disabled, Home Assistant discovery configs. All
+/// messages are retained.
+pub async fn publish_services(
+    config: &MqttConfig,
+    username: &str,
+    services: &[TrashService],
+) -> Result<()> {
+    let account = account_id(username);
+    let client_id = config
+        .client_id
+        .clone()
+        .unwrap_or_else(|| format!("pjhoy_{}", account));
+    let state_prefix = config.topic_prefix.as_deref().unwrap_or("pjhoy");
+    let discovery_prefix = config
+        .discovery_prefix
+        .as_deref()
+        .unwrap_or("homeassistant");
+
+    let mut options = MqttOptions::new(&client_id, &config.host, config.port.unwrap_or(1883));
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or(""));
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+
+    let mut messages = Vec::new();
+    for (group, state) in group_states(services) {
+        if config.discovery.unwrap_or(true) {
+            messages.push((
+                format!(
+                    "{}/sensor/{}/config",
+                    discovery_prefix,
+                    sensor_id(&account, group)
+                ),
+                discovery_payload(state_prefix, &account, group).to_string(),
+            ));
+        }
+        messages.push((
+            format!("{}/{}/{}/state", state_prefix, account, group),
+            state.next_date.to_string(),
+        ));
+        messages.push((
+            format!("{}/{}/{}/attributes", state_prefix, account, group),
+            json!({ "services": state.services }).to_string(),
+        ));
+    }
+
+    for (topic, payload) in messages {
+        client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await
+            .context("Failed to queue MQTT message")?;
+    }
+    client
+        .disconnect()
+        .await
+        .context("Failed to queue MQTT disconnect")?;
+
+    // Drive the event loop until everything queued above has been sent
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => {}
+            Err(e) => return Err(e).context("MQTT connection failed"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, group: &str, next_date: &str) -> TrashService {
+        TrashService::builder(name, next_date).group(group).build()
+    }
+
+    #[test]
+    fn test_group_states_picks_earliest_date() {
+        let services = vec![
+            service("Bio 1", "BIO", "2024-03-12"),
+            service("Seka", "SEK", "2024-03-15"),
+            service("Bio 2", "BIO", "2024-03-05"),
+        ];
+
+        let states = group_states(&services);
+
+        assert_eq!(states.len(), 2);
+        assert_eq!(
+            states[&ProductGroup::Bio],
+            GroupState {
+                next_date: NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(),
+                services: vec!["Bio 1", "Bio 2"],
+            }
+        );
+        assert_eq!(
+            states[&ProductGroup::Mixed].next_date.to_string(),
+            "2024-03-15"
+        );
+    }
+
+    #[test]
+    fn test_discovery_payload() {
+        let account = account_id("02-2891001-00");
+        let payload = discovery_payload("pjhoy", &account, &ProductGroup::Bio);
+
+        assert_eq!(payload["name"], "Biojäte");
+        assert_eq!(payload["unique_id"], "pjhoy_02_2891001_00_bio");
+        assert_eq!(payload["state_topic"], "pjhoy/02_2891001_00/BIO/state");
+        assert_eq!(payload["icon"], "mdi:leaf");
+        assert_eq!(payload["device"]["identifiers"][0], "pjhoy_02_2891001_00");
+    }
+}
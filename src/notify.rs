@@ -0,0 +1,476 @@
+use crate::config::{EmailConfig, NtfyConfig, SlackConfig, SmtpSecurity, WebhookConfig};
+use crate::diff::{Change, ScheduleDiff};
+use crate::models::{ProductGroup, TrashService};
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, NaiveDate};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials as SmtpCredentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message as EmailMessage, Tokio1Executor};
+use rust_decimal::Decimal;
+
+const WEEKDAYS_FI: [&str; 7] = ["ma", "ti", "ke", "to", "pe", "la", "su"];
+
+/// A pickup worth reminding about, one per product group and day
+#[derive(Debug, PartialEq)]
+pub struct Reminder {
+    pub group: Option<ProductGroup>,
+    pub name: String,
+    pub date: NaiveDate,
+}
+
+/// A notification ready to be delivered through any channel
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub title: String,
+    pub body: String,
+    /// Product group the message is about, used for per-group styling
+    pub group: Option<ProductGroup>,
+}
+
+/// Collect reminders for pickups between `from` and `to`, inclusive
+pub fn upcoming(services: &[TrashService], from: NaiveDate, to: NaiveDate) -> Vec<Reminder> {
+    let mut reminders: Vec<Reminder> = Vec::new();
+
+    for service in services {
+        let Some(date) = service.next_date else {
+            continue;
+        };
+        if date < from || date > to {
+            continue;
+        }
+
+        let group = service
+            .tariff
+            .as_ref()
+            .and_then(|tariff| tariff.product_group.clone());
+
+        if group.is_some() && reminders.iter().any(|r| r.group == group && r.date == date) {
+            continue;
+        }
+
+        let name = group
+            .as_ref()
+            .and_then(ProductGroup::finnish_name)
+            .map(str::to_string)
+            .unwrap_or_else(|| service.name.clone());
+
+        reminders.push(Reminder { group, name, date });
+    }
+
+    reminders.sort_by_key(|r| r.date);
+    reminders
+}
+
+/// Collect reminders for pickups happening the day after `today`
+pub fn due_tomorrow(services: &[TrashService], today: NaiveDate) -> Vec<Reminder> {
+    let tomorrow = today + Duration::days(1);
+    upcoming(services, tomorrow, tomorrow)
+}
+
+/// One message per reminder, meant to be sent the evening before
+pub fn reminder_messages(reminders: &[Reminder]) -> Vec<Message> {
+    reminders
+        .iter()
+        .map(|reminder| Message {
+            title: "Jätehuolto".to_string(),
+            body: format!("{} huomenna!", reminder.name),
+            group: reminder.group.clone(),
+        })
+        .collect()
+}
+
+/// e.g. `ti 5.3.`
+fn weekday_date(date: NaiveDate) -> String {
+    format!(
+        "{} {}.{}.",
+        WEEKDAYS_FI[date.weekday().num_days_from_monday() as usize],
+        date.day(),
+        date.month()
+    )
+}
+
+/// A single message listing all pickups of the coming week
+pub fn digest_message(reminders: &[Reminder]) -> Option<Message> {
+    if reminders.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<String> = reminders
+        .iter()
+        .map(|reminder| format!("{} {}", weekday_date(reminder.date), reminder.name))
+        .collect();
+
+    Some(Message {
+        title: "Jätehuolto: tulevan viikon tyhjennykset".to_string(),
+        body: lines.join("\n"),
+        group: None,
+    })
+}
+
+/// A pickup announced for `from` that the extranet now says happens on `to`
+#[derive(Debug, PartialEq)]
+pub struct MovedPickup {
+    pub group: Option<ProductGroup>,
+    pub name: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+/// Date changes of a fetch that move a pickup still ahead on `today`, as
+/// opposed to the next date following a pickup that took place. A change
+/// to exactly one interval later on the day of the pickup is taken for the
+/// latter. One per product group and dates.
+pub fn moved_pickups(
+    changes: &[Change<NaiveDate>],
+    services: &[TrashService],
+    today: NaiveDate,
+) -> Vec<MovedPickup> {
+    let mut moved: Vec<MovedPickup> = Vec::new();
+    for change in changes {
+        let (Some(from), Some(to)) = (change.old, change.new) else {
+            continue;
+        };
+        let interval = services
+            .iter()
+            .find(|service| {
+                service.customer_number == change.service.customer_number
+                    && service.position == change.service.position
+            })
+            .and_then(TrashService::interval_weeks);
+        let regular_next = interval.map(|weeks| from + Duration::weeks(weeks.into()));
+        let still_ahead = from > today || (from == today && regular_next.is_some());
+        if !still_ahead || Some(to) == regular_next {
+            continue;
+        }
+
+        let group = change.service.product_group.clone();
+        let name = group
+            .as_ref()
+            .and_then(ProductGroup::finnish_name)
+            .map(str::to_string)
+            .unwrap_or_else(|| change.service.name.clone());
+        let pickup = MovedPickup {
+            group,
+            name,
+            from,
+            to,
+        };
+        if !moved.contains(&pickup) {
+            moved.push(pickup);
+        }
+    }
+    moved
+}
+
+/// One message per moved pickup
+pub fn moved_messages(moved: &[MovedPickup]) -> Vec<Message> {
+    moved
+        .iter()
+        .map(|pickup| Message {
+            title: "Jätehuolto: tyhjennys siirtyy".to_string(),
+            body: format!(
+                "{}: tyhjennys siirtyy {} -> {}",
+                pickup.name,
+                weekday_date(pickup.from),
+                weekday_date(pickup.to)
+            ),
+            group: pickup.group.clone(),
+        })
+        .collect()
+}
+
+/// A single message listing the price changes of a fetch
+pub fn price_change_message(changes: &[Change<Decimal>]) -> Option<Message> {
+    if changes.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+
+    Some(Message {
+        title: "Jätehuolto: hinta muuttunut".to_string(),
+        body: lines.join("\n"),
+        group: None,
+    })
+}
+
+/// Alert of a fetch that failed unattended
+pub fn failure_message(error: &str) -> Message {
+    Message {
+        title: "Jätehuolto: haku epäonnistui".to_string(),
+        body: error.to_string(),
+        group: None,
+    }
+}
+
+/// ntfy tag of the product group of a message, a wastebasket without one
+fn ntfy_tag(message: &Message) -> &'static str {
+    message
+        .group
+        .as_ref()
+        .map_or(ProductGroup::Mixed.ntfy_tag(), ProductGroup::ntfy_tag)
+}
+
+/// Slack mrkdwn text of a message: the title in bold after the emoji of
+/// its product group, see https://api.slack.com/reference/surfaces/formatting
+fn slack_text(message: &Message) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    format!(
+        ":{}: *{}*\n{}",
+        ntfy_tag(message),
+        escape(&message.title),
+        escape(&message.body)
+    )
+}
+
+/// Post a message to a Slack incoming webhook
+pub async fn send_slack(
+    client: &reqwest::Client,
+    config: &SlackConfig,
+    message: &Message,
+) -> Result<()> {
+    let response = client
+        .post(&config.webhook_url)
+        .json(&serde_json::json!({ "text": slack_text(message) }))
+        .send()
+        .await
+        .context("Failed to send Slack notification")?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Slack notification failed: {}",
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Post a message to the configured ntfy topic
+pub async fn send_ntfy(
+    client: &reqwest::Client,
+    config: &NtfyConfig,
+    message: &Message,
+) -> Result<()> {
+    let server = config.server.as_deref().unwrap_or("https://ntfy.sh");
+    let url = format!("{}/{}", server.trim_end_matches('/'), config.topic);
+
+    let priority = message
+        .group
+        .as_ref()
+        .and_then(|group| config.priorities.as_ref()?.get(group.code()).copied())
+        .or(config.priority)
+        .unwrap_or(3);
+
+    let mut request = client
+        .post(&url)
+        .header("Title", &message.title)
+        .header("Priority", priority.to_string())
+        .header("Tags", ntfy_tag(message))
+        .body(message.body.clone());
+
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request
+        .send()
+        .await
+        .context("Failed to send ntfy notification")?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "ntfy notification failed: {}",
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Send a message as a plain text email over SMTP
+pub async fn send_email(config: &EmailConfig, message: &Message) -> Result<()> {
+    let mut builder = EmailMessage::builder()
+        .from(
+            config
+                .from
+                .parse()
+                .context("Invalid email sender address")?,
+        )
+        .subject(&message.title)
+        .header(ContentType::TEXT_PLAIN);
+    for to in &config.to {
+        builder = builder.to(to.parse().context("Invalid email recipient address")?);
+    }
+    let email = builder
+        .body(message.body.clone())
+        .context("Failed to build email")?;
+
+    let security = config.security.unwrap_or(SmtpSecurity::Starttls);
+    let mut transport = match security {
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .context("Failed to set up SMTP transport")?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .context("Failed to set up SMTP transport")?,
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+    };
+    if let Some(port) = config.port {
+        transport = transport.port(port);
+    }
+    if let Some(username) = &config.username {
+        transport = transport.credentials(SmtpCredentials::new(
+            username.clone(),
+            config.password.clone().unwrap_or_default(),
+        ));
+    }
+
+    transport
+        .build()
+        .send(email)
+        .await
+        .context("Failed to send email")?;
+
+    Ok(())
+}
+
+/// Raise a desktop notification through the session's notification daemon
+pub fn send_desktop(message: &Message) -> Result<()> {
+    notify_rust::Notification::new()
+        .appname("pjhoy")
+        .summary(&message.title)
+        .body(&message.body)
+        .icon("user-trash-full")
+        .show()
+        .context("Failed to show desktop notification")?;
+
+    Ok(())
+}
+
+/// POST the schedule changes as JSON to the configured webhook
+pub async fn send_webhook(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    diff: &ScheduleDiff,
+) -> Result<()> {
+    let mut request = client.post(&config.url).json(diff);
+
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request.send().await.context("Failed to call webhook")?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Webhook failed: {}", response.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, group: Option<&str>, next_date: &str) -> TrashService {
+        let builder = TrashService::builder(name, next_date);
+        match group {
+            Some(group) => builder.group(group).build(),
+            None => builder.build(),
+        }
+    }
+
+    #[test]
+    fn test_due_tomorrow() {
+        let services = vec![
+            service("Bio 1", Some("BIO"), "2024-03-05"),
+            service("Bio 2", Some("BIO"), "2024-03-05"),
+            service("Seka", Some("SEK"), "2024-03-12"),
+            service("Kompostori", None, "2024-03-05"),
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+
+        let messages = reminder_messages(&due_tomorrow(&services, today));
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].body, "Biojäte huomenna!");
+        assert_eq!(messages[0].group, Some(ProductGroup::Bio));
+        assert_eq!(messages[1].body, "Kompostori huomenna!");
+    }
+
+    #[test]
+    fn test_weekly_digest() {
+        let services = vec![
+            service("Seka", Some("SEK"), "2024-03-08"),
+            service("Bio", Some("BIO"), "2024-03-05"),
+            service("Paperi", Some("PP"), "2024-03-20"),
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+
+        let reminders = upcoming(&services, today, today + Duration::days(7));
+        let digest = digest_message(&reminders).unwrap();
+
+        assert_eq!(digest.body, "ti 5.3. Biojäte\npe 8.3. Sekajäte");
+        assert!(digest_message(&[]).is_none());
+    }
+
+    #[test]
+    fn test_moved_pickups() {
+        let services = [service("Bio", Some("BIO"), "2024-03-06")];
+        let change = |old: &str, new: &str| Change {
+            service: (&services[0]).into(),
+            old: old.parse().ok(),
+            new: new.parse().ok(),
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let changes = [
+            // Moved by a holiday
+            change("2024-03-05", "2024-03-06"),
+            // Emptied as planned, the next one two weeks later
+            change("2024-03-04", "2024-03-18"),
+            change("2024-03-01", "2024-03-15"),
+        ];
+
+        let moved = moved_pickups(&changes, &services, today);
+        assert_eq!(moved.len(), 1);
+        let messages = moved_messages(&moved);
+        assert_eq!(
+            messages[0].body,
+            "Biojäte: tyhjennys siirtyy ti 5.3. -> ke 6.3."
+        );
+        assert_eq!(messages[0].group, Some(ProductGroup::Bio));
+    }
+
+    #[test]
+    fn test_price_change_message() {
+        let mut bio = service("Bio", Some("BIO"), "2024-03-05");
+        bio.price = Some(Decimal::new(510, 2));
+        let change = Change {
+            service: (&bio).into(),
+            old: bio.price,
+            new: Some(Decimal::new(561, 2)),
+        };
+
+        let message = price_change_message(&[change]).unwrap();
+        assert_eq!(message.body, "Bio (12345): 5.10 € -> 5.61 € (+10.0 %)");
+        assert!(price_change_message(&[]).is_none());
+    }
+
+    #[test]
+    fn test_slack_text() {
+        let message = Message {
+            title: "Biojäte huomenna!".to_string(),
+            body: "Bio <140 l> & Seka".to_string(),
+            group: Some(ProductGroup::Bio),
+        };
+        assert_eq!(
+            slack_text(&message),
+            ":leaves: *Biojäte huomenna!*\nBio &lt;140 l&gt; &amp; Seka"
+        );
+        assert!(slack_text(&failure_message("timeout")).starts_with(":wastebasket: *"));
+    }
+}
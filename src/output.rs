@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+
+/// How informational console messages are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Translated, human readable messages
+    #[default]
+    Normal,
+    /// No informational messages, only errors and requested data
+    Quiet,
+    /// One stable `key<TAB>value...` line per message for scripts
+    Porcelain,
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// Log target of the printed messages, kept off stderr and written to the
+/// `--log-file`
+pub const REPORT_TARGET: &str = "pjhoy::report";
+
+/// Select the output mode for the rest of the run
+pub fn set_mode(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+pub fn mode() -> Mode {
+    MODE.get().copied().unwrap_or_default()
+}
+
+/// When to color listings by product group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// When printing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+/// Select whether to color for the rest of the run
+pub fn set_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+    };
+    let _ = COLOR.set(enabled);
+}
+
+/// ANSI foreground of a `ProductGroup::color`
+fn ansi_code(color: &str) -> Option<&'static str> {
+    match color {
+        "gray" => Some("90"),
+        "red" => Some("31"),
+        "green" => Some("32"),
+        "olive" => Some("33"),
+        "blue" => Some("34"),
+        "purple" => Some("35"),
+        "teal" => Some("36"),
+        "silver" => Some("37"),
+        _ => None,
+    }
+}
+
+fn painted(text: &str, color: Option<&str>) -> String {
+    match color.and_then(ansi_code) {
+        Some(code) => format!("\x1b[{}m{}\x1b[0m", code, text),
+        None => text.to_string(),
+    }
+}
+
+/// `text` in the color of a product group when coloring is on
+pub fn paint(text: &str, color: Option<&str>) -> String {
+    if COLOR.get().copied().unwrap_or(false) {
+        painted(text, color)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Write each record as compact JSON on a line of its own (JSON Lines)
+fn write_json_lines<T: Serialize>(
+    writer: &mut impl Write,
+    records: impl IntoIterator<Item = T>,
+) -> io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Print records as JSON Lines as they come, for `--format jsonl`. A reader
+/// closing the pipe early, like `head`, ends the output without an error.
+pub fn print_json_lines<T: Serialize>(records: impl IntoIterator<Item = T>) -> io::Result<()> {
+    match write_json_lines(&mut io::stdout().lock(), records) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Porcelain line of a message: its key followed by the arguments,
+/// separated by tabs. Tabs and newlines inside values are escaped.
+fn porcelain_line(key: &str, args: &[&dyn Display]) -> String {
+    let mut line = key.to_string();
+    for arg in args {
+        line.push('\t');
+        line.push_str(
+            &arg.to_string()
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n"),
+        );
+    }
+    line
+}
+
+/// Print message `key` according to the output mode
+pub fn print(key: &str, args: &[&dyn Display]) {
+    tracing::info!(target: REPORT_TARGET, "{}", crate::i18n::message(key, args));
+    match mode() {
+        Mode::Normal => println!("{}", crate::i18n::message(key, args)),
+        Mode::Quiet => {}
+        Mode::Porcelain => println!("{}", porcelain_line(key, args)),
+    }
+}
+
+/// Print an informational message, e.g. `report!("calendar-saved", path.display())`
+macro_rules! report {
+    ($key:expr $(, $arg:expr)* $(,)?) => {
+        $crate::output::print($key, &[$(&$arg as &dyn std::fmt::Display),*])
+    };
+}
+pub(crate) use report;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_porcelain_line() {
+        assert_eq!(
+            porcelain_line("fetched-services", &[&12]),
+            "fetched-services\t12"
+        );
+        assert_eq!(
+            porcelain_line("calendar-saved", &[&"/tmp/a\tb.ics"]),
+            "calendar-saved\t/tmp/a\\tb.ics"
+        );
+        assert_eq!(porcelain_line("login-ok", &[]), "login-ok");
+    }
+
+    #[test]
+    fn test_write_json_lines() -> io::Result<()> {
+        let mut out = Vec::new();
+        write_json_lines(
+            &mut out,
+            [
+                serde_json::json!({ "a": 1 }),
+                serde_json::json!({ "b": [2] }),
+            ],
+        )?;
+        assert_eq!(String::from_utf8_lossy(&out), "{\"a\":1}\n{\"b\":[2]}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_painted() {
+        assert_eq!(painted("BIO", Some("green")), "\x1b[32mBIO\x1b[0m");
+        assert_eq!(painted("BIO", None), "BIO");
+        assert_eq!(painted("BIO", Some("chartreuse")), "BIO");
+        // Off unless set_color turns it on
+        assert_eq!(paint("BIO", Some("green")), "BIO");
+    }
+}
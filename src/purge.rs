@@ -0,0 +1,122 @@
+use crate::{client, cookies, history, lock};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Files of the state directory holding account data or diagnostics
+const STATE_FILES: &[&str] = &[
+    cookies::COOKIES_FILE,
+    cookies::LEGACY_COOKIES_FILE,
+    history::HISTORY_FILE,
+    "history.db-journal",
+    crate::SERVICES_FILE,
+    crate::SERVICES_FULL_FILE,
+    client::FAILED_RESPONSE_FILE,
+    "launchd-fetch.log",
+    "launchd-notify.log",
+];
+
+/// Config files, including the default age identity
+const CONFIG_FILES: &[&str] = &["config.toml", "config.toml.age", "identity.txt"];
+
+/// Existing files written by pjhoy: the state files, the calendar with its
+/// backups and, when `config_dir` is given, the config. Files of other
+/// programs sharing the directories are left alone.
+pub fn stored_files(state_dir: &Path, calendar: &Path, config_dir: Option<&Path>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = STATE_FILES
+        .iter()
+        .map(|name| state_dir.join(name))
+        .collect();
+
+    files.push(calendar.to_path_buf());
+    if let (Some(dir), Some(name)) = (calendar.parent(), calendar.file_name()) {
+        let prefix = format!("{}.", name.to_string_lossy());
+        let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .is_some_and(|number| {
+                        !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+                    })
+            })
+            .collect();
+        backups.sort();
+        files.extend(backups);
+    }
+
+    if let Some(dir) = config_dir {
+        files.extend(CONFIG_FILES.iter().map(|name| dir.join(name)));
+    }
+    files.retain(|path| path.is_file());
+    files
+}
+
+/// Delete `files`, then the given directories if nothing but a lock file
+/// is left in them
+pub fn delete(files: &[PathBuf], dirs: &[&Path]) -> Result<()> {
+    for file in files {
+        std::fs::remove_file(file).context(format!("Failed to delete {:?}", file))?;
+    }
+    for dir in dirs {
+        let lock = dir.join(lock::LOCK_FILE);
+        let only_lock = std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().all(|entry| entry.path() == lock))
+            .unwrap_or(false);
+        if only_lock {
+            let _ = std::fs::remove_file(&lock);
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_files() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let (state, data, config) = (dir.join("state"), dir.join("data"), dir.join("config"));
+        for sub in [&state, &data, &config] {
+            std::fs::create_dir_all(sub)?;
+        }
+        for file in [
+            state.join("pjhoy.lock"),
+            config.join("pjhoy.lock"),
+            state.join("cookies.json"),
+            state.join("history.db"),
+            state.join("notes.txt"),
+            data.join("pjhoy.ics"),
+            data.join("pjhoy.ics.1"),
+            data.join("pjhoy.ics.bak"),
+            config.join("config.toml"),
+        ] {
+            std::fs::write(file, "")?;
+        }
+
+        let calendar = data.join("pjhoy.ics");
+        assert_eq!(
+            stored_files(&state, &calendar, None),
+            vec![
+                state.join("cookies.json"),
+                state.join("history.db"),
+                data.join("pjhoy.ics"),
+                data.join("pjhoy.ics.1"),
+            ]
+        );
+        let files = stored_files(&state, &calendar, Some(&config));
+        assert_eq!(files.last(), Some(&config.join("config.toml")));
+
+        delete(&files, &[&state, &config])?;
+        assert!(state.join("notes.txt").exists());
+        assert!(state.join("pjhoy.lock").exists());
+        assert!(!config.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+use crate::calendar::EventOptions;
+use crate::feed::{self, FeedFormat};
+use crate::health;
+use crate::metrics::Metrics;
+use crate::models::TrashService;
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use cron::Schedule;
+use hyper::header::{HeaderValue, ALLOW, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tokio_native_tls::TlsAcceptor;
+
+/// Content type of the Prometheus text format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Fetching a served calendar again when clients ask for it after it has
+/// grown old, instead of only on the schedule
+#[derive(Debug)]
+pub struct Refresh {
+    pub ttl: Duration,
+    /// Wakes the jobs of the account to fetch
+    pub fetch: Arc<Notify>,
+    /// When a fetch was last asked for, successful or not
+    requested_at: Mutex<Option<Instant>>,
+}
+
+impl Refresh {
+    pub fn new(ttl: Duration, fetch: Arc<Notify>) -> Self {
+        Self {
+            ttl,
+            fetch,
+            requested_at: Mutex::new(None),
+        }
+    }
+
+    /// Ask for a fetch when the calendar is older than the TTL. However
+    /// often clients poll, and whether or not the fetches succeed, this
+    /// happens at most once per TTL.
+    fn request_if_stale(&self, age: Option<Duration>, now: Instant) -> bool {
+        if age.is_some_and(|age| age < self.ttl) {
+            return false;
+        }
+        let mut requested_at = self.requested_at.lock().unwrap_or_else(|e| e.into_inner());
+        if requested_at.is_some_and(|at| now.duration_since(at) < self.ttl) {
+            return false;
+        }
+        *requested_at = Some(now);
+        self.fetch.notify_one();
+        true
+    }
+}
+
+/// The calendar file of an account served at a URL path, with a feed of
+/// the same pickups next to it
+#[derive(Debug)]
+pub struct ServedCalendar {
+    /// Without the leading slash, e.g. `pjhoy.ics` or `calendars/koti.ics`
+    pub url_path: String,
+    /// Feeds without the extension, e.g. `feed` for the Atom feed at
+    /// `feed.xml` and JSON Feed at `feed.json`
+    pub feed_path: String,
+    pub path: PathBuf,
+    /// Secret required as `/<token>/pjhoy.ics` or as a bearer token
+    pub token: Option<String>,
+    pub refresh: Option<Refresh>,
+    /// Saved services of the account for the feed
+    pub storage: Arc<dyn Storage>,
+    pub options: EventOptions,
+}
+
+/// What a request for a served calendar asks for
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Resource {
+    Calendar,
+    Feed(FeedFormat),
+}
+
+/// Outcome of matching a request against a served calendar
+#[derive(Debug, PartialEq)]
+enum Access {
+    Allowed(Resource),
+    /// The calendar or feed without a valid token
+    Denied,
+    /// Some other path, or a wrong token in the path, which is not told
+    /// apart so as not to reveal the calendar
+    NotCalendar,
+}
+
+impl ServedCalendar {
+    fn resource(&self, path: &str) -> Option<Resource> {
+        if path == self.url_path {
+            Some(Resource::Calendar)
+        } else {
+            match path.strip_prefix(self.feed_path.as_str())? {
+                ".xml" => Some(Resource::Feed(FeedFormat::Atom)),
+                ".json" => Some(Resource::Feed(FeedFormat::Json)),
+                _ => None,
+            }
+        }
+    }
+
+    fn access(&self, path: &str, authorization: Option<&str>) -> Access {
+        let path = path.trim_start_matches('/');
+
+        if let Some(resource) = self.resource(path) {
+            let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+            return match (&self.token, bearer) {
+                (None, _) => Access::Allowed(resource),
+                (Some(token), Some(given)) if same_secret(token, given.trim()) => {
+                    Access::Allowed(resource)
+                }
+                (Some(_), _) => Access::Denied,
+            };
+        }
+        match (&self.token, path.split_once('/')) {
+            (Some(token), Some((given, rest))) if same_secret(token, given) => self
+                .resource(rest)
+                .map_or(Access::NotCalendar, Access::Allowed),
+            _ => Access::NotCalendar,
+        }
+    }
+
+    /// Have a calendar older than the TTL fetched again
+    async fn refresh_if_stale(&self) {
+        let Some(refresh) = &self.refresh else {
+            return;
+        };
+        let age = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(|modified| modified.elapsed().unwrap_or_default());
+        if refresh.request_if_stale(age, Instant::now()) {
+            tracing::info!("{} is stale, fetching", self.url_path);
+        }
+    }
+}
+
+/// Compare secrets in time independent of where they differ
+fn same_secret(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// What the HTTP server of the daemon answers from
+#[derive(Debug)]
+pub struct ServerState {
+    pub storage: Arc<dyn Storage>,
+    pub metrics: Arc<Metrics>,
+    /// The calendar of the main account and those of the tenants
+    pub calendars: Vec<ServedCalendar>,
+    /// When the daemon fetches, to tell whether the data is stale
+    pub fetch_schedule: Schedule,
+}
+
+/// The services saved by the last fetch, none before the first one
+fn saved_services(storage: &dyn Storage) -> Result<Vec<TrashService>> {
+    match storage.read(crate::SERVICES_FILE)? {
+        Some(data) => serde_json::from_slice(&data)
+            .context(format!("Failed to parse {}", crate::SERVICES_FILE)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// TLS setup from a PEM certificate chain and its PKCS#8 PEM private key
+pub fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let cert_pem = std::fs::read(cert).context(format!("Failed to read {:?}", cert))?;
+    let key_pem = std::fs::read(key).context(format!("Failed to read {:?}", key))?;
+    let identity = native_tls::Identity::from_pkcs8(&cert_pem, &key_pem).context(format!(
+        "Failed to load the certificate {:?} with the key {:?}",
+        cert, key
+    ))?;
+    let acceptor = native_tls::TlsAcceptor::new(identity).context("Failed to set up TLS")?;
+    Ok(TlsAcceptor::from(acceptor))
+}
+
+/// Where the daemon serves, over HTTPS when `tls` is given
+pub struct Listen {
+    pub addr: SocketAddr,
+    pub tls: Option<TlsAcceptor>,
+}
+
+impl Listen {
+    /// URL of the server root for messages
+    pub fn url(&self) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://{}", scheme, self.addr)
+    }
+}
+
+/// Bind right away, so that a taken port fails the start, and return the
+/// server to run
+pub async fn bind(listen: Listen, state: Arc<ServerState>) -> Result<impl Future<Output = ()>> {
+    let Listen { addr, tls } = listen;
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    Ok(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    // E.g. out of file descriptors, which takes a while to pass
+                    tracing::error!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let (state, tls) = (state.clone(), tls.clone());
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(respond(&request, &state).await) }
+                });
+                let result = match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => Http::new().serve_connection(stream, service).await,
+                        Err(e) => {
+                            tracing::debug!("TLS handshake with {} failed: {}", peer, e);
+                            return;
+                        }
+                    },
+                    None => Http::new().serve_connection(stream, service).await,
+                };
+                if let Err(e) = result {
+                    tracing::debug!("Connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    })
+}
+
+fn text_response(status: StatusCode, content_type: &'static str, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+/// The calendar file, 404 before the first fetch has written it. A stale
+/// calendar is still served while a fresh one is fetched, so that clients
+/// never wait for the extranet and keep getting one when it is down.
+async fn calendar_response(calendar: &ServedCalendar) -> Response<Body> {
+    calendar.refresh_if_stale().await;
+    match tokio::fs::read(&calendar.path).await {
+        Ok(data) => {
+            let mut response = Response::new(Body::from(data));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/calendar; charset=utf-8"),
+            );
+            response
+        }
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::error!("Failed to read {:?}: {}", calendar.path, e);
+            }
+            text_response(
+                StatusCode::NOT_FOUND,
+                "text/plain",
+                "No calendar yet\n".to_string(),
+            )
+        }
+    }
+}
+
+/// Feed of the saved services, refreshed like the calendar
+async fn feed_response(calendar: &ServedCalendar, format: FeedFormat) -> Response<Body> {
+    calendar.refresh_if_stale().await;
+    match saved_services(calendar.storage.as_ref()) {
+        Ok(services) => {
+            let updated = calendar
+                .storage
+                .modified(crate::SERVICES_FILE)
+                .map(chrono::DateTime::from)
+                .unwrap_or_else(chrono::Utc::now);
+            let today = chrono::Local::now().date_naive();
+            text_response(
+                StatusCode::OK,
+                format.content_type(),
+                feed::generate(format, &services, &calendar.options, updated, today),
+            )
+        }
+        Err(e) => services_error(e),
+    }
+}
+
+fn services_error(e: anyhow::Error) -> Response<Body> {
+    tracing::error!("{:#}", e);
+    text_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "text/plain",
+        "Saved services could not be read\n".to_string(),
+    )
+}
+
+async fn respond(request: &Request<Body>, state: &ServerState) -> Response<Body> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        let mut response = text_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        );
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
+    }
+
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let access = state.calendars.iter().find_map(|calendar| {
+        match calendar.access(request.uri().path(), authorization) {
+            Access::NotCalendar => None,
+            access => Some((calendar, access)),
+        }
+    });
+    match access {
+        Some((calendar, Access::Allowed(Resource::Calendar))) => {
+            return calendar_response(calendar).await
+        }
+        Some((calendar, Access::Allowed(Resource::Feed(format)))) => {
+            return feed_response(calendar, format).await
+        }
+        Some((_, _)) => {
+            let mut response = text_response(
+                StatusCode::UNAUTHORIZED,
+                "text/plain",
+                "Token required\n".to_string(),
+            );
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+        None => {}
+    }
+
+    match request.uri().path() {
+        "/metrics" => match saved_services(state.storage.as_ref()) {
+            Ok(services) => {
+                let today = chrono::Local::now().date_naive();
+                text_response(
+                    StatusCode::OK,
+                    METRICS_CONTENT_TYPE,
+                    state.metrics.render(&services, today),
+                )
+            }
+            Err(e) => services_error(e),
+        },
+        "/healthz" => {
+            let health = health::check(&state.metrics, &state.fetch_schedule, chrono::Utc::now());
+            let status = if health.healthy {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            let body = serde_json::to_string(&health).expect("health serializes") + "\n";
+            text_response(status, "application/json", body)
+        }
+        _ => text_response(
+            StatusCode::NOT_FOUND,
+            "text/plain",
+            "Not found\n".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_access() {
+        let mut calendar = ServedCalendar {
+            url_path: "pjhoy.ics".to_string(),
+            feed_path: "feed".to_string(),
+            path: PathBuf::from("/srv/pjhoy/pjhoy.ics"),
+            token: None,
+            refresh: None,
+            storage: Arc::new(crate::storage::MemoryStorage::default()),
+            options: EventOptions::default(),
+        };
+        assert_eq!(
+            calendar.access("/pjhoy.ics", None),
+            Access::Allowed(Resource::Calendar)
+        );
+        assert_eq!(calendar.access("/metrics", None), Access::NotCalendar);
+
+        calendar.token = Some("s3cret".to_string());
+        assert_eq!(calendar.access("/pjhoy.ics", None), Access::Denied);
+        assert_eq!(
+            calendar.access("/pjhoy.ics", Some("Bearer wrong")),
+            Access::Denied
+        );
+        assert_eq!(
+            calendar.access("/pjhoy.ics", Some("Bearer s3cret")),
+            Access::Allowed(Resource::Calendar)
+        );
+        assert_eq!(
+            calendar.access("/s3cret/pjhoy.ics", None),
+            Access::Allowed(Resource::Calendar)
+        );
+        assert_eq!(
+            calendar.access("/s3cret/feed.xml", None),
+            Access::Allowed(Resource::Feed(FeedFormat::Atom))
+        );
+        assert_eq!(
+            calendar.access("/s3cret/feed.json", None),
+            Access::Allowed(Resource::Feed(FeedFormat::Json))
+        );
+        assert_eq!(
+            calendar.access("/s3cret/feed.rss", None),
+            Access::NotCalendar
+        );
+        assert_eq!(
+            calendar.access("/s3cre/pjhoy.ics", None),
+            Access::NotCalendar
+        );
+    }
+
+    #[test]
+    fn test_refresh_at_most_once_per_ttl() {
+        let hour = Duration::from_secs(3600);
+        let refresh = Refresh::new(6 * hour, Arc::new(Notify::new()));
+        let start = Instant::now();
+
+        assert!(!refresh.request_if_stale(Some(hour), start));
+        assert!(refresh.request_if_stale(Some(7 * hour), start));
+        // The fetch failed and the calendar is still old
+        assert!(!refresh.request_if_stale(Some(8 * hour), start + hour));
+        assert!(refresh.request_if_stale(Some(13 * hour), start + 6 * hour));
+        assert!(!refresh.request_if_stale(None, start + 7 * hour));
+    }
+}
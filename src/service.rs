@@ -0,0 +1,326 @@
+use crate::daemon::{Job, Schedules};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use cron::{Schedule, TimeUnitSpec};
+use std::path::{Path, PathBuf};
+
+/// Service manager to write the definitions for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ServiceManager {
+    /// User-level systemd service and timer units
+    Systemd,
+    /// macOS launchd agent
+    Launchd,
+}
+
+impl Default for ServiceManager {
+    fn default() -> Self {
+        if cfg!(target_os = "macos") {
+            ServiceManager::Launchd
+        } else {
+            ServiceManager::Systemd
+        }
+    }
+}
+
+impl ServiceManager {
+    /// Directory the service manager loads user definitions from
+    pub fn install_dir(&self) -> Result<PathBuf> {
+        let dirs = directories::BaseDirs::new().context("Could not determine home directory")?;
+        Ok(match self {
+            ServiceManager::Systemd => dirs.config_dir().join("systemd").join("user"),
+            ServiceManager::Launchd => dirs.home_dir().join("Library").join("LaunchAgents"),
+        })
+    }
+
+    /// Command that starts the installed definitions
+    pub fn enable_command(&self, dir: &Path, files: &[ServiceFile]) -> String {
+        match self {
+            ServiceManager::Systemd => {
+                let timers: Vec<&str> = files
+                    .iter()
+                    .map(|file| file.name.as_str())
+                    .filter(|name| name.ends_with(".timer"))
+                    .collect();
+                format!(
+                    "systemctl --user daemon-reload && systemctl --user enable --now {}",
+                    timers.join(" ")
+                )
+            }
+            ServiceManager::Launchd => files
+                .iter()
+                .map(|file| format!("launchctl load {}", dir.join(&file.name).display()))
+                .collect::<Vec<_>>()
+                .join(" && "),
+        }
+    }
+}
+
+/// A generated definition file
+#[derive(Debug, PartialEq)]
+pub struct ServiceFile {
+    pub name: String,
+    pub contents: String,
+}
+
+/// Definitions running `exe` on the schedules of the `[daemon]` config, one
+/// per job
+pub fn service_files(
+    manager: ServiceManager,
+    schedules: &Schedules,
+    exe: &Path,
+    output_path: &Path,
+    state_dir: &Path,
+) -> Result<Vec<ServiceFile>> {
+    let mut files = Vec::new();
+    for (job, schedule) in schedules.jobs() {
+        let mut args = vec![
+            exe.display().to_string(),
+            "--state-dir".to_string(),
+            state_dir.display().to_string(),
+        ];
+        match job {
+            Job::Fetch => args.extend([
+                "--output".to_string(),
+                output_path.display().to_string(),
+                "fetch".to_string(),
+                "--save-json".to_string(),
+            ]),
+            Job::Notify => args.push("notify".to_string()),
+        }
+
+        match manager {
+            ServiceManager::Systemd => {
+                files.push(ServiceFile {
+                    name: format!("pjhoy-{}.service", job),
+                    contents: systemd_service(job, &args),
+                });
+                files.push(ServiceFile {
+                    name: format!("pjhoy-{}.timer", job),
+                    contents: systemd_timer(job, schedule),
+                });
+            }
+            ServiceManager::Launchd => {
+                let log = state_dir.join(format!("launchd-{}.log", job));
+                files.push(ServiceFile {
+                    name: format!("fi.pjhoy.{}.plist", job),
+                    contents: launchd_plist(job, &args, schedule, &log)?,
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn systemd_service(job: Job, args: &[String]) -> String {
+    let command: Vec<String> = args.iter().map(|arg| systemd_quote(arg)).collect();
+    format!(
+        "[Unit]\n\
+         Description=pjhoy {job}\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={}\n",
+        command.join(" ")
+    )
+}
+
+fn systemd_timer(job: Job, schedule: &Schedule) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Run pjhoy {job} on schedule\n\
+         \n\
+         [Timer]\n\
+         OnCalendar={}\n\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        on_calendar(schedule)
+    )
+}
+
+/// Quote an `ExecStart` word, escaping the specifiers and variables systemd
+/// would otherwise expand
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+/// Included values of a cron field as a systemd list, `*` for all
+fn calendar_list(spec: &impl TimeUnitSpec, width: usize) -> String {
+    if spec.is_all() {
+        return "*".to_string();
+    }
+    spec.iter()
+        .map(|value| format!("{:0width$}", value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Cron weekday numbers start from Sunday as 1
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// The cron schedule as a systemd calendar event, e.g. `*-*-* 06:00:00`
+fn on_calendar(schedule: &Schedule) -> String {
+    let weekdays = if schedule.days_of_week().is_all() {
+        String::new()
+    } else {
+        let names: Vec<&str> = schedule
+            .days_of_week()
+            .iter()
+            .map(|day| WEEKDAYS[day as usize - 1])
+            .collect();
+        format!("{} ", names.join(","))
+    };
+    format!(
+        "{}{}-{}-{} {}:{}:{}",
+        weekdays,
+        calendar_list(schedule.years(), 4),
+        calendar_list(schedule.months(), 2),
+        calendar_list(schedule.days_of_month(), 2),
+        calendar_list(schedule.hours(), 2),
+        calendar_list(schedule.minutes(), 2),
+        calendar_list(schedule.seconds(), 2),
+    )
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// launchd keys of a cron field with its values, `None` for all
+fn launchd_values(
+    key: &'static str,
+    spec: &impl TimeUnitSpec,
+    offset: u32,
+) -> Option<(&'static str, Vec<u32>)> {
+    (!spec.is_all()).then(|| (key, spec.iter().map(|value| value - offset).collect()))
+}
+
+/// launchd has no lists or ranges, so the schedule becomes one
+/// `StartCalendarInterval` entry per combination of the restricted fields
+fn calendar_intervals(schedule: &Schedule) -> Result<Vec<Vec<(&'static str, u32)>>> {
+    if schedule.seconds().iter().any(|second| second != 0) {
+        bail!(
+            "launchd cannot start jobs at seconds, use 0 as the seconds of {:?}",
+            schedule.source()
+        );
+    }
+    if !schedule.years().is_all() {
+        bail!(
+            "launchd cannot limit jobs to years: {:?}",
+            schedule.source()
+        );
+    }
+
+    let fields = [
+        launchd_values("Minute", schedule.minutes(), 0),
+        launchd_values("Hour", schedule.hours(), 0),
+        launchd_values("Day", schedule.days_of_month(), 0),
+        launchd_values("Month", schedule.months(), 0),
+        // launchd counts weekdays from Sunday as 0
+        launchd_values("Weekday", schedule.days_of_week(), 1),
+    ];
+    let mut intervals = vec![Vec::new()];
+    for (key, values) in fields.into_iter().flatten() {
+        intervals = intervals
+            .into_iter()
+            .flat_map(|interval: Vec<(&'static str, u32)>| {
+                values.iter().map(move |&value| {
+                    let mut interval = interval.clone();
+                    interval.push((key, value));
+                    interval
+                })
+            })
+            .collect();
+    }
+    Ok(intervals)
+}
+
+fn launchd_plist(job: Job, args: &[String], schedule: &Schedule, log: &Path) -> Result<String> {
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n",
+    );
+    plist.push_str(&format!(
+        "  <key>Label</key>\n  <string>fi.pjhoy.{}</string>\n",
+        job
+    ));
+    plist.push_str("  <key>ProgramArguments</key>\n  <array>\n");
+    for arg in args {
+        plist.push_str(&format!("    <string>{}</string>\n", xml_escape(arg)));
+    }
+    plist.push_str("  </array>\n  <key>StartCalendarInterval</key>\n  <array>\n");
+    for interval in calendar_intervals(schedule)? {
+        plist.push_str("    <dict>\n");
+        for (key, value) in interval {
+            plist.push_str(&format!(
+                "      <key>{}</key>\n      <integer>{}</integer>\n",
+                key, value
+            ));
+        }
+        plist.push_str("    </dict>\n");
+    }
+    let log = xml_escape(&log.display().to_string());
+    plist.push_str(&format!(
+        "  </array>\n\
+         \x20 <key>StandardOutPath</key>\n  <string>{log}</string>\n\
+         \x20 <key>StandardErrorPath</key>\n  <string>{log}</string>\n\
+         </dict>\n\
+         </plist>\n"
+    ));
+    Ok(plist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::parse_schedule;
+
+    #[test]
+    fn test_on_calendar() -> Result<()> {
+        assert_eq!(
+            on_calendar(&parse_schedule("0 0 6 * * *")?),
+            "*-*-* 06:00:00"
+        );
+        assert_eq!(
+            on_calendar(&parse_schedule("0 30 6,18 * * Mon-Fri")?),
+            "Mon,Tue,Wed,Thu,Fri *-*-* 06,18:30:00"
+        );
+        assert_eq!(
+            on_calendar(&parse_schedule("0 0 7 1 */6 *")?),
+            "*-01,07-01 07:00:00"
+        );
+        assert_eq!(
+            systemd_quote("/home/me/100% $HOME"),
+            "\"/home/me/100%% $$HOME\""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_calendar_intervals() -> Result<()> {
+        assert_eq!(
+            calendar_intervals(&parse_schedule("0 30 6,18 * * Sun")?)?,
+            vec![
+                vec![("Minute", 30), ("Hour", 6), ("Weekday", 0)],
+                vec![("Minute", 30), ("Hour", 18), ("Weekday", 0)],
+            ]
+        );
+        assert!(calendar_intervals(&parse_schedule("30 0 6 * * *")?).is_err());
+        Ok(())
+    }
+}
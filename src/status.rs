@@ -0,0 +1,62 @@
+use crate::i18n::tr;
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::fmt;
+
+/// State of the saved session and data
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub session_valid: bool,
+    pub cookies_saved_at: Option<DateTime<Utc>>,
+    pub last_fetched_at: Option<DateTime<Utc>>,
+}
+
+fn local_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| {
+        time.with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    })
+    .unwrap_or_else(|| tr!("never"))
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let session = if self.session_valid {
+            tr!("session-valid")
+        } else {
+            tr!("session-invalid")
+        };
+        writeln!(f, "{}", session)?;
+        writeln!(
+            f,
+            "{}",
+            tr!("cookies-saved-at", local_time(self.cookies_saved_at))
+        )?;
+        writeln!(
+            f,
+            "{}",
+            tr!("last-fetched-at", local_time(self.last_fetched_at))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_display() {
+        let status = Status {
+            session_valid: false,
+            cookies_saved_at: None,
+            last_fetched_at: None,
+        };
+
+        let text = status.to_string();
+
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.contains(&tr!("session-invalid")));
+        assert!(text.contains(&tr!("never")));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::calendar::{event_category, EventOptions};
+use crate::costs::pickup_dates;
+use crate::i18n::tr;
+use crate::models::TrashService;
+use chrono::{Duration, NaiveDate};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Output of `pjhoy status-bar`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum BarFormat {
+    /// JSON object for Waybar custom modules
+    #[default]
+    Waybar,
+    /// Plain line for i3blocks, polybar and the like
+    Text,
+}
+
+/// Services picked up on the first pickup day from today on
+#[derive(Debug, PartialEq)]
+pub struct NextPickup {
+    pub date: NaiveDate,
+    pub days: i64,
+    /// Icon and name of each product group, without duplicates
+    pub groups: Vec<(String, String)>,
+}
+
+/// Next pickup of a product group in `pjhoy next`
+#[derive(Debug, Serialize, PartialEq)]
+pub struct GroupPickup {
+    pub date: NaiveDate,
+    pub in_days: i64,
+}
+
+/// Waybar custom module output, see `man waybar-custom`
+#[derive(Debug, Serialize, PartialEq)]
+struct WaybarOutput {
+    text: String,
+    tooltip: String,
+    /// `today`, `tomorrow` or `later` for styling
+    class: String,
+}
+
+/// Icon of a service as in the calendar, the group code when emojis are
+/// turned off
+pub fn icon(service: &TrashService, options: &EventOptions) -> String {
+    let Some(group) = service
+        .tariff
+        .as_ref()
+        .and_then(|tariff| tariff.product_group.as_ref())
+    else {
+        return "📦".to_string();
+    };
+    match options.icon(group) {
+        icon if icon.is_empty() => group.code().to_string(),
+        icon => icon,
+    }
+}
+
+/// The first pickup of each service on or after `today`. Pickup dates of
+/// services last fetched a while ago are carried forward by their
+/// intervals, so a stale cache still gives the right day.
+fn upcoming(services: &[TrashService], today: NaiveDate) -> Vec<(NaiveDate, &TrashService)> {
+    // Intervals are at most a few months
+    let until = today + Duration::days(366);
+    services
+        .iter()
+        .filter_map(|service| {
+            let date = pickup_dates(service, today, until).into_iter().next()?;
+            Some((date, service))
+        })
+        .collect()
+}
+
+/// Whether any of the services is picked up on `date`
+pub fn is_pickup_day(services: &[TrashService], date: NaiveDate) -> bool {
+    upcoming(services, date)
+        .iter()
+        .any(|(pickup, _)| *pickup == date)
+}
+
+/// Icon and name of each group picked up on `date`
+pub fn pickups_on(
+    services: &[TrashService],
+    options: &EventOptions,
+    date: NaiveDate,
+) -> Vec<(String, String)> {
+    next_pickup(services, options, date)
+        .filter(|next| next.days == 0)
+        .map(|next| next.groups)
+        .unwrap_or_default()
+}
+
+/// The next pickup day on or after `today` with the groups picked up then
+pub fn next_pickup(
+    services: &[TrashService],
+    options: &EventOptions,
+    today: NaiveDate,
+) -> Option<NextPickup> {
+    let dated = upcoming(services, today);
+    let date = dated.iter().map(|(date, _)| *date).min()?;
+
+    let mut groups: Vec<(String, String)> = Vec::new();
+    for (_, service) in dated.iter().filter(|(day, _)| *day == date) {
+        let name = event_category(service, options).unwrap_or_else(|| service.name.clone());
+        let group = (icon(service, options), name);
+        if !groups.contains(&group) {
+            groups.push(group);
+        }
+    }
+
+    Some(NextPickup {
+        date,
+        days: (date - today).num_days(),
+        groups,
+    })
+}
+
+/// The next pickup of each product group keyed by its code, or by the
+/// service name for services without a group
+pub fn next_by_group(services: &[TrashService], today: NaiveDate) -> BTreeMap<String, GroupPickup> {
+    let mut next: BTreeMap<String, GroupPickup> = BTreeMap::new();
+    for (date, service) in upcoming(services, today) {
+        let key = service
+            .tariff
+            .as_ref()
+            .and_then(|tariff| tariff.product_group.as_ref())
+            .map_or_else(|| service.name.clone(), |group| group.code().to_string());
+        if next.get(&key).is_none_or(|known| date < known.date) {
+            let in_days = (date - today).num_days();
+            next.insert(key, GroupPickup { date, in_days });
+        }
+    }
+    next
+}
+
+/// "today", "tomorrow" or the number of days left
+pub fn when(days: i64) -> String {
+    match days {
+        0 => tr!("bar-today"),
+        1 => tr!("bar-tomorrow"),
+        days => tr!("bar-days", days),
+    }
+}
+
+/// The line to print, empty when nothing is coming so that bars hide the
+/// module
+pub fn render(next: Option<&NextPickup>, format: BarFormat) -> String {
+    let Some(next) = next else {
+        return match format {
+            BarFormat::Waybar => serde_json::json!({ "text": "" }).to_string(),
+            BarFormat::Text => String::new(),
+        };
+    };
+    let icons: Vec<&str> = next.groups.iter().map(|(icon, _)| icon.as_str()).collect();
+    let text = format!("{} {}", icons.concat(), when(next.days));
+
+    match format {
+        BarFormat::Text => text,
+        BarFormat::Waybar => {
+            let names: Vec<&str> = next.groups.iter().map(|(_, name)| name.as_str()).collect();
+            let output = WaybarOutput {
+                text,
+                tooltip: format!(
+                    "{}: {} ({})",
+                    next.date.format("%d.%m.%Y"),
+                    names.join(", "),
+                    when(next.days)
+                ),
+                class: match next.days {
+                    0 => "today",
+                    1 => "tomorrow",
+                    _ => "later",
+                }
+                .to_string(),
+            };
+            serde_json::to_string(&output).expect("status bar output serializes")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CalendarConfig;
+
+    fn service(group: &str, next_date: &str, interval: &str) -> TrashService {
+        TrashService::builder("Astia", next_date)
+            .interval(interval)
+            .group(group)
+            .build()
+    }
+
+    #[test]
+    fn test_next_pickup() {
+        let options = EventOptions {
+            calendar: CalendarConfig {
+                icons: Some([("SEK".to_string(), "S".to_string())].into()),
+                ..CalendarConfig::default()
+            },
+            ..EventOptions::default()
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let services = [
+            // Fetched two weeks ago, picked up again today + 2
+            service("BIO", "2024-02-21", "2"),
+            service("SEK", "2024-03-06", "4"),
+            service("MU", "2024-03-20", "4"),
+        ];
+
+        let next = next_pickup(&services, &options, today).unwrap();
+        assert_eq!(next.date, NaiveDate::from_ymd_opt(2024, 3, 6).unwrap());
+        assert_eq!(next.days, 2);
+        assert_eq!(
+            next.groups,
+            vec![
+                ("🍃".to_string(), "Biojäte".to_string()),
+                ("S".to_string(), "Sekajäte".to_string()),
+            ]
+        );
+
+        assert_eq!(render(Some(&next), BarFormat::Text), "🍃S 2 pv");
+        let waybar: serde_json::Value =
+            serde_json::from_str(&render(Some(&next), BarFormat::Waybar)).unwrap();
+        assert_eq!(waybar["class"], "later");
+        assert_eq!(waybar["tooltip"], "06.03.2024: Biojäte, Sekajäte (2 pv)");
+        assert_eq!(render(None, BarFormat::Text), "");
+
+        assert_eq!(pickups_on(&services, &options, next.date).len(), 2);
+        assert!(pickups_on(&services, &options, today).is_empty());
+    }
+
+    #[test]
+    fn test_next_by_group() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let services = [
+            service("BIO", "2024-03-13", "2"),
+            service("BIO", "2024-03-06", "2"),
+            service("MU", "2024-01-01", ""),
+        ];
+
+        assert!(is_pickup_day(&services, today + Duration::days(16)));
+        assert!(!is_pickup_day(&services, today + Duration::days(1)));
+
+        let next = next_by_group(&services, today);
+        assert_eq!(
+            serde_json::to_value(&next).unwrap(),
+            serde_json::json!({ "BIO": { "date": "2024-03-06", "in_days": 2 } })
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::{PjhoyError, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Where the client keeps its state: session cookies, the fetched schedule
+/// and diagnostics. Entries are addressed by file names such as
+/// `cookies.json`.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// Contents of an entry, `None` if it does not exist
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Create or replace an entry
+    fn write(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// Delete an entry, succeeding if it does not exist
+    fn remove(&self, key: &str) -> Result<()>;
+
+    /// When the entry was last written
+    fn modified(&self, key: &str) -> Option<SystemTime>;
+
+    /// Path of the entry on the filesystem, for consumers that need a real
+    /// file such as the SQLite history. `None` when not backed by files.
+    fn path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Where an entry lives, for messages
+    fn location(&self, key: &str) -> String {
+        match self.path(key) {
+            Some(path) => path.display().to_string(),
+            None => key.to_string(),
+        }
+    }
+}
+
+/// Entries as files of a directory, normally the data directory
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(key);
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(PjhoyError::io(format!("Failed to read {:?}", path), e)),
+        }
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.dir.join(key);
+        write_atomic(&path, data, 0)
+            .map_err(|e| PjhoyError::io(format!("Failed to write {:?}", path), e))
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let path = self.dir.join(key);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(PjhoyError::io(format!("Failed to delete {:?}", path), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn modified(&self, key: &str) -> Option<SystemTime> {
+        std::fs::metadata(self.dir.join(key))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.dir.join(key))
+    }
+}
+
+/// Name of the `number`th older generation of `path`, e.g. `pjhoy.ics.2`
+fn generation(path: &Path, number: usize) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}", file_name, number))
+}
+
+/// Move `path.1` to `path.2` and so on, dropping the one beyond `keep`,
+/// to make room for a new `path.1`
+fn shift_generations(path: &Path, keep: usize) -> std::io::Result<()> {
+    for number in (1..keep).rev() {
+        let older = generation(path, number);
+        if older.exists() {
+            std::fs::rename(&older, generation(path, number + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move `path` to `path.1`, keeping `keep` older generations like
+/// `write_atomic`; with `keep` 0 it is deleted
+pub fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    shift_generations(path, keep)?;
+    std::fs::rename(path, generation(path, 1))
+}
+
+/// Replace the file at `path` with `data` so that readers see either the
+/// old or the new contents, never a partial write: the data goes to a
+/// temporary file in the same directory which is then renamed over `path`.
+/// With `backups` > 0 the replaced contents are kept as `path.1`, the one
+/// before as `path.2` and so on.
+pub fn write_atomic(path: &Path, data: &[u8], backups: usize) -> std::io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(data)?;
+        file.sync_all()?;
+
+        if backups > 0 && path.exists() {
+            shift_generations(path, backups)?;
+            // A link keeps `path` in place until the rename below
+            let backup = generation(path, 1);
+            if std::fs::hard_link(path, &backup).is_err() {
+                std::fs::copy(path, &backup)?;
+            }
+        }
+        std::fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Entries kept in memory for the lifetime of the value, for tests and
+/// environments without a writable directory
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: RwLock<BTreeMap<String, (Vec<u8>, SystemTime)>>,
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let entries = self.entries.read().expect("storage lock poisoned");
+        Ok(entries.get(key).map(|(data, _)| data.clone()))
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        let mut entries = self.entries.write().expect("storage lock poisoned");
+        entries.insert(key.to_string(), (data.to_vec(), SystemTime::now()));
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let mut entries = self.entries.write().expect("storage lock poisoned");
+        entries.remove(key);
+        Ok(())
+    }
+
+    fn modified(&self, key: &str) -> Option<SystemTime> {
+        let entries = self.entries.read().expect("storage lock poisoned");
+        entries.get(key).map(|(_, modified)| *modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn round_trip(storage: &dyn Storage) -> Result<()> {
+        assert_eq!(storage.read("entry.txt")?, None);
+        assert!(storage.modified("entry.txt").is_none());
+
+        storage.write("entry.txt", b"first")?;
+        storage.write("entry.txt", b"second")?;
+        assert_eq!(storage.read("entry.txt")?.as_deref(), Some(&b"second"[..]));
+        assert!(storage.modified("entry.txt").is_some());
+
+        storage.remove("entry.txt")?;
+        storage.remove("entry.txt")?;
+        assert_eq!(storage.read("entry.txt")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_storage() -> Result<()> {
+        let storage = MemoryStorage::default();
+        round_trip(&storage)?;
+        assert_eq!(storage.location("cookies.json"), "cookies.json");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_backups() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let path = dir.join("pjhoy.ics");
+
+        for version in ["one", "two", "three", "four"] {
+            write_atomic(&path, version.as_bytes(), 2)?;
+        }
+        assert_eq!(std::fs::read_to_string(&path)?, "four");
+        assert_eq!(std::fs::read_to_string(dir.join("pjhoy.ics.1"))?, "three");
+        assert_eq!(std::fs::read_to_string(dir.join("pjhoy.ics.2"))?, "two");
+        assert!(!dir.join("pjhoy.ics.3").exists());
+        // No temporary files are left behind
+        assert_eq!(std::fs::read_dir(dir)?.count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_storage() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let storage = FileStorage::new(dir);
+        round_trip(&storage)?;
+        assert_eq!(storage.path("cookies.json"), Some(dir.join("cookies.json")));
+        Ok(())
+    }
+}
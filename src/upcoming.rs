@@ -0,0 +1,241 @@
+use crate::calendar::{event_category, EventOptions};
+use crate::costs::pickup_dates;
+use crate::models::TrashService;
+use crate::output::paint;
+use chrono::{Datelike, Duration, NaiveDate};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How `pjhoy upcoming` groups the pickups
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum GroupBy {
+    /// ISO week of the pickup
+    Week,
+    Customer,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct UpcomingPickup {
+    pub date: NaiveDate,
+    pub customer_number: String,
+    pub name: String,
+    /// Product group name as in the calendar categories
+    pub group: Option<String>,
+    /// Price including VAT, `None` when the extranet gives none
+    pub cost: Option<Decimal>,
+    #[serde(skip)]
+    color: Option<&'static str>,
+}
+
+/// Pickups of one week or customer number with their totals
+#[derive(Debug, Serialize)]
+pub struct PickupGroup {
+    /// e.g. `2024-W10` or the customer number
+    pub key: String,
+    /// Heading of the group in the table
+    pub title: String,
+    pub pickups: Vec<UpcomingPickup>,
+    pub total: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpcomingTable {
+    pub from: NaiveDate,
+    pub until: NaiveDate,
+    pub groups: Vec<PickupGroup>,
+    pub pickups: usize,
+    pub total: Decimal,
+}
+
+/// Key and heading of the group of a pickup
+fn group_of(pickup: &UpcomingPickup, group_by: GroupBy) -> (String, String) {
+    match group_by {
+        GroupBy::Week => {
+            let week = pickup.date.iso_week();
+            let monday =
+                pickup.date - Duration::days(pickup.date.weekday().num_days_from_monday().into());
+            let sunday = monday + Duration::days(6);
+            (
+                format!("{}-W{:02}", week.year(), week.week()),
+                format!(
+                    "Week {} ({} – {})",
+                    week.week(),
+                    monday.format("%d.%m."),
+                    sunday.format("%d.%m.%Y")
+                ),
+            )
+        }
+        GroupBy::Customer => (
+            pickup.customer_number.clone(),
+            format!("Customer {}", pickup.customer_number),
+        ),
+    }
+}
+
+/// Pickups from `from` to `until` (inclusive), grouped and summed
+pub fn upcoming(
+    services: &[TrashService],
+    options: &EventOptions,
+    from: NaiveDate,
+    until: NaiveDate,
+    group_by: GroupBy,
+) -> UpcomingTable {
+    let mut pickups: Vec<UpcomingPickup> = services
+        .iter()
+        .flat_map(|service| {
+            pickup_dates(service, from, until + Duration::days(1))
+                .into_iter()
+                .map(move |date| UpcomingPickup {
+                    date,
+                    customer_number: service.customer_number.clone(),
+                    name: service.name.clone(),
+                    group: event_category(service, options),
+                    cost: service
+                        .price
+                        .map(|price| options.vat_rates.gross_on(price, date)),
+                    color: service.color(),
+                })
+        })
+        .collect();
+    pickups.sort_by(|a, b| {
+        (a.date, &a.customer_number, &a.name).cmp(&(b.date, &b.customer_number, &b.name))
+    });
+
+    let mut groups: BTreeMap<String, PickupGroup> = BTreeMap::new();
+    let mut total = Decimal::ZERO;
+    let count = pickups.len();
+    for pickup in pickups {
+        let (key, title) = group_of(&pickup, group_by);
+        let group = groups.entry(key.clone()).or_insert_with(|| PickupGroup {
+            key,
+            title,
+            pickups: Vec::new(),
+            total: Decimal::ZERO,
+        });
+        let cost = pickup.cost.unwrap_or_default();
+        group.total += cost;
+        total += cost;
+        group.pickups.push(pickup);
+    }
+
+    UpcomingTable {
+        from,
+        until,
+        groups: groups.into_values().collect(),
+        pickups: count,
+        total,
+    }
+}
+
+impl fmt::Display for UpcomingTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pickups = || self.groups.iter().flat_map(|group| &group.pickups);
+        let width = |column: fn(&UpcomingPickup) -> usize, header: &str| {
+            pickups().map(column).max().unwrap_or(0).max(header.len())
+        };
+        let name_width = width(|pickup| pickup.name.chars().count(), "Service");
+        let group_width = width(
+            |pickup| {
+                pickup
+                    .group
+                    .as_deref()
+                    .map_or(1, |group| group.chars().count())
+            },
+            "Group",
+        );
+        let customer_width = width(|pickup| pickup.customer_number.len(), "Customer");
+        let cost_column = 10 + 2;
+
+        for (index, group) in self.groups.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", group.title)?;
+            writeln!(
+                f,
+                "  {:<10}  {:<customer_width$}  {:<name_width$}  {:<group_width$}  {:>cost_column$}",
+                "Date", "Customer", "Service", "Group", "Cost"
+            )?;
+            for pickup in &group.pickups {
+                let group_name =
+                    format!("{:<group_width$}", pickup.group.as_deref().unwrap_or("-"));
+                let cost = pickup
+                    .cost
+                    .map_or_else(|| "-".to_string(), |cost| format!("{:.2} €", cost));
+                writeln!(
+                    f,
+                    "  {:<10}  {:<customer_width$}  {:<name_width$}  {}  {:>cost_column$}",
+                    pickup.date.format("%d.%m.%Y"),
+                    pickup.customer_number,
+                    pickup.name,
+                    paint(&group_name, pickup.color),
+                    cost
+                )?;
+            }
+            let line = 2 + 10 + 2 + customer_width + 2 + name_width + 2 + group_width + 2;
+            writeln!(
+                f,
+                "{:<line$}{:>cost_column$}",
+                format!("  {} pickup(s)", group.pickups.len()),
+                format!("{:.2} €", group.total)
+            )?;
+        }
+        if self.groups.len() > 1 {
+            writeln!(f)?;
+            writeln!(f, "Total {} pickup(s), {:.2} €", self.pickups, self.total)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::VatRates;
+
+    fn service(customer: &str, group: &str, next_date: &str) -> TrashService {
+        TrashService::builder(&format!("{} astia", group), next_date)
+            .customer(customer)
+            .price(Decimal::TEN)
+            .interval("1")
+            .group(group)
+            .build()
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let services = [
+            service("02-2891001-01", "BIO", "2024-03-06"),
+            service("02-2891001-02", "SEK", "2024-03-08"),
+        ];
+        let options = EventOptions {
+            vat_rates: VatRates::flat(25.5),
+            ..EventOptions::default()
+        };
+        let from = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let until = NaiveDate::from_ymd_opt(2024, 3, 13).unwrap();
+
+        let by_week = upcoming(&services, &options, from, until, GroupBy::Week);
+        let keys: Vec<&str> = by_week
+            .groups
+            .iter()
+            .map(|group| group.key.as_str())
+            .collect();
+        assert_eq!(keys, ["2024-W10", "2024-W11"]);
+        assert_eq!(by_week.groups[0].total, Decimal::new(2510, 2));
+        assert_eq!(by_week.groups[1].pickups.len(), 1);
+        assert_eq!(by_week.pickups, 3);
+
+        let by_customer = upcoming(&services, &options, from, until, GroupBy::Customer);
+        assert_eq!(by_customer.groups[0].pickups.len(), 2);
+        assert_eq!(by_customer.total, Decimal::new(3765, 2));
+
+        let text = by_week.to_string();
+        assert!(text.starts_with("Week 10 (04.03. – 10.03.2024)\n"));
+        assert!(text.contains("  06.03.2024  02-2891001-01  BIO astia  Biojäte        12.55 €\n"));
+        assert!(text.ends_with("Total 3 pickup(s), 37.65 €\n"));
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Latest release of the GitHub repository
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/jmalmari/pjhoy/releases/latest";
+
+/// Release asset listing `<sha256>  <asset name>` lines, as written by
+/// `sha256sum`
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// A release newer than the running version
+#[derive(Debug)]
+pub struct Update {
+    pub version: semver::Version,
+    binary_url: String,
+    binary_name: String,
+    checksums_url: String,
+}
+
+/// Name of the release binary for this platform, e.g. `pjhoy-x86_64-linux`
+fn asset_name() -> String {
+    format!(
+        "pjhoy-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    // GitHub rejects requests without a user agent
+    reqwest::Client::builder()
+        .user_agent(concat!("pjhoy/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Version of a release tag such as `v1.2.0`
+fn tag_version(tag: &str) -> Result<semver::Version> {
+    semver::Version::parse(tag.trim_start_matches('v'))
+        .context(format!("Release tag {:?} is not a version", tag))
+}
+
+/// The latest release if it is newer than `current`
+fn newer_release(release: Release, current: &semver::Version) -> Result<Option<Update>> {
+    let version = tag_version(&release.tag_name)?;
+    if version <= *current {
+        return Ok(None);
+    }
+    let url_of = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.clone())
+            .context(format!("Release {} has no {}", release.tag_name, name))
+    };
+    let binary_name = asset_name();
+    Ok(Some(Update {
+        binary_url: url_of(&binary_name)?,
+        checksums_url: url_of(CHECKSUMS_ASSET)?,
+        binary_name,
+        version,
+    }))
+}
+
+/// Ask GitHub for a release newer than the running version
+pub async fn check() -> Result<Option<Update>> {
+    let release: Release = http_client()?
+        .get(LATEST_RELEASE_URL)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to look up the latest release")?
+        .json()
+        .await
+        .context("Failed to parse the latest release")?;
+    let current = tag_version(env!("CARGO_PKG_VERSION"))?;
+    newer_release(release, &current)
+}
+
+/// Expected SHA-256 of `name` in a `SHA256SUMS` file
+fn expected_checksum(checksums: &str, name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let (checksum, file) = line.split_once(char::is_whitespace)?;
+        // sha256sum marks binary mode with a leading `*`
+        let file = file.trim_start().trim_start_matches('*');
+        (file == name).then(|| checksum.to_lowercase())
+    })
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context(format!("Failed to download {}", url))?;
+    Ok(response
+        .bytes()
+        .await
+        .context(format!("Failed to download {}", url))?
+        .to_vec())
+}
+
+/// Download the release binary, check it against the published checksum
+/// and put it in place of the running executable
+pub async fn install(update: &Update) -> Result<PathBuf> {
+    let client = http_client()?;
+    let checksums = download(&client, &update.checksums_url).await?;
+    let expected =
+        expected_checksum(&String::from_utf8_lossy(&checksums), &update.binary_name).context(
+            format!("{} does not list {}", CHECKSUMS_ASSET, update.binary_name),
+        )?;
+
+    let binary = download(&client, &update.binary_url).await?;
+    let actual = hex::encode(Sha256::digest(&binary));
+    if actual != expected {
+        bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            update.binary_name,
+            expected,
+            actual
+        );
+    }
+
+    let exe = std::env::current_exe().context("Could not locate the pjhoy executable")?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    replace_executable(&exe, &binary)?;
+    Ok(exe)
+}
+
+/// Write `binary` next to `exe` and rename it over, so that a failure
+/// leaves the old executable working
+fn replace_executable(exe: &Path, binary: &[u8]) -> Result<()> {
+    let new = exe.with_extension("new");
+    std::fs::write(&new, binary).context(format!("Failed to write {:?}", new))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new, std::fs::Permissions::from_mode(0o755))
+            .context(format!("Failed to make {:?} executable", new))?;
+    }
+    // A running executable cannot be replaced on Windows, only renamed
+    if cfg!(windows) {
+        let old = exe.with_extension("old");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).context(format!("Failed to move {:?} aside", exe))?;
+    }
+    std::fs::rename(&new, exe).context(format!("Failed to replace {:?}", exe))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newer_release() -> Result<()> {
+        let release = |tag: &str| Release {
+            tag_name: tag.to_string(),
+            assets: [asset_name(), CHECKSUMS_ASSET.to_string()]
+                .into_iter()
+                .map(|name| Asset {
+                    browser_download_url: format!("https://example.com/{}", name),
+                    name,
+                })
+                .collect(),
+        };
+        let current = semver::Version::new(0, 2, 0);
+
+        assert!(newer_release(release("v0.2.0"), &current)?.is_none());
+        let update = newer_release(release("v0.10.0"), &current)?.expect("newer release");
+        assert_eq!(update.version, semver::Version::new(0, 10, 0));
+        assert_eq!(update.checksums_url, "https://example.com/SHA256SUMS");
+
+        let mut without_binary = release("v1.0.0");
+        without_binary.assets.remove(0);
+        assert!(newer_release(without_binary, &current).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expected_checksum() {
+        let checksums = "AB12  pjhoy-aarch64-linux\ncd34 *pjhoy-x86_64-linux\n";
+        assert_eq!(
+            expected_checksum(checksums, "pjhoy-x86_64-linux").as_deref(),
+            Some("cd34")
+        );
+        assert_eq!(
+            expected_checksum(checksums, "pjhoy-aarch64-linux").as_deref(),
+            Some("ab12")
+        );
+        assert_eq!(expected_checksum(checksums, "pjhoy-x86_64-macos"), None);
+    }
+}